@@ -0,0 +1,5 @@
+// The migrations are embedded by `embed_migrations!` in src/migrations.rs,
+// which cargo doesn't know to rebuild when a migration is added
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,46 @@
+// Write persisted-queries.json, the manifest of the operations of
+// src/graphql/*.ts the server accepts when GRAPHQL_ALLOW_LIST_ONLY is set.
+// Operations are printed and hashed the way Apollo Client sends them, with
+// their __typename fields.
+const crypto = require('crypto')
+const fs = require('fs')
+const path = require('path')
+const { parse, print } = require('graphql')
+const { addTypenameToDocument } = require('@apollo/client/utilities')
+
+const directory = path.join(__dirname, '..', 'src', 'graphql')
+const output = path.join(__dirname, '..', 'persisted-queries.json')
+
+const operations = []
+
+for (const file of fs.readdirSync(directory).filter((name) => name.endsWith('.ts')).sort()) {
+  const source = fs.readFileSync(path.join(directory, file), 'utf8')
+
+  for (const [, template] of source.matchAll(/gql`([^`]*)`/g)) {
+    const document = addTypenameToDocument(parse(template))
+
+    for (const definition of document.definitions) {
+      if (definition.kind !== 'OperationDefinition') {
+        continue
+      }
+
+      const body = print({ kind: 'Document', definitions: [definition] })
+
+      operations.push({
+        id: crypto.createHash('sha256').update(body).digest('hex'),
+        name: definition.name ? definition.name.value : file,
+        type: definition.operation,
+        body,
+      })
+    }
+  }
+}
+
+const manifest = {
+  format: 'apollo-persisted-query-manifest',
+  version: 1,
+  operations,
+}
+
+fs.writeFileSync(output, JSON.stringify(manifest, null, 2) + '\n')
+console.log(`${operations.length} operations written to ${path.relative(process.cwd(), output)}`)
@@ -2,6 +2,7 @@ import { createApp, provide, h } from 'vue'
 import './style.css'
 import App from './App.vue'
 import { ApolloClient, InMemoryCache, HttpLink } from '@apollo/client/core'
+import { createPersistedQueryLink } from '@apollo/client/link/persisted-queries'
 import { DefaultApolloClient, provideApolloClient } from '@vue/apollo-composable'
 import { router } from './VueRouting'
 import 'virtual:windi.css'
@@ -12,8 +13,15 @@ const httpLink = new HttpLink({
   uri: 'http://localhost:5050/graphql'
 })
 
+// Operations are sent by hash, the server knows the ones of
+// persisted-queries.json and asks for the others once
+const sha256 = async (query: string) => {
+  const digest = await crypto.subtle.digest('SHA-256', new TextEncoder().encode(query))
+  return Array.from(new Uint8Array(digest), (byte) => byte.toString(16).padStart(2, '0')).join('')
+}
+
 const apolloClient = new ApolloClient({
-  link: httpLink,
+  link: createPersistedQueryLink({ sha256 }).concat(httpLink),
   cache,
 })
 
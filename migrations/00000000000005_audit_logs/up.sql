@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE audit_logs (
+    id SERIAL PRIMARY KEY,
+    actor_id INT NULL,
+    entity VARCHAR NOT NULL,
+    entity_id INT NOT NULL,
+    operation VARCHAR NOT NULL,
+    diff jsonb NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (actor_id) REFERENCES users(id)
+);
+
+CREATE INDEX audit_logs_entity_idx ON audit_logs (entity, entity_id);
+
+-- The audit trail is append-only: rows can never be rewritten or removed
+CREATE RULE audit_logs_no_update AS ON UPDATE TO audit_logs DO INSTEAD NOTHING;
+CREATE RULE audit_logs_no_delete AS ON DELETE TO audit_logs DO INSTEAD NOTHING;
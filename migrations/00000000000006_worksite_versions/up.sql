@@ -0,0 +1,35 @@
+-- Your SQL goes here
+CREATE TABLE worksite_versions (
+    id SERIAL PRIMARY KEY,
+    worksite_id INT NOT NULL,
+    version INT NOT NULL,
+    worksite jsonb NOT NULL,
+    author_id INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    UNIQUE (worksite_id, version),
+    FOREIGN KEY (worksite_id) REFERENCES worksites(id),
+    FOREIGN KEY (author_id) REFERENCES users(id)
+);
+
+-- Versions are immutable snapshots of the worksite document
+CREATE RULE worksite_versions_no_update AS ON UPDATE TO worksite_versions DO INSTEAD NOTHING;
+CREATE RULE worksite_versions_no_delete AS ON DELETE TO worksite_versions DO INSTEAD NOTHING;
+
+-- Existing worksites start their history at version 1
+INSERT INTO worksite_versions (worksite_id, version, worksite, created_at)
+SELECT id, 1, worksite, edited_at FROM worksites;
+
+-- A revision freezes one version when a report is issued (Rev 0, Rev 1, ...)
+CREATE TABLE worksite_revisions (
+    id SERIAL PRIMARY KEY,
+    worksite_id INT NOT NULL,
+    version_id INT NOT NULL,
+    revision INT NOT NULL,
+    issued_by INT NULL,
+    issued_at TIMESTAMP NOT NULL,
+    report_path VARCHAR NULL,
+    UNIQUE (worksite_id, revision),
+    FOREIGN KEY (worksite_id) REFERENCES worksites(id),
+    FOREIGN KEY (version_id) REFERENCES worksite_versions(id),
+    FOREIGN KEY (issued_by) REFERENCES users(id)
+);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE jobs (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    payload JSONB NOT NULL,
+    -- pending, running, succeeded or dead once every attempt failed
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL,
+    run_at TIMESTAMP NOT NULL,
+    last_error TEXT,
+    result JSONB,
+    created_by INT,
+    created_at TIMESTAMP NOT NULL,
+    started_at TIMESTAMP,
+    finished_at TIMESTAMP,
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+-- Workers only ever look for jobs due to run
+CREATE INDEX jobs_due ON jobs (run_at, id) WHERE status IN ('pending', 'running');
@@ -0,0 +1,50 @@
+-- Your SQL goes here
+-- Prices of a mission type from a day on. Grids are never changed, a new
+-- one replaces the previous from its `valid_from` day. Amounts are in euro
+-- cents, excluding tax.
+CREATE TABLE price_grids (
+    id SERIAL PRIMARY KEY,
+    mission_type VARCHAR NOT NULL,
+    base_price INT NOT NULL,
+    included_area DOUBLE PRECISION NOT NULL,
+    area_step DOUBLE PRECISION NOT NULL,
+    area_step_price INT NOT NULL,
+    included_samples INT NOT NULL,
+    sample_price INT NOT NULL,
+    included_distance INT NOT NULL,
+    distance_price INT NOT NULL,
+    minimum_price INT NOT NULL,
+    valid_from DATE NOT NULL,
+    created_by INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    UNIQUE (mission_type, valid_from),
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+-- A quote keeps the parameters it was priced with and its lines, so later
+-- grids don't change it
+CREATE TABLE quotes (
+    id SERIAL PRIMARY KEY,
+    number VARCHAR NOT NULL UNIQUE,
+    client_id INT NOT NULL,
+    mission_type VARCHAR NOT NULL,
+    parameters JSONB NOT NULL,
+    lines JSONB NOT NULL,
+    total_excluding_tax INT NOT NULL,
+    vat_rate INT NOT NULL,
+    vat_amount INT NOT NULL,
+    total_including_tax INT NOT NULL,
+    -- pending, accepted or declined
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    valid_until DATE NOT NULL,
+    worksite_id INT NULL UNIQUE,
+    document_path VARCHAR NULL,
+    created_by INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    edited_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (client_id) REFERENCES clients(id),
+    FOREIGN KEY (worksite_id) REFERENCES worksites(id),
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+CREATE INDEX quotes_client ON quotes (client_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE payments;
+DROP TABLE credit_notes;
+DROP TABLE invoices;
+DROP TABLE document_sequences;
@@ -0,0 +1,76 @@
+-- Your SQL goes here
+-- Last number given in a series of documents for a year. Numbers are taken
+-- in the transaction issuing the document, so a rolled back document gives
+-- its number back and a series never has a gap.
+CREATE TABLE document_sequences (
+    series VARCHAR NOT NULL,
+    year INT NOT NULL,
+    last_number INT NOT NULL,
+    PRIMARY KEY (series, year)
+);
+
+-- Issued invoices are never changed but for what was paid or credited,
+-- mistakes are corrected with credit notes. Amounts are in euro cents.
+CREATE TABLE invoices (
+    id SERIAL PRIMARY KEY,
+    number VARCHAR NOT NULL UNIQUE,
+    client_id INT NOT NULL,
+    worksite_id INT NOT NULL,
+    quote_id INT NULL,
+    lines JSONB NOT NULL,
+    total_excluding_tax INT NOT NULL,
+    vat_rate INT NOT NULL,
+    vat_amount INT NOT NULL,
+    total_including_tax INT NOT NULL,
+    amount_paid INT NOT NULL DEFAULT 0,
+    amount_credited INT NOT NULL DEFAULT 0,
+    -- unpaid, partially_paid, paid or credited
+    status VARCHAR NOT NULL DEFAULT 'unpaid',
+    due_date DATE NOT NULL,
+    document_path VARCHAR NULL,
+    issued_by INT NULL,
+    issued_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (client_id) REFERENCES clients(id),
+    FOREIGN KEY (worksite_id) REFERENCES worksites(id),
+    FOREIGN KEY (quote_id) REFERENCES quotes(id),
+    FOREIGN KEY (issued_by) REFERENCES users(id)
+);
+
+CREATE INDEX invoices_client ON invoices (client_id);
+CREATE INDEX invoices_worksite ON invoices (worksite_id);
+
+CREATE TABLE credit_notes (
+    id SERIAL PRIMARY KEY,
+    number VARCHAR NOT NULL UNIQUE,
+    invoice_id INT NOT NULL,
+    reason VARCHAR NOT NULL,
+    lines JSONB NOT NULL,
+    total_excluding_tax INT NOT NULL,
+    vat_rate INT NOT NULL,
+    vat_amount INT NOT NULL,
+    total_including_tax INT NOT NULL,
+    document_path VARCHAR NULL,
+    issued_by INT NULL,
+    issued_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (invoice_id) REFERENCES invoices(id),
+    FOREIGN KEY (issued_by) REFERENCES users(id)
+);
+
+CREATE TABLE payments (
+    id SERIAL PRIMARY KEY,
+    invoice_id INT NOT NULL,
+    amount INT NOT NULL,
+    paid_on DATE NOT NULL,
+    -- transfer, cheque, card or cash
+    method VARCHAR NOT NULL,
+    reference VARCHAR NULL,
+    recorded_by INT NULL,
+    recorded_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (invoice_id) REFERENCES invoices(id),
+    FOREIGN KEY (recorded_by) REFERENCES users(id)
+);
+
+-- Invoices, credit notes and payments are kept as issued
+CREATE RULE credit_notes_no_delete AS ON DELETE TO credit_notes DO INSTEAD NOTHING;
+CREATE RULE invoices_no_delete AS ON DELETE TO invoices DO INSTEAD NOTHING;
+CREATE RULE payments_no_delete AS ON DELETE TO payments DO INSTEAD NOTHING;
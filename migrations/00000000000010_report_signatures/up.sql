@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+-- Every report written for an issued revision. A regenerated report gets a
+-- new row, so any copy sent out can still be verified by its hash.
+CREATE TABLE report_signatures (
+    id SERIAL PRIMARY KEY,
+    revision_id INT NOT NULL,
+    -- SHA-256 of the PDF as written, hexadecimal
+    content_hash VARCHAR(64) NOT NULL,
+    signed_by INT NULL,
+    signer_name VARCHAR NOT NULL,
+    -- Subject of the certificate, NULL when no certificate is configured
+    -- and the report is only hashed
+    certificate_subject VARCHAR NULL,
+    signed_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (revision_id) REFERENCES worksite_revisions(id),
+    FOREIGN KEY (signed_by) REFERENCES users(id)
+);
+
+CREATE INDEX report_signatures_content_hash ON report_signatures (content_hash);
+
+CREATE RULE report_signatures_no_update AS ON UPDATE TO report_signatures DO INSTEAD NOTHING;
+CREATE RULE report_signatures_no_delete AS ON DELETE TO report_signatures DO INSTEAD NOTHING;
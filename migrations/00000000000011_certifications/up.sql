@@ -0,0 +1,24 @@
+-- Your SQL goes here
+
+-- Certifications of the operators. A renewed certification is a new row,
+-- the report of a mission is only issued by an operator holding a valid
+-- certification for its domain.
+CREATE TABLE certifications (
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    -- asbestos or lead
+    domain VARCHAR NOT NULL,
+    -- Accredited body which certified the operator
+    body VARCHAR NOT NULL,
+    number VARCHAR NOT NULL,
+    valid_from DATE NOT NULL,
+    valid_until DATE NOT NULL,
+    created_by INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    UNIQUE (body, number),
+    CHECK (valid_until >= valid_from),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+CREATE INDEX certifications_user ON certifications (user_id);
@@ -0,0 +1,41 @@
+-- Your SQL goes here
+
+-- XRF analysers measuring lead in paints. The report of a lead mission
+-- names the device and its radioactive source.
+CREATE TABLE instruments (
+    id SERIAL PRIMARY KEY,
+    manufacturer VARCHAR NOT NULL,
+    model VARCHAR NOT NULL,
+    serial_number VARCHAR NOT NULL UNIQUE,
+    -- Radionuclide of the source, such as Cd-109
+    radionuclide VARCHAR NOT NULL,
+    source_date DATE NOT NULL,
+    -- Last day the instrument may be used
+    retired_on DATE NULL,
+    created_by INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+-- Periodic checks of an instrument, kept as recorded. A session may only
+-- use an instrument with a passed check covering its day.
+CREATE TABLE calibration_checks (
+    id SERIAL PRIMARY KEY,
+    instrument_id INT NOT NULL,
+    checked_on DATE NOT NULL,
+    valid_until DATE NOT NULL,
+    -- Who checked the instrument
+    body VARCHAR NOT NULL,
+    passed BOOLEAN NOT NULL,
+    certificate_number VARCHAR NULL,
+    recorded_by INT NULL,
+    recorded_at TIMESTAMP NOT NULL,
+    CHECK (valid_until >= checked_on),
+    FOREIGN KEY (instrument_id) REFERENCES instruments(id),
+    FOREIGN KEY (recorded_by) REFERENCES users(id)
+);
+
+CREATE INDEX calibration_checks_instrument ON calibration_checks (instrument_id);
+
+CREATE RULE calibration_checks_no_update AS ON UPDATE TO calibration_checks DO INSTEAD NOTHING;
+CREATE RULE calibration_checks_no_delete AS ON DELETE TO calibration_checks DO INSTEAD NOTHING;
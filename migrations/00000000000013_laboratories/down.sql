@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE analysis_orders;
+DROP TABLE laboratories;
@@ -0,0 +1,44 @@
+-- Your SQL goes here
+
+-- Laboratories analysing the asbestos samples
+CREATE TABLE laboratories (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    -- COFRAC accreditation
+    accreditation_number VARCHAR NOT NULL UNIQUE,
+    contact_name VARCHAR NULL,
+    email VARCHAR NULL,
+    phone VARCHAR NULL,
+    -- Days between sending samples and their results
+    turnaround_days INT NOT NULL,
+    urgent_turnaround_days INT NOT NULL,
+    created_by INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+-- Samples of a worksite sent together to a laboratory
+CREATE TABLE analysis_orders (
+    id SERIAL PRIMARY KEY,
+    laboratory_id INT NOT NULL,
+    worksite_id INT NOT NULL,
+    -- Codes of the samples, as in `sampling` of the worksite
+    samples JSONB NOT NULL,
+    -- molp, meta or meba
+    analysis_type VARCHAR NOT NULL,
+    -- standard or urgent
+    urgency VARCHAR NOT NULL,
+    sent_on DATE NOT NULL,
+    expected_on DATE NOT NULL,
+    received_on DATE NULL,
+    -- sent, received or cancelled
+    status VARCHAR NOT NULL DEFAULT 'sent',
+    created_by INT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (laboratory_id) REFERENCES laboratories(id),
+    FOREIGN KEY (worksite_id) REFERENCES worksites(id),
+    FOREIGN KEY (created_by) REFERENCES users(id)
+);
+
+CREATE INDEX analysis_orders_worksite ON analysis_orders (worksite_id);
+CREATE INDEX analysis_orders_laboratory ON analysis_orders (laboratory_id);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- A worksite is invoiced once, again only after its invoice is credited
+CREATE UNIQUE INDEX invoices_worksite_not_credited ON invoices (worksite_id) WHERE status <> 'credited';
@@ -0,0 +1,244 @@
+use diesel::prelude::*;
+use std::fs;
+use crate::config::{BillingConfig, Config};
+use crate::dates;
+use crate::errors::AppResult;
+use crate::models::clients::Client;
+use crate::models::invoices::{CreditNote, Invoice};
+use crate::models::quotes::Quote;
+use crate::models::worksites::Worksite;
+use crate::pdf::{self, Line};
+use crate::pricing::PriceLine;
+
+// Render the PDF of a quote to disk and remember where it is
+pub fn write_quote(conn: &PgConnection, config: &Config, quote: &Quote) -> AppResult<Quote> {
+    use crate::schema::clients;
+
+    // Shutdown waits for the file to be written and recorded
+    let _task = crate::shutdown::track("quote document");
+
+    let client: Client = clients::table.find(quote.client_id).first(conn)?;
+
+    let lines = quote_document(&config.billing, &client, quote)?;
+    let bytes = pdf::render(&format!("Devis {}", quote.number), &lines)?;
+
+    fs::create_dir_all(&config.storage.quotes_path)?;
+
+    let path = config.storage.quotes_path.join(format!("quote-{}.pdf", quote.number));
+    fs::write(&path, bytes)?;
+
+    Ok(quote.set_document_path(conn, &path.to_string_lossy())?)
+}
+
+// Render the PDF of an invoice to disk and remember where it is
+pub fn write_invoice(conn: &PgConnection, config: &Config, invoice: &Invoice) -> AppResult<Invoice> {
+    use crate::schema::clients;
+
+    let _task = crate::shutdown::track("invoice document");
+
+    let client: Client = clients::table.find(invoice.client_id).first(conn)?;
+    let worksite = Worksite::find(conn, invoice.worksite_id)?;
+
+    let lines = invoice_document(&config.billing, &client, &worksite, invoice);
+    let bytes = pdf::render(&format!("Facture {}", invoice.number), &lines)?;
+
+    fs::create_dir_all(&config.storage.invoices_path)?;
+
+    let path = config.storage.invoices_path.join(format!("invoice-{}.pdf", invoice.number));
+    fs::write(&path, bytes)?;
+
+    Ok(invoice.set_document_path(conn, &path.to_string_lossy())?)
+}
+
+// Render the PDF of a credit note next to the invoices
+pub fn write_credit_note(conn: &PgConnection, config: &Config, credit_note: &CreditNote) -> AppResult<CreditNote> {
+    use crate::schema::clients;
+
+    let _task = crate::shutdown::track("credit note document");
+
+    let invoice = Invoice::find(conn, credit_note.invoice_id)?;
+    let client: Client = clients::table.find(invoice.client_id).first(conn)?;
+
+    let lines = credit_note_document(&config.billing, &client, &invoice, credit_note);
+    let bytes = pdf::render(&format!("Avoir {}", credit_note.number), &lines)?;
+
+    fs::create_dir_all(&config.storage.invoices_path)?;
+
+    let path = config
+        .storage
+        .invoices_path
+        .join(format!("credit-note-{}.pdf", credit_note.number));
+    fs::write(&path, bytes)?;
+
+    Ok(credit_note.set_document_path(conn, &path.to_string_lossy())?)
+}
+
+// Who issues the document, only what is configured
+fn seller(billing: &BillingConfig) -> Vec<Line> {
+    let mut lines = vec![];
+
+    for (label, value) in [
+        ("", &billing.company_name),
+        ("", &billing.company_address),
+        ("SIRET : ", &billing.company_siret),
+        ("N° TVA intracommunautaire : ", &billing.company_vat_number),
+    ] {
+        if !value.trim().is_empty() {
+            lines.push(Line::Text(format!("{}{}", label, value.trim())));
+        }
+    }
+
+    lines
+}
+
+fn buyer(client: &Client) -> Vec<Line> {
+    vec![
+        Line::Text(format!("Client : {}", client.name)),
+        Line::Text(format!(
+            "Adresse : {} {} {} {}",
+            client.address.street_number,
+            client.address.street,
+            client.address.postal_code.as_deref().unwrap_or_default(),
+            client.address.city.as_deref().unwrap_or_default(),
+        ).trim_end().to_string()),
+    ]
+}
+
+fn detail(lines: &mut Vec<Line>, priced: &[PriceLine]) {
+    lines.push(Line::Heading("Détail".to_string()));
+
+    for line in priced {
+        lines.push(Line::Text(format!(
+            "{} : {} x {} = {}",
+            line.label,
+            line.quantity,
+            euros(line.unit_price),
+            euros(line.amount),
+        )));
+    }
+}
+
+fn total(lines: &mut Vec<Line>, excluding_tax: i32, vat_rate: i32, vat_amount: i32, including_tax: i32) {
+    lines.push(Line::Heading("Total".to_string()));
+    lines.push(Line::Text(format!("Total HT : {}", euros(excluding_tax))));
+    lines.push(Line::Text(format!(
+        "TVA {} % : {}",
+        (f64::from(vat_rate) / 100.0).to_string().replace('.', ","),
+        euros(vat_amount)
+    )));
+    lines.push(Line::Text(format!("Total TTC : {}", euros(including_tax))));
+}
+
+fn quote_document(billing: &BillingConfig, client: &Client, quote: &Quote) -> AppResult<Vec<Line>> {
+    let parameters = &quote.parameters;
+
+    let mut lines = vec![Line::Heading(format!("Devis {}", quote.number))];
+    lines.extend(seller(billing));
+    lines.extend(buyer(client));
+    lines.extend([
+        Line::Text(format!("Établi le {}", dates::paris(quote.created_at).format("%d/%m/%Y"))),
+        Line::Text(format!("Valable jusqu'au {}", quote.valid_until.format("%d/%m/%Y"))),
+        Line::Heading("Mission".to_string()),
+        Line::Text(quote.mission()?.label().to_string()),
+        Line::Text(format!("Bien : {}", parameters.property_address)),
+        Line::Text(format!("Surface : {} m²", parameters.surface_area)),
+        Line::Text(format!("Prélèvements prévus : {}", parameters.expected_samples)),
+        Line::Text(format!("Distance : {} km", parameters.distance)),
+    ]);
+
+    detail(&mut lines, &quote.lines);
+    total(&mut lines, quote.total_excluding_tax, quote.vat_rate, quote.vat_amount, quote.total_including_tax);
+    lines.push(Line::Text("Bon pour accord, date et signature du client :".to_string()));
+
+    Ok(lines)
+}
+
+fn invoice_document(billing: &BillingConfig, client: &Client, worksite: &Worksite, invoice: &Invoice) -> Vec<Line> {
+    let mut lines = vec![Line::Heading(format!("Facture {}", invoice.number))];
+    lines.extend(seller(billing));
+    lines.extend(buyer(client));
+    lines.push(Line::Text(format!("Émise le {}", dates::paris(invoice.issued_at).format("%d/%m/%Y"))));
+    if let Some(information) = &worksite.worksite.worksite_information {
+        lines.push(Line::Text(format!("Dossier n° {}", information.folder_number)));
+    }
+
+    detail(&mut lines, &invoice.lines);
+    total(&mut lines, invoice.total_excluding_tax, invoice.vat_rate, invoice.vat_amount, invoice.total_including_tax);
+
+    lines.push(Line::Heading("Règlement".to_string()));
+    lines.push(Line::Text(format!("À régler au plus tard le {}", invoice.due_date.format("%d/%m/%Y"))));
+    lines.push(Line::Text("Pas d'escompte pour paiement anticipé.".to_string()));
+    lines.push(Line::Text(
+        "En cas de retard, pénalités au taux de trois fois le taux d'intérêt légal \
+         et indemnité forfaitaire pour frais de recouvrement de 40 €."
+            .to_string(),
+    ));
+
+    lines
+}
+
+fn credit_note_document(billing: &BillingConfig, client: &Client, invoice: &Invoice, credit_note: &CreditNote) -> Vec<Line> {
+    let mut lines = vec![Line::Heading(format!("Avoir {}", credit_note.number))];
+    lines.extend(seller(billing));
+    lines.extend(buyer(client));
+    lines.push(Line::Text(format!("Émis le {}", dates::paris(credit_note.issued_at).format("%d/%m/%Y"))));
+    lines.push(Line::Text(format!(
+        "Sur la facture {} du {}",
+        invoice.number,
+        dates::paris(invoice.issued_at).format("%d/%m/%Y")
+    )));
+    lines.push(Line::Text(format!("Motif : {}", credit_note.reason)));
+
+    detail(&mut lines, &credit_note.lines);
+    total(
+        &mut lines,
+        credit_note.total_excluding_tax,
+        credit_note.vat_rate,
+        credit_note.vat_amount,
+        credit_note.total_including_tax,
+    );
+
+    lines
+}
+
+// Cents written the French way, `1 234,50 €`
+pub fn euros(cents: i32) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let cents = i64::from(cents).abs();
+    let units = (cents / 100).to_string();
+
+    let mut grouped = String::new();
+    for (index, digit) in units.chars().enumerate() {
+        if index > 0 && (units.len() - index) % 3 == 0 {
+            grouped.push(' ');
+        }
+        grouped.push(digit);
+    }
+
+    format!("{}{},{:02} €", sign, grouped, cents % 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn euros_have_two_decimals_after_a_comma() {
+        assert_eq!(euros(0), "0,00 €");
+        assert_eq!(euros(5), "0,05 €");
+        assert_eq!(euros(1_050), "10,50 €");
+    }
+
+    #[test]
+    fn thousands_are_grouped_by_spaces() {
+        assert_eq!(euros(99_999), "999,99 €");
+        assert_eq!(euros(123_450), "1 234,50 €");
+        assert_eq!(euros(123_456_789), "1 234 567,89 €");
+    }
+
+    #[test]
+    fn credits_are_negative() {
+        assert_eq!(euros(-123_450), "-1 234,50 €");
+        assert_eq!(euros(i32::MIN), "-21 474 836,48 €");
+    }
+}
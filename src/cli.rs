@@ -0,0 +1,413 @@
+use diesel::pg::PgConnection;
+use log::{error, info};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use crate::config::Config;
+use crate::database::PostgresPool;
+use crate::errors::{AppError, AppResult};
+use crate::exports;
+use crate::migrations;
+use crate::missions::MissionType;
+use crate::models::clients::{AddressInput, Client, ClientInput, InterlocutorInput};
+use crate::models::users::{Authorization, User, UserInput};
+use crate::models::worksite_versions::WorksiteRevision;
+use crate::models::worksites::{Lead, Worksite, WorksiteContent, WorksiteInformation, Asbestos};
+
+pub const USAGE: &str = "Usage: general_service_amiantes [COMMAND]
+
+Commands:
+  serve                                   Run the GraphQL server (default)
+  migrate                                 Apply pending database migrations
+  check-schema                            Compare src/schema.rs with the database
+  create-user <name> <role>               Create a user, the password is read from stdin
+  reset-password <name>                   Replace a password, read from stdin
+  list-users                              List users and their role
+  seed                                    Insert demo clients and worksites
+  export <file>                           Write clients and worksites as JSON
+  import <file>                           Insert the clients and worksites of an export
+  regenerate-report <worksite> [revision] Render again the report of a revision (latest by default)
+  graphql-schema [file]                   Write the GraphQL schema language of the API (stdout by default)
+  check-graphql-schema [file]             Compare a GraphQL schema file with the API (frontend/schema.graphql by default)
+  help                                    Show this message";
+
+// The schema the frontend is written against
+const FRONTEND_SCHEMA: &str = "frontend/schema.graphql";
+
+// Subcommands of the binary, `serve` when none is given
+pub enum Command {
+    Serve,
+    Migrate,
+    CheckSchema,
+    CreateUser { name: String, role: String },
+    ResetPassword { name: String },
+    ListUsers,
+    Seed,
+    Export { file: String },
+    Import { file: String },
+    RegenerateReport { worksite_id: i32, revision: Option<i32> },
+    GraphQLSchema { file: Option<String>, check: bool },
+    Help,
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        let argument = |index: usize, what: &str| -> Result<String, String> {
+            args.get(index)
+                .cloned()
+                .ok_or_else(|| format!("Missing {}", what))
+        };
+        let number = |value: String, what: &str| -> Result<i32, String> {
+            value
+                .parse()
+                .map_err(|_| format!("{} must be a number, got {}", what, value))
+        };
+
+        let command = match args.first().map(String::as_str) {
+            None | Some("serve") => Command::Serve,
+            Some("migrate") => Command::Migrate,
+            Some("check-schema") => Command::CheckSchema,
+            Some("create-user") => Command::CreateUser {
+                name: argument(1, "user name")?,
+                role: argument(2, "role")?,
+            },
+            Some("reset-password") => Command::ResetPassword {
+                name: argument(1, "user name")?,
+            },
+            Some("list-users") => Command::ListUsers,
+            Some("seed") => Command::Seed,
+            Some("export") => Command::Export {
+                file: argument(1, "export file")?,
+            },
+            Some("import") => Command::Import {
+                file: argument(1, "import file")?,
+            },
+            Some("regenerate-report") => Command::RegenerateReport {
+                worksite_id: number(argument(1, "worksite id")?, "worksite id")?,
+                revision: match args.get(2) {
+                    Some(value) => Some(number(value.clone(), "revision")?),
+                    None => None,
+                },
+            },
+            Some("graphql-schema") => Command::GraphQLSchema {
+                file: args.get(1).cloned(),
+                check: false,
+            },
+            Some("check-graphql-schema") => Command::GraphQLSchema {
+                file: Some(args.get(1).cloned().unwrap_or_else(|| FRONTEND_SCHEMA.to_string())),
+                check: true,
+            },
+            Some("help") | Some("--help") | Some("-h") => Command::Help,
+            Some(other) => return Err(format!("Unknown command `{}`", other)),
+        };
+
+        Ok(command)
+    }
+}
+
+// Run every command but `serve`. They work directly on a connection and go
+// through the same model functions as the GraphQL resolvers, without actor.
+pub fn run(command: Command, config: &Config, pool: &PostgresPool) -> AppResult<()> {
+    if let Command::Migrate = command {
+        return migrate(pool);
+    }
+
+    let conn = pool.get()?;
+
+    match command {
+        Command::Serve | Command::Migrate | Command::GraphQLSchema { .. } => Ok(()),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::CheckSchema => check_schema(&conn),
+        Command::CreateUser { name, role } => create_user(&conn, name, &role),
+        Command::ResetPassword { name } => reset_password(&conn, &name),
+        Command::ListUsers => list_users(&conn),
+        Command::Seed => seed(&conn),
+        Command::Export { file } => export(&conn, &file),
+        Command::Import { file } => import(&conn, &file),
+        Command::RegenerateReport { worksite_id, revision } => {
+            regenerate_report(&conn, config, worksite_id, revision)
+        }
+    }
+}
+
+// Report the failure of a command through the exit code
+pub fn exit_on_error<T>(result: AppResult<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            match &err {
+                AppError::Internal(details) => error!("{}", details),
+                AppError::Validation(violations) => {
+                    for violation in violations {
+                        error!("{}: {}", violation.field, violation.message);
+                    }
+                }
+                _ => error!("{}", err),
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+// Write the GraphQL schema, or fail when the file doesn't match it, meant
+// to be run by CI so the frontend can't drift away from the API. It works
+// on the code alone, without database.
+pub fn graphql_schema(file: Option<&str>, check: bool) -> AppResult<()> {
+    let sdl = crate::graphql::schema_language(&crate::graphql::create_schema());
+
+    match (file, check) {
+        (Some(file), true) => {
+            let committed = fs::read_to_string(file)?;
+
+            if committed == sdl {
+                info!("{} matches the API", file);
+                return Ok(());
+            }
+
+            for difference in diff_lines(&committed, &sdl) {
+                error!("{}", difference);
+            }
+            Err(AppError::Internal(format!(
+                "{} differs from the API, write it again with `graphql-schema {}`",
+                file, file
+            )))
+        }
+        (Some(file), false) => {
+            fs::write(file, &sdl)?;
+            info!("GraphQL schema written to {}", file);
+            Ok(())
+        }
+        (None, _) => {
+            print!("{}", sdl);
+            Ok(())
+        }
+    }
+}
+
+// Lines only in the file (-) or only in the API (+)
+fn diff_lines(committed: &str, sdl: &str) -> Vec<String> {
+    let committed_lines: HashSet<&str> = committed.lines().collect();
+    let sdl_lines: HashSet<&str> = sdl.lines().collect();
+
+    let removed = committed
+        .lines()
+        .filter(|line| !sdl_lines.contains(line))
+        .map(|line| format!("- {}", line));
+    let added = sdl
+        .lines()
+        .filter(|line| !committed_lines.contains(line))
+        .map(|line| format!("+ {}", line));
+
+    removed.chain(added).collect()
+}
+
+pub fn migrate(pool: &PostgresPool) -> AppResult<()> {
+    let conn = pool.get()?;
+    let applied = migrations::run(&conn)?;
+
+    if applied.is_empty() {
+        info!("The database is up to date");
+    }
+    for name in applied {
+        info!("Applied migration {}", name);
+    }
+    Ok(())
+}
+
+// Fails when `src/schema.rs` doesn't match the migrated database, meant to
+// be run by CI after `migrate`
+fn check_schema(conn: &PgConnection) -> AppResult<()> {
+    let differences = migrations::schema_differences(conn)?;
+
+    if differences.is_empty() {
+        info!("src/schema.rs matches the database");
+        return Ok(());
+    }
+
+    for difference in &differences {
+        error!("{}", difference);
+    }
+    Err(AppError::Internal(format!("{} differences between src/schema.rs and the database", differences.len())))
+}
+
+// Read a password from stdin so it never shows up in the shell history
+fn read_password(prompt: &str) -> AppResult<String> {
+    eprint!("{}: ", prompt);
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn create_user(conn: &PgConnection, name: String, role: &str) -> AppResult<()> {
+    let authorization = Authorization::find_by_level(conn, role)?;
+    let password = read_password("Password")?;
+
+    let user = User::create(
+        conn,
+        &UserInput {
+            authorization_id: authorization.id,
+            name,
+            password,
+        },
+        None,
+    )?;
+
+    info!("Created user {} ({}) with id {}", user.name, authorization.level, user.id);
+    Ok(())
+}
+
+fn reset_password(conn: &PgConnection, name: &str) -> AppResult<()> {
+    let user = User::find_by_name(conn, name)?;
+    let password = read_password("New password")?;
+
+    user.reset_password(conn, &password, None)?;
+
+    info!("Password of {} replaced, its sessions are closed", user.name);
+    Ok(())
+}
+
+fn list_users(conn: &PgConnection) -> AppResult<()> {
+    let levels = Authorization::all(conn)?;
+
+    println!("{:>5}  {:<32} role", "id", "name");
+    for user in User::all(conn)? {
+        let level = levels
+            .iter()
+            .find(|authorization| authorization.id == user.authorization_id)
+            .map(|authorization| authorization.level.as_str())
+            .unwrap_or("-");
+
+        println!("{:>5}  {:<32} {}", user.id, user.name, level);
+    }
+    Ok(())
+}
+
+fn seed(conn: &PgConnection) -> AppResult<()> {
+    let client = Client::create(
+        conn,
+        ClientInput {
+            name: "Immobilière du Parc".to_string(),
+            address: Some(AddressInput {
+                street: "rue des Lilas".to_string(),
+                street_number: 12,
+                postal_code: Some("75019".to_string()),
+                city: Some("Paris".to_string()),
+            }),
+            interlocutors: Some(vec![InterlocutorInput {
+                name: "Claire Martin".to_string(),
+                position: "Gestionnaire".to_string(),
+                email: Some("claire.martin@example.com".to_string()),
+                phone: Some("01 23 45 67 89".to_string()),
+            }]),
+        },
+        None,
+    )?;
+
+    let asbestos_worksite = Worksite::create(
+        conn,
+        client.id,
+        WorksiteContent {
+            worksite_information: Some(WorksiteInformation {
+                folder_number: "DEMO-0001".to_string(),
+                property_address: Some("12 rue des Lilas 75019 Paris".to_string()),
+                surface_area: Some(85.0),
+                mission_type: Some(MissionType::AsbestosBeforeSale),
+            }),
+            leads: None,
+            asbestos: Some(vec![Asbestos {
+                unit: 1,
+                area: "Cave".to_string(),
+                equipments: "Canalisation".to_string(),
+                localization: "Chaufferie".to_string(),
+                surveyed_element: "Calorifugeage".to_string(),
+                materials_description: "Tresse".to_string(),
+                sampling: "P001".to_string(),
+                date_of_sampling: "01/06/2022".to_string(),
+                fcr_result: "Présence".to_string(),
+                conservation_state: "Dégradé".to_string(),
+                equipment_volume: "3 ml".to_string(),
+                material_volume: "0,1 m3".to_string(),
+                picture_id: 0,
+            }]),
+            lead_sessions: None,
+        },
+        None,
+    )?;
+
+    let lead_worksite = Worksite::create(
+        conn,
+        client.id,
+        WorksiteContent {
+            worksite_information: Some(WorksiteInformation {
+                folder_number: "DEMO-0002".to_string(),
+                property_address: Some("12 rue des Lilas 75019 Paris".to_string()),
+                surface_area: Some(85.0),
+                mission_type: Some(MissionType::Crep),
+            }),
+            leads: Some(vec![Lead {
+                number: 1,
+                localization: "Séjour".to_string(),
+                area: "A".to_string(),
+                number_ud: 1,
+                diagnostic_unity: "Mur".to_string(),
+                substrate: "Plâtre".to_string(),
+                exposed_coating: "Peinture".to_string(),
+                measure_localization: "Centre".to_string(),
+                measure: 1,
+                incertitude: 0,
+                result: "Négatif".to_string(),
+                session: None,
+            }]),
+            asbestos: None,
+            lead_sessions: None,
+        },
+        None,
+    )?;
+
+    info!(
+        "Seeded client {} and worksites {} and {}",
+        client.id, asbestos_worksite.id, lead_worksite.id
+    );
+    Ok(())
+}
+
+fn export(conn: &PgConnection, file: &str) -> AppResult<()> {
+    let data = exports::collect(conn, None)?;
+
+    info!("Exporting {} clients and {} worksites to {}", data.clients.len(), data.worksites.len(), file);
+
+    exports::write(&data, Path::new(file))
+}
+
+fn import(conn: &PgConnection, file: &str) -> AppResult<()> {
+    let data = exports::read(Path::new(file))?;
+    let imported = exports::import(conn, data)?;
+
+    info!("Imported {} clients", imported);
+    Ok(())
+}
+
+fn regenerate_report(conn: &PgConnection, config: &Config, worksite_id: i32, revision: Option<i32>) -> AppResult<()> {
+    let revision = match revision {
+        Some(number) => WorksiteRevision::find(conn, worksite_id, number)?,
+        None => WorksiteRevision::latest(conn, worksite_id)?
+            .ok_or_else(|| AppError::NotFound(format!("Issued revision of worksite {}", worksite_id)))?,
+    };
+
+    let revision = crate::reports::write_revision_report(conn, config, &revision)?;
+
+    info!(
+        "Report of worksite {} Rev {} written to {}",
+        worksite_id,
+        revision.revision,
+        revision.report_path.unwrap_or_default()
+    );
+    Ok(())
+}
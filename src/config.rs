@@ -0,0 +1,534 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+// Settings of the whole application. They are read once at startup from an
+// optional TOML file (`config.toml`, or the file named by `CONFIG_FILE`),
+// then every value can be overridden by an environment variable, which is
+// what deployments use. Secrets (the database url holds its password) can
+// also be read from a file named by `<KEY>_FILE`, as mounted by Docker.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub log: LogConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub jobs: JobsConfig,
+    pub limits: LimitsConfig,
+    pub graphql: GraphQLConfig,
+    pub billing: BillingConfig,
+    pub signing: SigningConfig,
+    pub certifications: CertificationsConfig,
+    pub mail: MailConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    // SERVER_HOST
+    pub host: String,
+    // SERVER_PORT
+    pub port: u16,
+    // CORS_ORIGINS, comma separated, `*` allows any origin
+    pub cors_origins: Vec<String>,
+    // SERVER_SHUTDOWN_TIMEOUT, seconds given to in-flight requests and
+    // background tasks to finish once SIGINT or SIGTERM is received
+    pub shutdown_timeout: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    // DATABASE_URL
+    pub url: String,
+    // DATABASE_POOL_SIZE
+    pub pool_size: u32,
+    // DATABASE_POOL_TIMEOUT, seconds to wait for a free connection
+    pub pool_timeout: u64,
+    // DATABASE_MIGRATE_ON_STARTUP, apply pending migrations before serving
+    // instead of refusing to start
+    pub migrate_on_startup: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    // RUST_LOG, env_logger filter such as `info,actix_web=warn`
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    // REPORTS_PATH, directory of generated reports
+    pub reports_path: PathBuf,
+    // EXPORTS_PATH, directory of the exports made by background jobs
+    pub exports_path: PathBuf,
+    // QUOTES_PATH, directory of the quotes sent to clients
+    pub quotes_path: PathBuf,
+    // INVOICES_PATH, directory of the invoices and credit notes
+    pub invoices_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    // SESSION_LIFETIME_HOURS
+    pub session_lifetime_hours: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    // JOB_WORKERS, background workers run by `serve`, 0 disables them
+    pub workers: usize,
+    // JOB_POLL_INTERVAL, seconds an idle worker waits before looking for due
+    // jobs again
+    pub poll_interval: u64,
+    // JOB_MAX_ATTEMPTS, runs of a job before it is marked dead
+    pub max_attempts: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    // GRAPHQL_MAX_DEPTH, nesting of the selections of an operation, 0 for no
+    // limit
+    pub max_depth: usize,
+    // GRAPHQL_MAX_COMPLEXITY, cost of an operation where every field costs 1
+    // and list fields multiply the cost of their selection by their page
+    // size, 0 for no limit
+    pub max_complexity: u64,
+    // GRAPHQL_MAX_BODY_SIZE, bytes of a request body
+    pub max_body_size: usize,
+    // RATE_LIMIT_PER_MINUTE, operations per user, or per client address when
+    // not authenticated, 0 for no limit
+    pub rate_limit: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GraphQLConfig {
+    // GRAPHIQL, serve the GraphiQL explorer on /graphiql and the schema
+    // language on /schema.graphql, for development
+    pub graphiql: bool,
+    // PERSISTED_QUERIES, manifest of the operations of the frontend as
+    // written by `npm run persisted-queries`, they are known without being
+    // registered by clients
+    pub persisted_queries: Option<PathBuf>,
+    // GRAPHQL_ALLOW_LIST_ONLY, execute the operations of the manifest only
+    pub allow_list_only: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SigningConfig {
+    // SIGNING_CERTIFICATE, PKCS#12 file of the certificate signing issued
+    // reports. Reports are only hashed without it.
+    pub certificate: Option<PathBuf>,
+    // SIGNING_CERTIFICATE_PASSWORD (or SIGNING_CERTIFICATE_PASSWORD_FILE)
+    pub certificate_password: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CertificationsConfig {
+    // CERTIFICATION_WARNING_DAYS, days before its end a certification of an
+    // operator is reported as expiring
+    pub warning_days: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    // MAIL_FROM, sender of the emails sent by background jobs, no email is
+    // sent without it
+    pub from: Option<String>,
+    // MAIL_SENDMAIL, sendmail compatible command the messages are piped to
+    pub sendmail: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BillingConfig {
+    // VAT_RATE, percent applied to new quotes and invoices
+    pub vat_rate: f64,
+    // QUOTE_VALIDITY_DAYS, days a client has to accept a quote
+    pub quote_validity_days: i64,
+    // PAYMENT_TERMS_DAYS, days between an invoice and its due date, the law
+    // allows 60 at most
+    pub payment_terms_days: i64,
+    // COMPANY_NAME, COMPANY_ADDRESS, COMPANY_SIRET and COMPANY_VAT_NUMBER,
+    // the seller printed on quotes and invoices. Neither can be created
+    // without the name, SIRET and VAT number.
+    pub company_name: String,
+    pub company_address: String,
+    pub company_siret: String,
+    pub company_vat_number: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 5050,
+            cors_origins: vec!["*".to_string()],
+            shutdown_timeout: 30,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            pool_size: 10,
+            pool_timeout: 5,
+            migrate_on_startup: false,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            reports_path: PathBuf::from("reports"),
+            exports_path: PathBuf::from("exports"),
+            quotes_path: PathBuf::from("quotes"),
+            invoices_path: PathBuf::from("invoices"),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            session_lifetime_hours: 12,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_depth: 15,
+            max_complexity: 10_000,
+            max_body_size: 1024 * 1024,
+            rate_limit: 300,
+        }
+    }
+}
+
+impl Default for BillingConfig {
+    fn default() -> Self {
+        BillingConfig {
+            vat_rate: 20.0,
+            quote_validity_days: 90,
+            payment_terms_days: 30,
+            company_name: String::new(),
+            company_address: String::new(),
+            company_siret: String::new(),
+            company_vat_number: String::new(),
+        }
+    }
+}
+
+impl BillingConfig {
+    // As stored with documents, in hundredths of a percent
+    pub fn vat_rate_basis_points(&self) -> i32 {
+        (self.vat_rate * 100.0).round() as i32
+    }
+
+    // Variable of the first blank mention of the seller that the law
+    // requires on invoices, if any
+    pub fn missing_seller_mention(&self) -> Option<&'static str> {
+        [
+            ("COMPANY_NAME", &self.company_name),
+            ("COMPANY_SIRET", &self.company_siret),
+            ("COMPANY_VAT_NUMBER", &self.company_vat_number),
+        ]
+        .into_iter()
+        .find(|(_, value)| value.trim().is_empty())
+        .map(|(variable, _)| variable)
+    }
+}
+
+impl Default for CertificationsConfig {
+    fn default() -> Self {
+        CertificationsConfig {
+            warning_days: 60,
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            from: None,
+            sendmail: "sendmail".to_string(),
+        }
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            workers: 2,
+            poll_interval: 2,
+            max_attempts: 5,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn load() -> Result<Config, ConfigError> {
+        let explicit_file = env::var("CONFIG_FILE").ok();
+        let file = PathBuf::from(explicit_file.clone().unwrap_or_else(|| "config.toml".to_string()));
+
+        let mut config = if file.exists() {
+            let content = fs::read_to_string(&file)
+                .map_err(|err| ConfigError(format!("cannot read {}: {}", file.display(), err)))?;
+
+            toml::from_str(&content)
+                .map_err(|err| ConfigError(format!("cannot parse {}: {}", file.display(), err)))?
+        } else if explicit_file.is_some() {
+            return Err(ConfigError(format!("CONFIG_FILE {} does not exist", file.display())));
+        } else {
+            Config::default()
+        };
+
+        config.apply_environment()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn apply_environment(&mut self) -> Result<(), ConfigError> {
+        // `SERVER` is the historical name of the bind address
+        if let Some(host) = var("SERVER_HOST").or_else(|| var("SERVER")) {
+            self.server.host = host;
+        }
+        if let Some(port) = parsed("SERVER_PORT")? {
+            self.server.port = port;
+        }
+        if let Some(timeout) = parsed("SERVER_SHUTDOWN_TIMEOUT")? {
+            self.server.shutdown_timeout = timeout;
+        }
+        if let Some(origins) = var("CORS_ORIGINS") {
+            self.server.cors_origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        if let Some(url) = secret("DATABASE_URL")? {
+            self.database.url = url;
+        }
+        if let Some(size) = parsed("DATABASE_POOL_SIZE")? {
+            self.database.pool_size = size;
+        }
+        if let Some(timeout) = parsed("DATABASE_POOL_TIMEOUT")? {
+            self.database.pool_timeout = timeout;
+        }
+        if let Some(migrate) = parsed("DATABASE_MIGRATE_ON_STARTUP")? {
+            self.database.migrate_on_startup = migrate;
+        }
+        if let Some(level) = var("RUST_LOG") {
+            self.log.level = level;
+        }
+        if let Some(path) = var("REPORTS_PATH") {
+            self.storage.reports_path = PathBuf::from(path);
+        }
+        if let Some(path) = var("EXPORTS_PATH") {
+            self.storage.exports_path = PathBuf::from(path);
+        }
+        if let Some(path) = var("QUOTES_PATH") {
+            self.storage.quotes_path = PathBuf::from(path);
+        }
+        if let Some(path) = var("INVOICES_PATH") {
+            self.storage.invoices_path = PathBuf::from(path);
+        }
+        if let Some(hours) = parsed("SESSION_LIFETIME_HOURS")? {
+            self.auth.session_lifetime_hours = hours;
+        }
+        if let Some(workers) = parsed("JOB_WORKERS")? {
+            self.jobs.workers = workers;
+        }
+        if let Some(interval) = parsed("JOB_POLL_INTERVAL")? {
+            self.jobs.poll_interval = interval;
+        }
+        if let Some(attempts) = parsed("JOB_MAX_ATTEMPTS")? {
+            self.jobs.max_attempts = attempts;
+        }
+        if let Some(depth) = parsed("GRAPHQL_MAX_DEPTH")? {
+            self.limits.max_depth = depth;
+        }
+        if let Some(complexity) = parsed("GRAPHQL_MAX_COMPLEXITY")? {
+            self.limits.max_complexity = complexity;
+        }
+        if let Some(size) = parsed("GRAPHQL_MAX_BODY_SIZE")? {
+            self.limits.max_body_size = size;
+        }
+        if let Some(rate) = parsed("RATE_LIMIT_PER_MINUTE")? {
+            self.limits.rate_limit = rate;
+        }
+        if let Some(graphiql) = parsed("GRAPHIQL")? {
+            self.graphql.graphiql = graphiql;
+        }
+        if let Some(path) = var("PERSISTED_QUERIES") {
+            self.graphql.persisted_queries = Some(PathBuf::from(path));
+        }
+        if let Some(allow_list_only) = parsed("GRAPHQL_ALLOW_LIST_ONLY")? {
+            self.graphql.allow_list_only = allow_list_only;
+        }
+        if let Some(rate) = parsed("VAT_RATE")? {
+            self.billing.vat_rate = rate;
+        }
+        if let Some(days) = parsed("QUOTE_VALIDITY_DAYS")? {
+            self.billing.quote_validity_days = days;
+        }
+        if let Some(days) = parsed("PAYMENT_TERMS_DAYS")? {
+            self.billing.payment_terms_days = days;
+        }
+        if let Some(name) = var("COMPANY_NAME") {
+            self.billing.company_name = name;
+        }
+        if let Some(address) = var("COMPANY_ADDRESS") {
+            self.billing.company_address = address;
+        }
+        if let Some(siret) = var("COMPANY_SIRET") {
+            self.billing.company_siret = siret;
+        }
+        if let Some(number) = var("COMPANY_VAT_NUMBER") {
+            self.billing.company_vat_number = number;
+        }
+        if let Some(path) = var("SIGNING_CERTIFICATE") {
+            self.signing.certificate = Some(PathBuf::from(path));
+        }
+        if let Some(password) = secret("SIGNING_CERTIFICATE_PASSWORD")? {
+            self.signing.certificate_password = password;
+        }
+        if let Some(days) = parsed("CERTIFICATION_WARNING_DAYS")? {
+            self.certifications.warning_days = days;
+        }
+        if let Some(from) = var("MAIL_FROM") {
+            self.mail.from = Some(from);
+        }
+        if let Some(sendmail) = var("MAIL_SENDMAIL") {
+            self.mail.sendmail = sendmail;
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.database.url.is_empty() {
+            return Err(ConfigError(
+                "no database url, set DATABASE_URL (or DATABASE_URL_FILE) or database.url".to_string(),
+            ));
+        }
+        if self.server.host.is_empty() {
+            return Err(ConfigError("server.host must not be empty".to_string()));
+        }
+        if self.server.port == 0 {
+            return Err(ConfigError("server.port must not be 0".to_string()));
+        }
+        if self.server.cors_origins.is_empty() {
+            return Err(ConfigError(
+                "no CORS origin allowed, set CORS_ORIGINS or server.cors_origins (`*` for any)".to_string(),
+            ));
+        }
+        if self.database.pool_size == 0 {
+            return Err(ConfigError("database.pool_size must be at least 1".to_string()));
+        }
+        if self.database.pool_timeout == 0 {
+            return Err(ConfigError("database.pool_timeout must be at least 1 second".to_string()));
+        }
+        if self.auth.session_lifetime_hours <= 0 {
+            return Err(ConfigError("auth.session_lifetime_hours must be positive".to_string()));
+        }
+        if self.jobs.poll_interval == 0 {
+            return Err(ConfigError("jobs.poll_interval must be at least 1 second".to_string()));
+        }
+        if self.graphql.allow_list_only && self.graphql.persisted_queries.is_none() {
+            return Err(ConfigError("graphql.allow_list_only needs the persisted_queries manifest".to_string()));
+        }
+        if self.limits.max_body_size == 0 {
+            return Err(ConfigError("limits.max_body_size must be at least 1 byte".to_string()));
+        }
+        if self.jobs.max_attempts < 1 {
+            return Err(ConfigError("jobs.max_attempts must be at least 1".to_string()));
+        }
+        if !(0.0..=100.0).contains(&self.billing.vat_rate) {
+            return Err(ConfigError("billing.vat_rate must be a percent between 0 and 100".to_string()));
+        }
+        if self.billing.quote_validity_days < 1 {
+            return Err(ConfigError("billing.quote_validity_days must be at least 1".to_string()));
+        }
+        if !(0..=60).contains(&self.billing.payment_terms_days) {
+            return Err(ConfigError("billing.payment_terms_days must be between 0 and 60".to_string()));
+        }
+        if self.certifications.warning_days < 0 {
+            return Err(ConfigError("certifications.warning_days must not be negative".to_string()));
+        }
+        if self.mail.from.as_deref().is_some_and(|from| from.contains(['\r', '\n'])) {
+            return Err(ConfigError("mail.from must be a single line".to_string()));
+        }
+        if let Some(certificate) = &self.signing.certificate {
+            if !certificate.is_file() {
+                return Err(ConfigError(format!("signing.certificate {} does not exist", certificate.display())));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Environment variable, ignoring empty values
+fn var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.trim().is_empty())
+}
+
+// Secret given either inline or as the path of a file holding it
+fn secret(key: &str) -> Result<Option<String>, ConfigError> {
+    match var(&format!("{}_FILE", key)) {
+        Some(path) => fs::read_to_string(&path)
+            .map(|value| Some(value.trim().to_string()))
+            .map_err(|err| ConfigError(format!("cannot read {}_FILE {}: {}", key, path, err))),
+        None => Ok(var(key)),
+    }
+}
+
+// Numbers, or `true`/`false` for switches
+fn parsed<T: std::str::FromStr>(key: &str) -> Result<Option<T>, ConfigError> {
+    match var(key) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError(format!("{} has an invalid value: {}", key, value))),
+        None => Ok(None),
+    }
+}
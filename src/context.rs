@@ -1,12 +1,71 @@
+use diesel::pg::PgConnection;
+use std::sync::Arc;
 use super::database::PostgresPool;
+use crate::config::Config;
+use crate::loaders::Loaders;
+use crate::errors::{AppError, AppResult};
+use crate::models::users::User;
 
 // The GraphQL context, which needs to provide everything necessary for
-// interacting with the database.
-#[derive(Clone)]
+// interacting with the database. It is built for every request so it also
+// carries the user authenticated by the request's bearer token and the
+// loaders caching what this request already fetched.
 pub struct GraphQLContext {
+    pub config: Arc<Config>,
     pub pool: PostgresPool,
+    pub session_token: Option<String>,
+    pub user: Option<User>,
+    pub loaders: Loaders,
 }
 
 // This impl allows us to pass in GraphQLContext as the Context for GraphQL
 // objects
 impl juniper::Context for GraphQLContext {}
+
+impl GraphQLContext {
+    // Run database work off the async workers, see `database::run`
+    pub async fn run<F, T>(&self, work: F) -> AppResult<T>
+    where
+        F: FnOnce(&PgConnection) -> AppResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        super::database::run(&self.pool, work).await
+    }
+
+    // Id of the authenticated user, recorded as the actor of audited changes
+    pub fn actor_id(&self) -> Option<i32> {
+        self.user.as_ref().map(|user| user.id)
+    }
+
+    pub fn require_user(&self) -> AppResult<&User> {
+        self.user.as_ref().ok_or(AppError::Unauthorized)
+    }
+
+    pub async fn require_administrator(&self) -> AppResult<&User> {
+        let user = self.require_user()?;
+
+        if self.is_administrator().await? {
+            Ok(user)
+        } else {
+            Err(AppError::Forbidden("administrators only".to_string()))
+        }
+    }
+
+    pub async fn is_administrator(&self) -> AppResult<bool> {
+        use crate::schema::authorizations::dsl::*;
+        use diesel::prelude::*;
+
+        let user_authorization = self.require_user()?.authorization_id;
+
+        let user_level: String = self
+            .run(move |conn| {
+                Ok(authorizations
+                    .find(user_authorization)
+                    .select(level)
+                    .first(conn)?)
+            })
+            .await?;
+
+        Ok(user_level == "administrateur" || user_level == "developpeur")
+    }
+}
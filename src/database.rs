@@ -1,16 +1,35 @@
 use diesel::pg::PgConnection;
 use diesel::r2d2::{ConnectionManager, Pool};
-use dotenv::dotenv;
-use std::env;
+use std::time::Duration;
+use crate::config::DatabaseConfig;
+use crate::errors::{AppError, AppResult};
 
 pub type PostgresPool = Pool<ConnectionManager<PgConnection>>;
 
-pub fn get_pool() -> PostgresPool {
-    dotenv().ok();
-    let url: String = env::var("DATABASE_URL").expect("No database url set");
-    let mgr: ConnectionManager<PgConnection> = ConnectionManager::<PgConnection>::new(url);
+pub fn get_pool(config: &DatabaseConfig) -> PostgresPool {
+    let mgr: ConnectionManager<PgConnection> = ConnectionManager::<PgConnection>::new(config.url.as_str());
     Pool::builder()
-        .max_size(4)
+        .max_size(config.pool_size)
+        // A request waiting longer than this for a connection fails with an
+        // UNAVAILABLE error instead of hanging
+        .connection_timeout(Duration::from_secs(config.pool_timeout))
         .build(mgr)
         .expect("Could not build connection pool")
 }
+
+// Run blocking database work on the blocking thread pool so a slow query or a
+// report being rendered never stalls the async workers
+pub async fn run<F, T>(pool: &PostgresPool, work: F) -> AppResult<T>
+where
+    F: FnOnce(&PgConnection) -> AppResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+
+    actix_web::web::block(move || {
+        let conn = pool.get()?;
+        work(&conn)
+    })
+    .await
+    .map_err(|err| AppError::Internal(format!("blocking task: {}", err)))?
+}
@@ -0,0 +1,165 @@
+use chrono::{Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Europe::Paris;
+use juniper::parser::{ParseError, ScalarToken, Token};
+use juniper::{ParseScalarResult, Value};
+use crate::validation::{Validate, Validator};
+
+// Timestamps are stored in `timestamp` columns, always in UTC. They are
+// exposed as `DateTime` with an explicit offset and shown in French time in
+// reports, see `paris`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct DateTime(pub chrono::DateTime<Utc>);
+
+// A calendar day, `YYYY-MM-DD`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Date(pub NaiveDate);
+
+#[juniper::graphql_scalar(
+    name = "DateTime",
+    description = "ISO 8601 date and time, in UTC on output (`2022-06-01T14:30:00.000Z`), any offset on input"
+)]
+impl<S> GraphQLScalar for DateTime
+where
+    S: ScalarValue,
+{
+    fn resolve(&self) -> Value {
+        Value::scalar(self.0.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+
+    fn from_input_value(value: &InputValue) -> Option<DateTime> {
+        value
+            .as_string_value()
+            .and_then(|value| chrono::DateTime::parse_from_rfc3339(value).ok())
+            .map(|parsed| DateTime(parsed.with_timezone(&Utc)))
+    }
+
+    fn from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, S> {
+        if let ScalarToken::String(value) = value {
+            Ok(S::from(value.to_owned()))
+        } else {
+            Err(ParseError::UnexpectedToken(Token::Scalar(value)))
+        }
+    }
+}
+
+#[juniper::graphql_scalar(name = "Date", description = "ISO 8601 calendar date (`2022-06-01`)")]
+impl<S> GraphQLScalar for Date
+where
+    S: ScalarValue,
+{
+    fn resolve(&self) -> Value {
+        Value::scalar(self.0.format("%Y-%m-%d").to_string())
+    }
+
+    fn from_input_value(value: &InputValue) -> Option<Date> {
+        value
+            .as_string_value()
+            .and_then(|value| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok())
+            .map(Date)
+    }
+
+    fn from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, S> {
+        if let ScalarToken::String(value) = value {
+            Ok(S::from(value.to_owned()))
+        } else {
+            Err(ParseError::UnexpectedToken(Token::Scalar(value)))
+        }
+    }
+}
+
+impl From<NaiveDateTime> for DateTime {
+    fn from(utc: NaiveDateTime) -> DateTime {
+        DateTime(Utc.from_utc_datetime(&utc))
+    }
+}
+
+// Offset of French time at an instant, from the tz database
+fn paris_offset(utc: NaiveDateTime) -> FixedOffset {
+    Paris.offset_from_utc_datetime(&utc).fix()
+}
+
+// A stored timestamp in French time, for documents read by people
+pub fn paris(utc: NaiveDateTime) -> chrono::DateTime<FixedOffset> {
+    paris_offset(utc).from_utc_datetime(&utc)
+}
+
+// The current day in France
+pub fn today() -> NaiveDate {
+    paris(Utc::now().naive_utc()).naive_local().date()
+}
+
+// Day typed in a worksite, DD/MM/YYYY or YYYY-MM-DD
+pub fn parse(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%d/%m/%Y")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d"))
+        .ok()
+}
+
+// Start of a day in French time, as a UTC timestamp. Midnight is never near
+// a summer time switch.
+fn paris_midnight(day: NaiveDate) -> NaiveDateTime {
+    let midnight = day.and_hms(0, 0, 0);
+    midnight - Duration::seconds(paris_offset(midnight).local_minus_utc().into())
+}
+
+#[derive(Debug, GraphQLInputObject)]
+#[graphql(description = "Days of a filter, both included, in French time. A missing bound leaves the range open.")]
+pub struct DateRange {
+    pub from: Option<Date>,
+    pub to: Option<Date>,
+}
+
+impl Validate for DateRange {
+    fn rules(&self, v: &mut Validator) {
+        v.ordered("to", &self.from, &self.to);
+    }
+}
+
+impl DateRange {
+    // UTC timestamps to filter on: at or after the first, before the second
+    pub fn bounds(&self) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+        (
+            self.from.map(|from| paris_midnight(from.0)),
+            self.to.map(|to| paris_midnight(to.0 + Duration::days(1))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn hours(utc: &str) -> i32 {
+        paris_offset(at(utc)).local_minus_utc() / 3600
+    }
+
+    #[test]
+    fn summer_time_starts_on_the_last_sunday_of_march() {
+        assert_eq!(hours("2026-03-29 00:59:59"), 1);
+        assert_eq!(hours("2026-03-29 01:00:00"), 2);
+        assert_eq!(paris(at("2026-03-29 00:59:59")).to_rfc3339(), "2026-03-29T01:59:59+01:00");
+        assert_eq!(paris(at("2026-03-29 01:00:00")).to_rfc3339(), "2026-03-29T03:00:00+02:00");
+    }
+
+    #[test]
+    fn summer_time_ends_on_the_last_sunday_of_october() {
+        assert_eq!(hours("2026-10-25 00:59:59"), 2);
+        assert_eq!(hours("2026-10-25 01:00:00"), 1);
+        assert_eq!(paris(at("2026-10-25 00:59:59")).to_rfc3339(), "2026-10-25T02:59:59+02:00");
+        assert_eq!(paris(at("2026-10-25 01:00:00")).to_rfc3339(), "2026-10-25T02:00:00+01:00");
+    }
+
+    #[test]
+    fn days_start_at_midnight_in_french_time() {
+        let range = DateRange {
+            from: Some(Date(NaiveDate::from_ymd(2026, 3, 28))),
+            to: Some(Date(NaiveDate::from_ymd(2026, 10, 25))),
+        };
+
+        assert_eq!(range.bounds(), (Some(at("2026-03-27 23:00:00")), Some(at("2026-10-25 23:00:00"))));
+    }
+}
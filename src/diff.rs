@@ -0,0 +1,88 @@
+use serde_json::{Map, Value};
+
+// A single leaf that differs between two JSON documents, addressed by a
+// dotted path such as `worksite.asbestos.2.fcr_result`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+// Walk both documents and collect every leaf whose value differs. Objects and
+// arrays are compared member by member so a single edited field doesn't show
+// up as a whole replaced section.
+pub fn changes(before: &Value, after: &Value) -> Vec<Change> {
+    let mut found = Vec::new();
+    walk(String::new(), before, after, &mut found);
+    found
+}
+
+// Same as `changes`, shaped as `{ "<path>": { "before": .., "after": .. } }`
+// so it can be stored as-is in a jsonb column.
+pub fn diff(before: &Value, after: &Value) -> Value {
+    let mut object = Map::new();
+
+    for change in changes(before, after) {
+        let mut entry = Map::new();
+        entry.insert("before".to_string(), change.before);
+        entry.insert("after".to_string(), change.after);
+        object.insert(change.path, Value::Object(entry));
+    }
+
+    Value::Object(object)
+}
+
+fn walk(path: String, before: &Value, after: &Value, found: &mut Vec<Change>) {
+    // A created or deleted object is reported field by field rather than as
+    // one opaque value, which is what an audit reader expects to see.
+    let empty = Map::new();
+
+    match (before, after) {
+        (Value::Object(left), Value::Object(right)) => walk_objects(&path, left, right, found),
+        (Value::Object(left), Value::Null) => walk_objects(&path, left, &empty, found),
+        (Value::Null, Value::Object(right)) => walk_objects(&path, &empty, right, found),
+        (Value::Array(left), Value::Array(right)) => {
+            for index in 0..left.len().max(right.len()) {
+                walk(
+                    join(&path, &index.to_string()),
+                    left.get(index).unwrap_or(&Value::Null),
+                    right.get(index).unwrap_or(&Value::Null),
+                    found,
+                );
+            }
+        }
+        _ => {
+            if before != after {
+                found.push(Change {
+                    path,
+                    before: before.clone(),
+                    after: after.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn walk_objects(path: &str, left: &Map<String, Value>, right: &Map<String, Value>, found: &mut Vec<Change>) {
+    let mut keys: Vec<&String> = left.keys().chain(right.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    for key in keys {
+        walk(
+            join(path, key),
+            left.get(key).unwrap_or(&Value::Null),
+            right.get(key).unwrap_or(&Value::Null),
+            found,
+        );
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use juniper::{FieldError, IntoFieldError, Object, ScalarValue, Value};
+use log::{error, warn};
+
+// One invalid field of an input, `field` is the GraphQL path of the value
+// such as `address.streetNumber`.
+#[derive(Debug, Clone)]
+pub struct FieldViolation {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldViolation {
+    pub fn new(field: &str, message: &str) -> FieldViolation {
+        FieldViolation {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+// Every error a resolver can return. Each variant is reported to the client
+// with a stable `code` in the GraphQL error extensions so the frontend never
+// has to parse messages.
+#[derive(Debug, Clone)]
+pub enum AppError {
+    NotFound(String),
+    Validation(Vec<FieldViolation>),
+    Unauthorized,
+    Forbidden(String),
+    Conflict(String),
+    Unavailable(String),
+    // The operation was refused before running, too deep or too expensive
+    QueryTooComplex(String),
+    // Seconds before the client may retry
+    RateLimited(u64),
+    // Largest body accepted, in bytes
+    PayloadTooLarge(usize),
+    // The hash of an automatic persisted query is unknown, the client sends
+    // the query again along with its hash
+    PersistedQueryNotFound,
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::Validation(_) => "VALIDATION_FAILED",
+            AppError::Unauthorized => "UNAUTHENTICATED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::Unavailable(_) => "UNAVAILABLE",
+            AppError::QueryTooComplex(_) => "QUERY_TOO_COMPLEX",
+            AppError::RateLimited(_) => "RATE_LIMITED",
+            AppError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            AppError::PersistedQueryNotFound => "PERSISTED_QUERY_NOT_FOUND",
+            AppError::Internal(_) => "INTERNAL",
+        }
+    }
+
+    // Shortcut for an input rejected because of a single field
+    pub fn invalid(field: &str, message: &str) -> AppError {
+        AppError::Validation(vec![FieldViolation::new(field, message)])
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(what) => write!(f, "{} not found", what),
+            AppError::Validation(violations) => write!(f, "Invalid input ({} invalid fields)", violations.len()),
+            AppError::Unauthorized => write!(f, "You must be authenticated"),
+            AppError::Forbidden(reason) => write!(f, "Forbidden: {}", reason),
+            AppError::Conflict(reason) => write!(f, "Conflict: {}", reason),
+            AppError::Unavailable(reason) => write!(f, "Service unavailable: {}", reason),
+            AppError::QueryTooComplex(reason) => write!(f, "Query refused: {}", reason),
+            AppError::RateLimited(retry_after) => write!(f, "Too many requests, retry in {}s", retry_after),
+            AppError::PayloadTooLarge(limit) => write!(f, "Request body larger than {} bytes", limit),
+            // Apollo Client looks for this exact message
+            AppError::PersistedQueryNotFound => write!(f, "PersistedQueryNotFound"),
+            // Internal details are logged, never sent to the client
+            AppError::Internal(_) => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl<S: ScalarValue> IntoFieldError<S> for AppError {
+    fn into_field_error(self) -> FieldError<S> {
+        crate::monitoring::GRAPHQL_ERRORS.with_label_values(&[self.code()]).inc();
+
+        if let AppError::Internal(details) = &self {
+            error!("Internal error: {}", details);
+        }
+
+        let mut extensions = Object::with_capacity(2);
+        extensions.add_field("code", Value::scalar(self.code().to_string()));
+
+        if let AppError::Validation(violations) = &self {
+            let fields = violations
+                .iter()
+                .map(|violation| {
+                    let mut field = Object::with_capacity(2);
+                    field.add_field("field", Value::scalar(violation.field.clone()));
+                    field.add_field("message", Value::scalar(violation.message.clone()));
+                    Value::object(field)
+                })
+                .collect();
+
+            extensions.add_field("fields", Value::list(fields));
+        }
+
+        FieldError::new(self.to_string(), Value::object(extensions))
+    }
+}
+
+impl From<diesel::result::Error> for AppError {
+    fn from(err: diesel::result::Error) -> AppError {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        match err {
+            Error::NotFound => AppError::NotFound("Record".to_string()),
+            // The message names the constraint and the duplicated value,
+            // only the logs get it
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                warn!(
+                    "Unique violation on {}: {}",
+                    info.constraint_name().unwrap_or("unknown constraint"),
+                    info.message()
+                );
+                AppError::Conflict("Record already exists".to_string())
+            }
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                AppError::Validation(vec![FieldViolation::new(
+                    info.column_name().unwrap_or("input"),
+                    "References a record that does not exist",
+                )])
+            }
+            other => AppError::Internal(other.to_string()),
+        }
+    }
+}
+
+// The pool only fails when no connection frees up before its timeout
+impl From<r2d2::Error> for AppError {
+    fn from(err: r2d2::Error) -> AppError {
+        warn!("Database pool exhausted: {}", err);
+        AppError::Unavailable("the database is busy, retry later".to_string())
+    }
+}
+
+impl From<argon2::password_hash::Error> for AppError {
+    fn from(err: argon2::password_hash::Error) -> AppError {
+        AppError::Internal(format!("password hash: {}", err))
+    }
+}
+
+impl From<lopdf::Error> for AppError {
+    fn from(err: lopdf::Error) -> AppError {
+        AppError::Internal(format!("pdf: {}", err))
+    }
+}
+
+impl From<openssl::error::ErrorStack> for AppError {
+    fn from(err: openssl::error::ErrorStack) -> AppError {
+        AppError::Internal(format!("openssl: {}", err))
+    }
+}
+
+impl From<diesel_migrations::RunMigrationsError> for AppError {
+    fn from(err: diesel_migrations::RunMigrationsError) -> AppError {
+        AppError::Internal(format!("migration: {}", err))
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> AppError {
+        AppError::Internal(format!("io: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::result::{DatabaseErrorKind, Error};
+
+    fn database_error(kind: DatabaseErrorKind, message: &str) -> AppError {
+        Error::DatabaseError(kind, Box::new(message.to_string())).into()
+    }
+
+    #[test]
+    fn unique_violations_hide_the_constraint_and_the_value() {
+        let err = database_error(
+            DatabaseErrorKind::UniqueViolation,
+            "duplicate key value violates unique constraint \"users_name_key\"",
+        );
+
+        assert!(matches!(&err, AppError::Conflict(message) if message == "Record already exists"));
+        assert_eq!(err.code(), "CONFLICT");
+        assert!(!err.to_string().contains("users_name_key"));
+    }
+
+    #[test]
+    fn foreign_key_violations_are_invalid_input() {
+        let err = database_error(DatabaseErrorKind::ForeignKeyViolation, "violates foreign key constraint");
+
+        match err {
+            AppError::Validation(violations) => {
+                assert_eq!(violations.len(), 1);
+                assert_eq!(violations[0].field, "input");
+            }
+            other => panic!("unexpected error {}", other),
+        }
+    }
+
+    #[test]
+    fn missing_rows_are_not_found_and_the_rest_internal() {
+        assert!(matches!(AppError::from(Error::NotFound), AppError::NotFound(_)));
+        assert!(matches!(
+            database_error(DatabaseErrorKind::SerializationFailure, "could not serialize access"),
+            AppError::Internal(_)
+        ));
+    }
+}
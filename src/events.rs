@@ -0,0 +1,182 @@
+use futures::Stream;
+use lazy_static::lazy_static;
+use log::warn;
+use std::collections::HashSet;
+use std::pin::Pin;
+use tokio::sync::broadcast;
+use crate::GraphQLContext;
+use crate::database::{self, PostgresPool};
+use crate::dates::DateTime;
+use crate::errors::AppResult;
+use crate::models::audit_logs::{AuditLog, ENTITY_WORKSITE};
+use crate::models::worksites::Worksite;
+
+// Kinds of worksite events pushed to subscribers
+pub const EVENT_CREATED: &str = "created";
+pub const EVENT_UPDATED: &str = "updated";
+pub const EVENT_REVISION_ISSUED: &str = "revision_issued";
+pub const EVENT_REPORT_READY: &str = "report_ready";
+pub const EVENT_LAB_RESULTS: &str = "lab_results";
+
+// Events are broadcast inside the process that made the change, so a
+// subscriber only hears about the changes made through its own instance.
+// A subscriber lagging this far behind misses the oldest events.
+const CAPACITY: usize = 256;
+
+lazy_static! {
+    static ref CHANNEL: broadcast::Sender<WorksiteEvent> = broadcast::channel(CAPACITY).0;
+}
+
+#[derive(Debug, Clone)]
+pub struct WorksiteEvent {
+    pub kind: &'static str,
+    pub worksite_id: i32,
+    pub client_id: i32,
+    pub revision: Option<i32>,
+    pub at: chrono::NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Something happened to a worksite")]
+impl WorksiteEvent {
+    #[graphql(description = "created, updated, revision_issued, report_ready or lab_results")]
+    fn kind(&self) -> &str {
+        self.kind
+    }
+
+    fn worksite_id(&self) -> i32 {
+        self.worksite_id
+    }
+
+    fn client_id(&self) -> i32 {
+        self.client_id
+    }
+
+    #[graphql(description = "Revision issued or whose report is ready")]
+    fn revision(&self) -> Option<i32> {
+        self.revision
+    }
+
+    fn at(&self) -> DateTime {
+        self.at.into()
+    }
+
+    #[graphql(description = "The worksite as it is now")]
+    async fn worksite(&self, context: &GraphQLContext) -> AppResult<Worksite> {
+        // Not through the loaders, their cache lives as long as the
+        // subscription
+        let worksite_id = self.worksite_id;
+
+        context.run(move |conn| Worksite::find(conn, worksite_id)).await
+    }
+}
+
+impl WorksiteEvent {
+    pub fn new(kind: &'static str, worksite: &Worksite, revision: Option<i32>) -> WorksiteEvent {
+        WorksiteEvent {
+            kind,
+            worksite_id: worksite.id,
+            client_id: worksite.client_id,
+            revision,
+            at: chrono::offset::Utc::now().naive_utc(),
+        }
+    }
+}
+
+// Push an event to the subscribers, call it once the change is committed
+pub fn publish(event: WorksiteEvent) {
+    // Fails only when nobody is listening
+    let _ = CHANNEL.send(event);
+}
+
+pub type WorksiteEventStream = Pin<Box<dyn Stream<Item = WorksiteEvent> + Send>>;
+
+// Who a subscriber hears about
+#[derive(Debug, Clone, Copy)]
+pub enum Audience {
+    // Administrators, every worksite
+    Everything,
+    // Other users, the worksites whose audit trail names them: they created,
+    // edited or issued them
+    WorkedOnBy(i32),
+}
+
+impl Audience {
+    // Worksites found visible are remembered in `visible`, a user doesn't
+    // stop having worked on one
+    async fn may_see(self, pool: &PostgresPool, visible: &mut HashSet<i32>, worksite_id: i32) -> bool {
+        let user_id = match self {
+            Audience::Everything => return true,
+            Audience::WorkedOnBy(user_id) => user_id,
+        };
+        if visible.contains(&worksite_id) {
+            return true;
+        }
+
+        let worked_on =
+            database::run(pool, move |conn| Ok(AuditLog::acted_on(conn, user_id, ENTITY_WORKSITE, worksite_id)?)).await;
+
+        match worked_on {
+            Ok(true) => {
+                visible.insert(worksite_id);
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                warn!("Cannot tell whether user {} may see worksite {}: {}", user_id, worksite_id, err);
+                false
+            }
+        }
+    }
+}
+
+struct Subscriber {
+    receiver: broadcast::Receiver<WorksiteEvent>,
+    audience: Audience,
+    pool: PostgresPool,
+    visible: HashSet<i32>,
+}
+
+// Events from now on that `audience` may see, of a worksite or of the
+// worksites of a client when given
+pub fn subscribe(
+    worksite_id: Option<i32>,
+    client_id: Option<i32>,
+    audience: Audience,
+    pool: PostgresPool,
+) -> WorksiteEventStream {
+    let subscriber = Subscriber {
+        receiver: CHANNEL.subscribe(),
+        audience,
+        pool,
+        visible: HashSet::new(),
+    };
+
+    Box::pin(futures::stream::unfold(subscriber, move |mut subscriber| async move {
+        loop {
+            let event = match subscriber.receiver.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("A subscriber missed {} worksite events", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            if worksite_id.is_some_and(|id| event.worksite_id != id) || client_id.is_some_and(|id| event.client_id != id) {
+                continue;
+            }
+
+            let Subscriber {
+                audience,
+                pool,
+                visible,
+                ..
+            } = &mut subscriber;
+
+            if audience.may_see(pool, visible, event.worksite_id).await {
+                return Some((event, subscriber));
+            }
+        }
+    }))
+}
@@ -0,0 +1,229 @@
+use diesel::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::errors::{AppError, AppResult};
+use crate::models::clients::{Address, AddressInput, Client, ClientInput, Interlocutor, InterlocutorInput};
+use crate::models::worksites::{Worksite, WorksiteContent};
+use crate::validation::Validator;
+
+// Business data moved between databases. Ids are only used to link the
+// worksites to their client, importing creates new rows.
+#[derive(Serialize, Deserialize)]
+pub struct Export {
+    pub clients: Vec<ExportedClient>,
+    pub worksites: Vec<ExportedWorksite>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedClient {
+    pub id: i32,
+    pub name: String,
+    pub address: Address,
+    pub interlocutors: Vec<Interlocutor>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedWorksite {
+    pub id: i32,
+    pub client_id: i32,
+    pub worksite: WorksiteContent,
+}
+
+// Every client and worksite, or a single client with its worksites
+pub fn collect(conn: &PgConnection, of_client: Option<i32>) -> AppResult<Export> {
+    let clients = match of_client {
+        Some(client_id) => vec![crate::schema::clients::table.find(client_id).first::<Client>(conn)?],
+        None => Client::all(conn)?,
+    };
+
+    let worksites = Worksite::all(conn)?
+        .into_iter()
+        .filter(|worksite| of_client.is_none_or(|client_id| worksite.client_id == client_id))
+        .map(|worksite| ExportedWorksite {
+            id: worksite.id,
+            client_id: worksite.client_id,
+            worksite: worksite.worksite.0,
+        })
+        .collect();
+
+    let clients = clients
+        .into_iter()
+        .map(|client| ExportedClient {
+            id: client.id,
+            name: client.name,
+            address: client.address.0,
+            interlocutors: client.interlocutors.map(|list| list.0).unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(Export { clients, worksites })
+}
+
+pub fn write(data: &Export, path: &Path) -> AppResult<()> {
+    let json = serde_json::to_string_pretty(data)
+        .map_err(|err| AppError::Internal(format!("export: {}", err)))?;
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    Ok(fs::write(path, json)?)
+}
+
+pub fn read(path: &Path) -> AppResult<Export> {
+    serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|err| AppError::Internal(format!("{} is not an export: {}", path.display(), err)))
+}
+
+// Insert the content of an export through the model code, returns the number
+// of imported clients. Everything or nothing, a failed import leaves no half
+// copied client.
+pub fn import(conn: &PgConnection, data: Export) -> AppResult<usize> {
+    conn.transaction::<usize, AppError, _>(|| {
+        let mut client_ids = HashMap::new();
+
+        for exported in data.clients {
+            let client = Client::create(
+                conn,
+                ClientInput {
+                    name: exported.name,
+                    address: Some(AddressInput {
+                        street: exported.address.street,
+                        street_number: exported.address.street_number,
+                        postal_code: exported.address.postal_code,
+                        city: exported.address.city,
+                    }),
+                    interlocutors: Some(
+                        exported
+                            .interlocutors
+                            .into_iter()
+                            .map(|interlocutor| InterlocutorInput {
+                                name: interlocutor.name,
+                                position: interlocutor.position,
+                                email: interlocutor.email,
+                                phone: interlocutor.phone,
+                            })
+                            .collect(),
+                    ),
+                },
+                None,
+            )?;
+
+            client_ids.insert(exported.id, client.id);
+        }
+
+        for exported in data.worksites {
+            let client_id = *client_ids.get(&exported.client_id).ok_or_else(|| {
+                AppError::NotFound(format!("Client {} of worksite {} in the export", exported.client_id, exported.id))
+            })?;
+
+            Worksite::create(conn, client_id, exported.worksite, None)?;
+        }
+
+        Ok(client_ids.len())
+    })
+}
+
+// Columns of a spreadsheet of clients, `interlocutor_*` ones are optional
+const CSV_COLUMNS: &[&str] = &[
+    "name",
+    "street_number",
+    "street",
+    "postal_code",
+    "city",
+    "interlocutor_name",
+    "interlocutor_position",
+    "interlocutor_email",
+    "interlocutor_phone",
+];
+const CSV_REQUIRED: &[&str] = &["name", "street_number", "street"];
+
+// Clients of a spreadsheet saved as CSV, one per line below a header naming
+// the columns. French spreadsheets separate their columns with `;`, both
+// separators are read. Every line is checked, violations are named after
+// their line: `lines.3.address.postalCode`.
+pub fn read_clients_csv(content: &str) -> AppResult<Vec<ClientInput>> {
+    let content = content.trim_start_matches('\u{feff}');
+    let header = content.lines().next().unwrap_or_default();
+    let delimiter = if header.matches(';').count() > header.matches(',').count() { b';' } else { b',' };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let headers = reader.headers().map_err(|err| AppError::invalid("csv", &err.to_string()))?.clone();
+    let column = |name: &str| headers.iter().position(|header| header.eq_ignore_ascii_case(name));
+
+    let mut validator = Validator::default();
+    for name in CSV_REQUIRED {
+        validator.check("csv", column(name).is_some(), &format!("Missing the {} column", name));
+    }
+    for header in headers.iter().filter(|header| !CSV_COLUMNS.iter().any(|name| header.eq_ignore_ascii_case(name))) {
+        validator.check("csv", false, &format!("Unknown column {}, expected {}", header, CSV_COLUMNS.join(", ")));
+    }
+    validator.finish()?;
+
+    let mut clients = Vec::new();
+    let mut validator = Validator::default();
+
+    for record in reader.records() {
+        let record = record.map_err(|err| AppError::invalid("csv", &err.to_string()))?;
+        let line = record.position().map_or(0, |position| position.line());
+        let cell = |name: &str| {
+            column(name)
+                .and_then(|index| record.get(index))
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        let street_number = cell("street_number").unwrap_or_default();
+        let parsed_number = street_number.parse::<i32>();
+        validator.check(
+            &format!("lines.{}.address.streetNumber", line),
+            parsed_number.is_ok(),
+            "Must be a number",
+        );
+
+        let interlocutors = cell("interlocutor_name").map(|name| {
+            vec![InterlocutorInput {
+                name,
+                position: cell("interlocutor_position").unwrap_or_default(),
+                email: cell("interlocutor_email"),
+                phone: cell("interlocutor_phone"),
+            }]
+        });
+
+        let client = ClientInput {
+            name: cell("name").unwrap_or_default(),
+            address: Some(AddressInput {
+                street: cell("street").unwrap_or_default(),
+                street_number: parsed_number.unwrap_or(1),
+                postal_code: cell("postal_code"),
+                city: cell("city"),
+            }),
+            interlocutors,
+        };
+
+        validator.child(&format!("lines.{}", line), &client);
+        clients.push(client);
+    }
+
+    validator.check("csv", !clients.is_empty(), "No client below the header");
+    validator.finish()?;
+
+    Ok(clients)
+}
+
+// Create the clients of a spreadsheet, everything or nothing, returns their
+// ids
+pub fn import_clients(conn: &PgConnection, clients: Vec<ClientInput>, actor: Option<i32>) -> AppResult<Vec<i32>> {
+    conn.transaction::<Vec<i32>, AppError, _>(|| {
+        clients
+            .into_iter()
+            .map(|input| Ok(Client::create(conn, input, actor)?.id))
+            .collect()
+    })
+}
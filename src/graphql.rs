@@ -1,8 +1,21 @@
 use super::context::GraphQLContext;
+use crate::events::{self, Audience, WorksiteEvent, WorksiteEventStream, EVENT_CREATED, EVENT_UPDATED};
+use crate::issue_checks::IssueCheck;
+use crate::models::audit_logs::{AuditLog, AuditLogQuery, ENTITY_WORKSITE, OPERATION_UPDATE};
 use crate::models::users::{UserMutation, UserQuery};
+use crate::models::jobs::{JobMutation, JobQuery};
+use crate::models::price_grids::{PriceGridMutation, PriceGridQuery};
+use crate::models::quotes::{QuoteMutation, QuoteQuery};
+use crate::models::invoices::{InvoiceMutation, InvoiceQuery};
+use crate::models::certifications::{CertificationMutation, CertificationQuery};
+use crate::models::instruments::{InstrumentMutation, InstrumentQuery};
+use crate::models::laboratories::{LaboratoryMutation, LaboratoryQuery};
 use crate::models::clients::{ClientMutation, ClientQuery};
-use crate::models::worksites::{CreateNewWorksite, NewWorksite, Worksite, WorksiteContent};
-use juniper::{graphql_value, EmptySubscription, FieldError, FieldResult, RootNode};
+use crate::models::worksite_versions::{WorksiteVersion, WorksiteVersionMutation, WorksiteVersionQuery};
+use crate::models::worksites::{CreateNewWorksite, CreateWorksiteContent, Worksite, WorksiteContent};
+use crate::errors::{AppError, AppResult};
+use crate::validation::validate;
+use juniper::RootNode;
 
 pub struct Query;
 
@@ -16,25 +29,66 @@ impl Query {
         ClientQuery
     }
 
+    fn audit_logs(&self) -> AuditLogQuery {
+        AuditLogQuery
+    }
+
+    fn worksite_versions(&self) -> WorksiteVersionQuery {
+        WorksiteVersionQuery
+    }
+
+    fn jobs(&self) -> JobQuery {
+        JobQuery
+    }
+
+    fn price_grids(&self) -> PriceGridQuery {
+        PriceGridQuery
+    }
+
+    fn quotes(&self) -> QuoteQuery {
+        QuoteQuery
+    }
+
+    fn invoices(&self) -> InvoiceQuery {
+        InvoiceQuery
+    }
+
+    fn certifications(&self) -> CertificationQuery {
+        CertificationQuery
+    }
+
+    fn instruments(&self) -> InstrumentQuery {
+        InstrumentQuery
+    }
+
+    fn laboratories(&self) -> LaboratoryQuery {
+        LaboratoryQuery
+    }
 
     #[graphql(description = "Fetch a worksite")]
-    fn worksite(context: &GraphQLContext, worksite_id: i32) -> FieldResult<Worksite> {
+    async fn worksite(context: &GraphQLContext, worksite_id: i32) -> AppResult<Worksite> {
         use crate::schema::worksites::dsl::*;
         use diesel::prelude::*;
 
-        let conn = context.pool.get()?;
-
-        let worksite_result = worksites.find(worksite_id).first::<Worksite>(&conn);
+        context
+            .run(move |conn| {
+                worksites
+                    .find(worksite_id)
+                    .first::<Worksite>(conn)
+                    .optional()?
+                    .ok_or_else(|| AppError::NotFound("Worksite".to_string()))
+            })
+            .await
+    }
 
-        if worksite_result.is_ok() {
-            Ok(worksite_result.unwrap())
-        } else {
-            Err(FieldError::new(
-                "Could not get worksite",
-                graphql_value!({ "authentication_error": "Invalid value" }),
-            ))
-        }
+    #[graphql(description = "Check a worksite before the current user issues its report: errors block issuing, warnings don't")]
+    async fn validate_worksite_for_issue(context: &GraphQLContext, worksite_id: i32) -> AppResult<IssueCheck> {
+        let issuer = context.actor_id();
+        let warning_days = context.config.certifications.warning_days;
 
+        context
+            .run(move |conn| IssueCheck::of_worksite(conn, worksite_id, issuer, warning_days))
+            .await
     }
 
 }
@@ -52,6 +106,38 @@ impl Mutation {
         ClientMutation
     }
 
+    fn worksite_versions(&self) -> WorksiteVersionMutation {
+        WorksiteVersionMutation
+    }
+
+    fn jobs(&self) -> JobMutation {
+        JobMutation
+    }
+
+    fn price_grids(&self) -> PriceGridMutation {
+        PriceGridMutation
+    }
+
+    fn quotes(&self) -> QuoteMutation {
+        QuoteMutation
+    }
+
+    fn invoices(&self) -> InvoiceMutation {
+        InvoiceMutation
+    }
+
+    fn certifications(&self) -> CertificationMutation {
+        CertificationMutation
+    }
+
+    fn instruments(&self) -> InstrumentMutation {
+        InstrumentMutation
+    }
+
+    fn laboratories(&self) -> LaboratoryMutation {
+        LaboratoryMutation
+    }
+
 
     //  ██████╗██████╗ ███████╗ █████╗ ████████╗███████╗██╗    ██╗ ██████╗ ██████╗ ██╗  ██╗███████╗██╗████████╗███████╗
     // ██╔════╝██╔══██╗██╔════╝██╔══██╗╚══██╔══╝██╔════╝██║    ██║██╔═══██╗██╔══██╗██║ ██╔╝██╔════╝██║╚══██╔══╝██╔════╝
@@ -60,34 +146,123 @@ impl Mutation {
     // ╚██████╗██║  ██║███████╗██║  ██║   ██║   ███████╗╚███╔███╔╝╚██████╔╝██║  ██║██║  ██╗███████║██║   ██║   ███████╗
     //  ╚═════╝╚═╝  ╚═╝╚══════╝╚═╝  ╚═╝   ╚═╝   ╚══════╝ ╚══╝╚══╝  ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝╚═╝   ╚═╝   ╚══════╝
     #[graphql(description = "Create a new worksite associated with a client id")]
-    fn create_worksite(
+    async fn create_worksite(
         context: &GraphQLContext,
         input: CreateNewWorksite,
-    ) -> FieldResult<Worksite> {
+    ) -> AppResult<Worksite> {
+        let actor = context.require_user()?.id;
+
+        validate(&input)?;
+
+        let new_worksite: WorksiteContent = input
+            .worksite
+            .ok_or_else(|| AppError::invalid("worksite", "Is required"))?
+            .into();
+
+        let created = context
+            .run(move |conn| Worksite::create(conn, input.client_id, new_worksite, Some(actor)))
+            .await?;
+
+        events::publish(WorksiteEvent::new(EVENT_CREATED, &created, None));
+        Ok(created)
+    }
+
+    #[graphql(description = "Replace the document of a worksite, the previous one is kept as a version")]
+    async fn update_worksite(
+        context: &GraphQLContext,
+        worksite_id: i32,
+        input: CreateWorksiteContent,
+    ) -> AppResult<Worksite> {
         use crate::schema::worksites::dsl::*;
         use diesel::prelude::*;
 
-        let new_worksite: WorksiteContent = input.worksite.expect("No worksite content set").into();
+        let actor = Some(context.require_user()?.id);
 
-        let new_worksite: NewWorksite = NewWorksite {
-            client_id: &input.client_id,
-            worksite: &diesel_json::Json::new(new_worksite),
-            created_at: &chrono::offset::Utc::now().naive_utc(),
-            edited_at: &chrono::offset::Utc::now().naive_utc(),
-        };
+        validate(&input)?;
+
+        let new_content: WorksiteContent = input.into();
+
+        let updated = context
+            .run(move |conn| {
+                conn.transaction::<Worksite, AppError, _>(|| {
+                    let previous: Worksite = worksites
+                        .find(worksite_id)
+                        .first(conn)
+                        .optional()?
+                        .ok_or_else(|| AppError::NotFound("Worksite".to_string()))?;
 
-        let conn = context.pool.get()?;
+                    let updated: Worksite = diesel::update(worksites.find(worksite_id))
+                        .set((
+                            worksite.eq(diesel_json::Json::new(new_content)),
+                            edited_at.eq(chrono::offset::Utc::now().naive_utc()),
+                        ))
+                        .get_result(conn)?;
 
-        let created_worksite = diesel::insert_into(worksites)
-            .values(new_worksite)
-            .get_result(&conn);
+                    WorksiteVersion::record(conn, &updated, actor)?;
+                    AuditLog::record(conn, actor, ENTITY_WORKSITE, updated.id, OPERATION_UPDATE, Some(&previous), Some(&updated))?;
 
-        Ok(created_worksite.expect("Could not create a client"))
+                    Ok(updated)
+                })
+            })
+            .await?;
+
+        events::publish(WorksiteEvent::new(EVENT_UPDATED, &updated, None));
+        Ok(updated)
     }
 }
 
-pub type Schema = RootNode<'static, Query, Mutation, EmptySubscription<GraphQLContext>>;
+pub struct Subscription;
+
+#[juniper::graphql_subscription(Context = GraphQLContext, description = "Subscription Root")]
+impl Subscription {
+    #[graphql(description = "Changes of the worksites, of a single worksite or of the worksites of a client when given. Administrators hear about every worksite, other users about the worksites they created, edited or issued.")]
+    async fn worksite_events(
+        context: &GraphQLContext,
+        worksite_id: Option<i32>,
+        client_id: Option<i32>,
+    ) -> AppResult<WorksiteEventStream> {
+        let user_id = context.require_user()?.id;
+
+        let audience = if context.is_administrator().await? {
+            Audience::Everything
+        } else {
+            Audience::WorkedOnBy(user_id)
+        };
+
+        Ok(events::subscribe(worksite_id, client_id, audience, context.pool.clone()))
+    }
+}
+
+pub type Schema = RootNode<'static, Query, Mutation, Subscription>;
 
 pub fn create_schema() -> Schema {
-    Schema::new(Query {}, Mutation {}, EmptySubscription::new())
+    Schema::new(Query {}, Mutation {}, Subscription {})
+}
+
+// The schema in the GraphQL schema language, as committed in
+// `frontend/schema.graphql`. Types are sorted by name so the output only
+// changes with the schema.
+pub fn schema_language(schema: &Schema) -> String {
+    use graphql_parser::schema::{Definition, TypeDefinition};
+
+    let mut document = schema.as_parser_document();
+
+    document.definitions.sort_by_key(|definition| match definition {
+        Definition::SchemaDefinition(_) => (0, ""),
+        Definition::DirectiveDefinition(directive) => (1, directive.name),
+        Definition::TypeDefinition(definition) => (
+            2,
+            match definition {
+                TypeDefinition::Scalar(scalar) => scalar.name,
+                TypeDefinition::Object(object) => object.name,
+                TypeDefinition::Interface(interface) => interface.name,
+                TypeDefinition::Union(union) => union.name,
+                TypeDefinition::Enum(enumeration) => enumeration.name,
+                TypeDefinition::InputObject(input) => input.name,
+            },
+        ),
+        Definition::TypeExtension(_) => (3, ""),
+    });
+
+    document.to_string()
 }
@@ -0,0 +1,873 @@
+use diesel::prelude::*;
+use crate::dates;
+use crate::errors::{AppError, AppResult, FieldViolation};
+use crate::missions::{Domain, MissionType};
+use crate::models::certifications::Certification;
+use crate::models::clients::Client;
+use crate::models::instruments::InstrumentRecord;
+use crate::models::laboratories::{orders_of, AnalysisOrder, STATUS_SENT};
+use crate::models::users::User;
+use crate::models::worksites::{Asbestos, Lead, LeadSession, Worksite, WorksiteContent};
+
+// Largest gap between a reading of the reference standard and its value, in
+// mg/cm², before the measures of a session can't be trusted
+const CALIBRATION_TOLERANCE: f64 = 0.1;
+
+#[derive(Debug, Clone, GraphQLObject)]
+#[graphql(description = "Something to fix or to review in a worksite before issuing its report")]
+pub struct IssueFinding {
+    #[graphql(description = "Path of the value in the worksite, such as `asbestos.0.fcrResult`")]
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "Completeness of the report of a worksite, it can be issued once there are no errors")]
+pub struct IssueCheck {
+    pub worksite_id: i32,
+    pub issuable: bool,
+    #[graphql(description = "Block issuing until fixed")]
+    pub errors: Vec<IssueFinding>,
+    #[graphql(description = "Worth a look, they don't block issuing")]
+    pub warnings: Vec<IssueFinding>,
+}
+
+// Findings of the rules, in the order the rules run
+#[derive(Default)]
+struct Findings {
+    errors: Vec<IssueFinding>,
+    warnings: Vec<IssueFinding>,
+}
+
+impl Findings {
+    fn error(&mut self, field: &str, message: &str) {
+        self.errors.push(IssueFinding { field: field.to_string(), message: message.to_string() });
+    }
+
+    fn warning(&mut self, field: &str, message: &str) {
+        self.warnings.push(IssueFinding { field: field.to_string(), message: message.to_string() });
+    }
+}
+
+// Operator issuing the report, with their certifications
+pub struct Operator {
+    pub user: User,
+    pub certifications: Vec<Certification>,
+}
+
+impl Operator {
+    pub fn load(conn: &PgConnection, user_id: i32) -> AppResult<Operator> {
+        Ok(Operator {
+            user: User::find(conn, user_id)?,
+            certifications: Certification::of_user(conn, user_id)?,
+        })
+    }
+}
+
+impl IssueCheck {
+    // Run every rule over the content of a worksite, its client, its
+    // analysis orders, the instruments of its lead sessions and the operator
+    // issuing its report. Certifications ending within `warning_days` are
+    // reported.
+    pub fn run(
+        worksite_id: i32,
+        client: &Client,
+        content: &WorksiteContent,
+        orders: &[AnalysisOrder],
+        instruments: &[InstrumentRecord],
+        operator: Option<&Operator>,
+        warning_days: i64,
+    ) -> IssueCheck {
+        let mut findings = Findings::default();
+        let mission = content.mission();
+
+        information(&mut findings, content);
+        if let Some(mission) = mission {
+            sections(&mut findings, mission, content);
+            certification(&mut findings, mission, operator, warning_days);
+        }
+        for (index, entry) in content.asbestos.iter().flatten().enumerate() {
+            asbestos(&mut findings, mission, content, index, entry);
+        }
+        analysis_orders(&mut findings, orders);
+        if content.leads.as_ref().is_some_and(|leads| !leads.is_empty()) {
+            lead_sessions(&mut findings, content, instruments);
+        }
+        for (index, lead) in content.leads.iter().flatten().enumerate() {
+            leads(&mut findings, content, index, lead);
+        }
+        client_address(&mut findings, client);
+
+        IssueCheck {
+            worksite_id,
+            issuable: findings.errors.is_empty(),
+            errors: findings.errors,
+            warnings: findings.warnings,
+        }
+    }
+
+    // Check the current content of a worksite, as issued by `issuer`
+    pub fn of_worksite(conn: &PgConnection, worksite_id: i32, issuer: Option<i32>, warning_days: i64) -> AppResult<IssueCheck> {
+        use crate::schema::clients;
+
+        let worksite = Worksite::find(conn, worksite_id)?;
+        let client: Client = clients::table.find(worksite.client_id).first(conn)?;
+        let orders = orders_of(conn, worksite.id)?;
+        let instruments = InstrumentRecord::of_sessions(conn, worksite.worksite.lead_sessions.as_deref().unwrap_or_default())?;
+        let operator = issuer.map(|issuer| Operator::load(conn, issuer)).transpose()?;
+
+        Ok(IssueCheck::run(
+            worksite.id,
+            &client,
+            &worksite.worksite,
+            &orders,
+            &instruments,
+            operator.as_ref(),
+            warning_days,
+        ))
+    }
+
+    // The errors as a validation error, issuing stops on the first call
+    pub fn blocking(self) -> AppResult<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+
+        Err(AppError::Validation(
+            self.errors
+                .iter()
+                .map(|finding| FieldViolation::new(&finding.field, &finding.message))
+                .collect(),
+        ))
+    }
+}
+
+fn information(findings: &mut Findings, content: &WorksiteContent) {
+    let information = match &content.worksite_information {
+        Some(information) => information,
+        None => {
+            findings.error("worksiteInformation", "Is required to issue the report");
+            return;
+        }
+    };
+
+    if information.mission_type.is_none() {
+        findings.error("worksiteInformation.missionType", "Is required to issue the report");
+    }
+    if information.property_address.as_deref().unwrap_or_default().trim().is_empty() {
+        findings.error("worksiteInformation.propertyAddress", "Is required to issue the report");
+    }
+    if information.surface_area.is_none() {
+        findings.warning("worksiteInformation.surfaceArea", "The report doesn't say the surface surveyed");
+    }
+}
+
+// The sections of the mission are filled in, those of other missions empty
+fn sections(findings: &mut Findings, mission: MissionType, content: &WorksiteContent) {
+    let asbestos = content.asbestos.as_ref().map_or(0, Vec::len);
+    let leads = content.leads.as_ref().map_or(0, Vec::len);
+
+    let (required, excluded) = match mission.domain() {
+        Domain::Asbestos => (("asbestos", asbestos), ("leads", leads)),
+        Domain::Lead => (("leads", leads), ("asbestos", asbestos)),
+    };
+
+    if required.1 == 0 {
+        findings.error(required.0, &format!("Required by the mission {}", mission.label()));
+    }
+    if excluded.1 > 0 {
+        findings.error(excluded.0, &format!("Not part of the mission {}", mission.label()));
+    }
+}
+
+// Only an operator certified for the domain of the mission on the day the
+// report is issued may sign it
+fn certification(findings: &mut Findings, mission: MissionType, operator: Option<&Operator>, warning_days: i64) {
+    let domain = mission.domain();
+    let operator = match operator {
+        Some(operator) => operator,
+        None => {
+            findings.error("issuedBy", &format!("The report must be issued by an operator certified for {}", domain.code()));
+            return;
+        }
+    };
+
+    let today = dates::today();
+    let of_domain: Vec<&Certification> = operator
+        .certifications
+        .iter()
+        .filter(|certification| certification.domain == domain.code())
+        .collect();
+
+    // A renewal starting later counts for the warning, not for today
+    let last_day = of_domain.iter().map(|certification| certification.valid_until).max();
+    let current = of_domain.iter().find(|certification| certification.covers(today));
+
+    match (current, last_day) {
+        (Some(_), Some(last_day)) if (last_day - today).num_days() <= warning_days => findings.warning(
+            "issuedBy",
+            &format!(
+                "The {} certification of {} ends on {}",
+                domain.code(),
+                operator.user.name,
+                last_day.format("%d/%m/%Y")
+            ),
+        ),
+        (Some(_), _) => {}
+        (None, Some(last_day)) if last_day < today => findings.error(
+            "issuedBy",
+            &format!(
+                "The {} certification of {} expired on {}",
+                domain.code(),
+                operator.user.name,
+                last_day.format("%d/%m/%Y")
+            ),
+        ),
+        (None, Some(_)) => findings.error(
+            "issuedBy",
+            &format!("The {} certification of {} is not valid yet", domain.code(), operator.user.name),
+        ),
+        (None, None) => findings.error(
+            "issuedBy",
+            &format!("{} holds no {} certification", operator.user.name, domain.code()),
+        ),
+    }
+}
+
+fn asbestos(findings: &mut Findings, mission: Option<MissionType>, content: &WorksiteContent, index: usize, entry: &Asbestos) {
+    let field = |name: &str| format!("asbestos.{}.{}", index, name);
+
+    if entry.fcr_result.trim().is_empty() {
+        findings.error(&field("fcrResult"), &format!("The result of sample {} is pending", entry.sampling));
+    }
+
+    // The same sample can't have two results
+    let other = content.asbestos.iter().flatten().enumerate().find(|(other, sample)| {
+        *other < index && sample.sampling.trim() == entry.sampling.trim() && sample.fcr_result.trim() != entry.fcr_result.trim()
+    });
+    if let Some((other, _)) = other {
+        findings.error(&field("fcrResult"), &format!("Differs from the result of the same sample in entry {}", other));
+    }
+
+    match dates::parse(&entry.date_of_sampling) {
+        Some(day) if day > dates::today() => findings.error(&field("dateOfSampling"), "Must not be in the future"),
+        Some(_) => {}
+        None => findings.error(&field("dateOfSampling"), "Must be a date (DD/MM/YYYY)"),
+    }
+
+    // DTA and before sale surveys assess the conservation state of what
+    // they find, the others may leave it out
+    if entry.conservation_state.trim().is_empty() {
+        match mission {
+            Some(MissionType::Dta) | Some(MissionType::AsbestosBeforeSale) => {
+                findings.error(&field("conservationState"), "Is required by the mission")
+            }
+            _ => findings.warning(&field("conservationState"), "The conservation state is missing"),
+        }
+    }
+
+    // Works and demolition need the quantities to remove
+    let removal = matches!(mission, Some(MissionType::AsbestosBeforeWorks) | Some(MissionType::AsbestosBeforeDemolition));
+    if removal && entry.material_volume.trim().is_empty() {
+        findings.error(&field("materialVolume"), "Is required by the mission");
+    }
+
+    if entry.picture_id <= 0 {
+        findings.warning(&field("pictureId"), "No photo of the surveyed element");
+    }
+}
+
+// Samples sent to a laboratory have no result to print until it answers.
+// Orders are listed latest first, as in `analysisOrders`.
+fn analysis_orders(findings: &mut Findings, orders: &[AnalysisOrder]) {
+    for (index, order) in orders.iter().enumerate() {
+        if order.status == STATUS_SENT {
+            findings.error(
+                &format!("analysisOrders.{}", index),
+                &format!(
+                    "Analysis order {} still awaiting results, expected on {}",
+                    order.id,
+                    order.expected_on.format("%d/%m/%Y")
+                ),
+            );
+        }
+    }
+}
+
+// Lead measures name the XRF instrument of their session, which must be in
+// service with a passed calibration check on the day of the session, and
+// read the reference standard right at the start and the end of the session
+fn lead_sessions(findings: &mut Findings, content: &WorksiteContent, instruments: &[InstrumentRecord]) {
+    let sessions: &[LeadSession] = content.lead_sessions.as_deref().unwrap_or_default();
+
+    if sessions.is_empty() {
+        findings.error("leadSessions", "The XRF instrument and its calibration readings are required");
+    }
+
+    for (index, session) in sessions.iter().enumerate() {
+        let field = |name: &str| format!("leadSessions.{}.{}", index, name);
+        let instrument = instruments
+            .iter()
+            .find(|record| record.instrument.id == session.instrument_id);
+
+        for (name, reading) in [("startReading", session.start_reading), ("endReading", session.end_reading)] {
+            // Readings are typed to the hundredth, the margin absorbs the
+            // rounding of the subtraction
+            if (reading - session.reference_value).abs() > CALIBRATION_TOLERANCE + 1e-9 {
+                findings.error(
+                    &field(name),
+                    &format!(
+                        "Reading {} mg/cm² is more than {} mg/cm² away from the reference value {} mg/cm²",
+                        reading, CALIBRATION_TOLERANCE, session.reference_value
+                    ),
+                );
+            }
+        }
+
+        let day = match dates::parse(&session.date) {
+            Some(day) if day > dates::today() => {
+                findings.error(&field("date"), "Must not be in the future");
+                continue;
+            }
+            Some(day) => day,
+            None => {
+                findings.error(&field("date"), "Must be a date (DD/MM/YYYY)");
+                continue;
+            }
+        };
+
+        match instrument.map(|record| record.usable_on(day)) {
+            Some(Ok(_)) => {}
+            Some(Err(reason)) => findings.error(&field("instrumentId"), &reason),
+            None => findings.error(&field("instrumentId"), &format!("Unknown instrument {}", session.instrument_id)),
+        }
+    }
+}
+
+fn leads(findings: &mut Findings, content: &WorksiteContent, index: usize, lead: &Lead) {
+    let field = |name: &str| format!("leads.{}.{}", index, name);
+    let sessions = content.lead_sessions.as_ref().map_or(0, Vec::len);
+
+    match lead.session {
+        None if sessions > 1 => findings.error(&field("session"), "Is required when there are several sessions"),
+        Some(session) if session < 0 || session as usize >= sessions.max(1) => {
+            findings.error(&field("session"), &format!("No session {} in leadSessions", session))
+        }
+        _ => {}
+    }
+
+    if lead.result.trim().is_empty() {
+        findings.error(&field("result"), "The conclusion of the measure is missing");
+    }
+    if lead.measure_localization.trim().is_empty() {
+        findings.error(&field("measureLocalization"), "Must not be empty");
+    }
+
+    let duplicate = content
+        .leads
+        .iter()
+        .flatten()
+        .enumerate()
+        .find(|(other, measure)| *other < index && measure.number == lead.number);
+    if let Some((other, _)) = duplicate {
+        findings.error(&field("number"), &format!("Already used by entry {}", other));
+    }
+
+    if lead.incertitude > lead.measure {
+        findings.warning(&field("incertitude"), "Larger than the measure itself");
+    }
+}
+
+// The report prints the address of the client
+fn client_address(findings: &mut Findings, client: &Client) {
+    if client.address.postal_code.is_none() {
+        findings.warning("client.address.postalCode", "The client has no postal code");
+    }
+    if client.address.city.is_none() {
+        findings.warning("client.address.city", "The client has no city");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::clients::Address;
+    use crate::models::instruments::{CalibrationCheck, Instrument};
+    use crate::models::laboratories::{STATUS_CANCELLED, STATUS_RECEIVED};
+    use crate::models::worksites::WorksiteInformation;
+    use chrono::{Duration, NaiveDate};
+    use diesel_json::Json;
+
+    // Fields of the errors and of the warnings
+    fn fields(findings: Findings) -> (Vec<String>, Vec<String>) {
+        (
+            findings.errors.into_iter().map(|finding| finding.field).collect(),
+            findings.warnings.into_iter().map(|finding| finding.field).collect(),
+        )
+    }
+
+    fn no_findings() -> (Vec<String>, Vec<String>) {
+        (vec![], vec![])
+    }
+
+    fn day(offset: i64) -> String {
+        (dates::today() + Duration::days(offset)).format("%d/%m/%Y").to_string()
+    }
+
+    fn surveyed(mission: MissionType) -> WorksiteInformation {
+        WorksiteInformation {
+            folder_number: "2026-042".to_string(),
+            property_address: Some("12 rue des Lilas, 75019 Paris".to_string()),
+            surface_area: Some(85.0),
+            mission_type: Some(mission),
+        }
+    }
+
+    fn sample(sampling: &str, fcr_result: &str) -> Asbestos {
+        Asbestos {
+            unit: 1,
+            area: "Séjour".to_string(),
+            equipments: "Plafond".to_string(),
+            localization: "Faux plafond".to_string(),
+            surveyed_element: "Dalles".to_string(),
+            materials_description: "Dalles de faux plafond".to_string(),
+            sampling: sampling.to_string(),
+            date_of_sampling: day(-1),
+            fcr_result: fcr_result.to_string(),
+            conservation_state: "Bon".to_string(),
+            equipment_volume: "20 m²".to_string(),
+            material_volume: "20 m²".to_string(),
+            picture_id: 1,
+        }
+    }
+
+    fn measure(number: i32, session: Option<i32>) -> Lead {
+        Lead {
+            number,
+            localization: "Cuisine".to_string(),
+            area: "Mur A".to_string(),
+            number_ud: 1,
+            diagnostic_unity: "Plinthe".to_string(),
+            substrate: "Bois".to_string(),
+            exposed_coating: "Peinture".to_string(),
+            measure_localization: "Milieu".to_string(),
+            measure: 2,
+            incertitude: 1,
+            result: "Positif".to_string(),
+            session,
+        }
+    }
+
+    fn surveys(asbestos: Vec<Asbestos>, leads: Vec<Lead>) -> WorksiteContent {
+        WorksiteContent {
+            worksite_information: None,
+            leads: Some(leads),
+            asbestos: Some(asbestos),
+            lead_sessions: Some(vec![]),
+        }
+    }
+
+    fn information_findings(worksite_information: Option<WorksiteInformation>) -> (Vec<String>, Vec<String>) {
+        let mut findings = Findings::default();
+        information(&mut findings, &WorksiteContent { worksite_information, ..content(vec![]) });
+
+        fields(findings)
+    }
+
+    #[test]
+    fn information_names_the_mission_and_the_property() {
+        assert_eq!(information_findings(Some(surveyed(MissionType::Dta))), no_findings());
+        assert_eq!(information_findings(None), (vec!["worksiteInformation".to_string()], vec![]));
+
+        let blank = WorksiteInformation {
+            property_address: Some(" ".to_string()),
+            surface_area: None,
+            mission_type: None,
+            ..surveyed(MissionType::Dta)
+        };
+        assert_eq!(
+            information_findings(Some(blank)),
+            (
+                vec!["worksiteInformation.missionType".to_string(), "worksiteInformation.propertyAddress".to_string()],
+                vec!["worksiteInformation.surfaceArea".to_string()],
+            )
+        );
+    }
+
+    fn section_errors(mission: MissionType, content: &WorksiteContent) -> Vec<String> {
+        let mut findings = Findings::default();
+        sections(&mut findings, mission, content);
+
+        fields(findings).0
+    }
+
+    #[test]
+    fn sections_follow_the_domain_of_the_mission() {
+        let asbestos_only = surveys(vec![sample("P1", "Présence")], vec![]);
+        let leads_only = surveys(vec![], vec![measure(1, None)]);
+
+        assert!(section_errors(MissionType::AsbestosBeforeSale, &asbestos_only).is_empty());
+        assert!(section_errors(MissionType::Crep, &leads_only).is_empty());
+        assert_eq!(section_errors(MissionType::AsbestosBeforeSale, &leads_only), ["asbestos", "leads"]);
+        assert_eq!(section_errors(MissionType::Dripp, &asbestos_only), ["leads", "asbestos"]);
+        assert_eq!(section_errors(MissionType::Dta, &surveys(vec![], vec![])), ["asbestos"]);
+    }
+
+    fn asbestos_findings(mission: MissionType, samples: Vec<Asbestos>) -> (Vec<String>, Vec<String>) {
+        let content = surveys(samples, vec![]);
+        let mut findings = Findings::default();
+        for (index, entry) in content.asbestos.iter().flatten().enumerate() {
+            asbestos(&mut findings, Some(mission), &content, index, entry);
+        }
+
+        fields(findings)
+    }
+
+    #[test]
+    fn samples_need_a_result_and_a_date() {
+        assert_eq!(asbestos_findings(MissionType::Dta, vec![sample("P1", "Absence")]), no_findings());
+
+        let mut undated = sample("P2", "Absence");
+        undated.date_of_sampling = "31/02/2026".to_string();
+        let mut future = sample("P3", "Absence");
+        future.date_of_sampling = day(1);
+        assert_eq!(
+            asbestos_findings(MissionType::Dta, vec![sample("P1", " "), undated, future]).0,
+            ["asbestos.0.fcrResult", "asbestos.1.dateOfSampling", "asbestos.2.dateOfSampling"]
+        );
+
+        // The same sample twice agrees on its result
+        let samples = vec![sample("P1", "Présence"), sample("P1 ", "Présence"), sample("P1", "Absence")];
+        assert_eq!(asbestos_findings(MissionType::Dta, samples).0, ["asbestos.2.fcrResult"]);
+    }
+
+    #[test]
+    fn what_a_sample_must_say_depends_on_the_mission() {
+        let mut unassessed = sample("P1", "Présence");
+        unassessed.conservation_state = String::new();
+        unassessed.material_volume = String::new();
+        unassessed.picture_id = 0;
+
+        assert_eq!(
+            asbestos_findings(MissionType::AsbestosBeforeSale, vec![unassessed]),
+            (vec!["asbestos.0.conservationState".to_string()], vec!["asbestos.0.pictureId".to_string()])
+        );
+
+        let mut unassessed = sample("P1", "Présence");
+        unassessed.conservation_state = String::new();
+        unassessed.material_volume = String::new();
+        assert_eq!(
+            asbestos_findings(MissionType::AsbestosBeforeDemolition, vec![unassessed]),
+            (vec!["asbestos.0.materialVolume".to_string()], vec!["asbestos.0.conservationState".to_string()])
+        );
+    }
+
+    fn lead_findings(measures: Vec<Lead>, sessions: usize) -> (Vec<String>, Vec<String>) {
+        let content = WorksiteContent {
+            lead_sessions: Some((0..sessions).map(|_| session(1.0, 1.0, 1.0)).collect()),
+            ..surveys(vec![], measures)
+        };
+        let mut findings = Findings::default();
+        for (index, lead) in content.leads.iter().flatten().enumerate() {
+            leads(&mut findings, &content, index, lead);
+        }
+
+        fields(findings)
+    }
+
+    #[test]
+    fn measures_name_their_session_when_there_are_several() {
+        assert_eq!(lead_findings(vec![measure(1, None), measure(2, Some(0))], 1), no_findings());
+        assert_eq!(lead_findings(vec![measure(1, Some(1)), measure(2, Some(0))], 2), no_findings());
+        assert_eq!(
+            lead_findings(vec![measure(1, None), measure(2, Some(2)), measure(3, Some(-1))], 2).0,
+            ["leads.0.session", "leads.1.session", "leads.2.session"]
+        );
+        assert_eq!(lead_findings(vec![measure(1, Some(1))], 1).0, ["leads.0.session"]);
+    }
+
+    #[test]
+    fn measures_are_numbered_once_and_concluded() {
+        let mut unconcluded = measure(2, None);
+        unconcluded.result = " ".to_string();
+        unconcluded.measure_localization = String::new();
+        let mut uncertain = measure(1, None);
+        uncertain.incertitude = 3;
+
+        assert_eq!(
+            lead_findings(vec![measure(1, None), unconcluded, uncertain], 1),
+            (
+                vec![
+                    "leads.1.result".to_string(),
+                    "leads.1.measureLocalization".to_string(),
+                    "leads.2.number".to_string(),
+                ],
+                vec!["leads.2.incertitude".to_string()],
+            )
+        );
+    }
+
+    fn address_warnings(postal_code: Option<&str>, city: Option<&str>) -> Vec<String> {
+        let now = chrono::offset::Utc::now().naive_utc();
+        let client = Client {
+            id: 1,
+            name: "Foncière des Lilas".to_string(),
+            address: Json::new(Address {
+                street: "rue des Lilas".to_string(),
+                street_number: 12,
+                postal_code: postal_code.map(str::to_string),
+                city: city.map(str::to_string),
+            }),
+            interlocutors: None,
+            created_at: now,
+            edited_at: now,
+        };
+        let mut findings = Findings::default();
+        client_address(&mut findings, &client);
+
+        let (errors, warnings) = fields(findings);
+        assert!(errors.is_empty());
+        warnings
+    }
+
+    // Certification of `user_id` valid between two days counted from today
+    fn certificate(id: i32, user_id: i32, domain: Domain, from: i64, until: i64) -> Certification {
+        let today = dates::today();
+
+        Certification {
+            id,
+            user_id,
+            domain: domain.code().to_string(),
+            body: "Bureau Veritas".to_string(),
+            number: format!("CERT-{}", id),
+            valid_from: today + Duration::days(from),
+            valid_until: today + Duration::days(until),
+            created_by: None,
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        }
+    }
+
+    // What the certification rule finds for an asbestos survey, warning 60
+    // days ahead
+    fn certification_findings(certifications: Vec<Certification>) -> (Vec<String>, Vec<String>) {
+        let operator = Operator {
+            user: User { id: 2, authorization_id: 2, name: "Jean Dupont".to_string(), password: String::new() },
+            certifications,
+        };
+        let mut findings = Findings::default();
+        certification(&mut findings, MissionType::AsbestosBeforeSale, Some(&operator), 60);
+
+        let messages = |list: Vec<IssueFinding>| list.into_iter().map(|finding| finding.message).collect();
+        (messages(findings.errors), messages(findings.warnings))
+    }
+
+    fn last_day(offset: i64) -> String {
+        (dates::today() + Duration::days(offset)).format("%d/%m/%Y").to_string()
+    }
+
+    #[test]
+    fn a_current_certification_passes() {
+        assert_eq!(certification_findings(vec![certificate(1, 2, Domain::Asbestos, -300, 61)]), no_findings());
+    }
+
+    #[test]
+    fn certifications_ending_within_the_warning_days_are_reported() {
+        assert_eq!(
+            certification_findings(vec![certificate(1, 2, Domain::Asbestos, -300, 60)]),
+            (vec![], vec![format!("The asbestos certification of Jean Dupont ends on {}", last_day(60))])
+        );
+        assert_eq!(certification_findings(vec![certificate(1, 2, Domain::Asbestos, -300, 0)]).1.len(), 1);
+    }
+
+    #[test]
+    fn a_renewal_silences_the_warning() {
+        let renewed = vec![
+            certificate(1, 2, Domain::Asbestos, -300, 10),
+            certificate(2, 2, Domain::Asbestos, 11, 1_800),
+        ];
+
+        assert_eq!(certification_findings(renewed), no_findings());
+    }
+
+    #[test]
+    fn expired_certifications_block_issuing() {
+        assert_eq!(
+            certification_findings(vec![certificate(1, 2, Domain::Asbestos, -1_800, -1)]),
+            (vec![format!("The asbestos certification of Jean Dupont expired on {}", last_day(-1))], vec![])
+        );
+    }
+
+    #[test]
+    fn certifications_not_valid_yet_block_issuing() {
+        let not_yet = "The asbestos certification of Jean Dupont is not valid yet".to_string();
+
+        assert_eq!(
+            certification_findings(vec![certificate(1, 2, Domain::Asbestos, 1, 1_800)]),
+            (vec![not_yet.clone()], vec![])
+        );
+        // Expired the day before its renewal starts
+        let between = vec![
+            certificate(1, 2, Domain::Asbestos, -1_800, -1),
+            certificate(2, 2, Domain::Asbestos, 1, 1_800),
+        ];
+        assert_eq!(certification_findings(between), (vec![not_yet], vec![]));
+    }
+
+    #[test]
+    fn the_domain_of_the_mission_must_be_certified() {
+        assert_eq!(
+            certification_findings(vec![certificate(1, 2, Domain::Lead, -300, 300)]),
+            (vec!["Jean Dupont holds no asbestos certification".to_string()], vec![])
+        );
+
+        let mut findings = Findings::default();
+        certification(&mut findings, MissionType::Crep, None, 60);
+        assert_eq!(fields(findings), (vec!["issuedBy".to_string()], vec![]));
+    }
+
+    #[test]
+    fn the_last_certification_of_an_operator_and_domain_is_listed_when_ending() {
+        let limit = dates::today() + Duration::days(60);
+        let all = vec![
+            certificate(1, 2, Domain::Asbestos, -300, 10),
+            // Renewed
+            certificate(2, 2, Domain::Lead, -300, 10),
+            certificate(3, 2, Domain::Lead, 11, 1_800),
+            // Expired, listed first
+            certificate(4, 3, Domain::Asbestos, -1_800, -5),
+            // Ends after the limit
+            certificate(5, 3, Domain::Lead, -300, 61),
+            // Ends on the limit, the first one recorded is kept on a tie
+            certificate(6, 4, Domain::Asbestos, -300, 60),
+            certificate(7, 4, Domain::Asbestos, -200, 60),
+        ];
+
+        let ending = Certification::ending_by(all, limit);
+        assert_eq!(ending.iter().map(|certification| certification.id).collect::<Vec<_>>(), [4, 1, 6]);
+    }
+
+    #[test]
+    fn the_client_address_is_complete() {
+        assert!(address_warnings(Some("75019"), Some("Paris")).is_empty());
+        assert_eq!(address_warnings(None, Some("Paris")), ["client.address.postalCode"]);
+        assert_eq!(address_warnings(None, None), ["client.address.postalCode", "client.address.city"]);
+    }
+
+    fn instrument() -> InstrumentRecord {
+        let today = dates::today();
+        let now = chrono::offset::Utc::now().naive_utc();
+
+        InstrumentRecord {
+            instrument: Instrument {
+                id: 1,
+                manufacturer: "Fondis".to_string(),
+                model: "Protect+".to_string(),
+                serial_number: "P-1234".to_string(),
+                radionuclide: "Cd-109".to_string(),
+                source_date: today - Duration::days(200),
+                retired_on: None,
+                created_by: None,
+                created_at: now,
+            },
+            checks: vec![CalibrationCheck {
+                id: 1,
+                instrument_id: 1,
+                checked_on: today - Duration::days(30),
+                valid_until: today + Duration::days(30),
+                body: "Apave".to_string(),
+                passed: true,
+                certificate_number: None,
+                recorded_by: None,
+                recorded_at: now,
+            }],
+        }
+    }
+
+    fn session(reference_value: f64, start_reading: f64, end_reading: f64) -> LeadSession {
+        LeadSession {
+            instrument_id: 1,
+            date: dates::today().format("%d/%m/%Y").to_string(),
+            reference_value,
+            start_reading,
+            end_reading,
+        }
+    }
+
+    fn content(lead_sessions: Vec<LeadSession>) -> WorksiteContent {
+        WorksiteContent {
+            worksite_information: None,
+            leads: None,
+            asbestos: None,
+            lead_sessions: Some(lead_sessions),
+        }
+    }
+
+    // Fields of the errors of the lead session rules
+    fn session_errors(sessions: Vec<LeadSession>) -> Vec<String> {
+        let mut findings = Findings::default();
+        lead_sessions(&mut findings, &content(sessions), &[instrument()]);
+
+        findings.errors.into_iter().map(|finding| finding.field).collect()
+    }
+
+    #[test]
+    fn readings_within_the_tolerance_pass() {
+        assert!(session_errors(vec![session(1.0, 1.0, 1.0)]).is_empty());
+        assert!(session_errors(vec![session(1.0, 1.1, 0.9)]).is_empty());
+        assert!(session_errors(vec![session(10.2, 10.3, 10.1)]).is_empty());
+    }
+
+    #[test]
+    fn readings_drifting_from_the_reference_block_issuing() {
+        assert_eq!(session_errors(vec![session(1.0, 1.11, 1.0)]), ["leadSessions.0.startReading"]);
+        assert_eq!(session_errors(vec![session(1.0, 1.0, 0.85)]), ["leadSessions.0.endReading"]);
+        assert_eq!(
+            session_errors(vec![session(1.0, 1.0, 1.0), session(1.0, 0.7, 1.3)]),
+            ["leadSessions.1.startReading", "leadSessions.1.endReading"]
+        );
+    }
+
+    #[test]
+    fn sessions_need_a_calibrated_instrument() {
+        let mut unknown = session(1.0, 1.0, 1.0);
+        unknown.instrument_id = 2;
+
+        assert_eq!(session_errors(vec![unknown]), ["leadSessions.0.instrumentId"]);
+        assert_eq!(session_errors(vec![]), ["leadSessions"]);
+    }
+
+    fn order(id: i32, status: &str) -> AnalysisOrder {
+        AnalysisOrder {
+            id,
+            laboratory_id: 1,
+            worksite_id: 1,
+            samples: Json::new(vec!["P1".to_string()]),
+            analysis_type: "molp".to_string(),
+            urgency: "standard".to_string(),
+            sent_on: NaiveDate::from_ymd(2026, 10, 12),
+            expected_on: NaiveDate::from_ymd(2026, 10, 22),
+            received_on: None,
+            status: status.to_string(),
+            created_by: None,
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn orders_awaiting_results_block_issuing() {
+        let mut findings = Findings::default();
+        analysis_orders(&mut findings, &[order(3, STATUS_SENT), order(2, STATUS_CANCELLED), order(1, STATUS_RECEIVED)]);
+
+        assert_eq!(findings.errors.len(), 1);
+        assert_eq!(findings.errors[0].field, "analysisOrders.0");
+        assert_eq!(findings.errors[0].message, "Analysis order 3 still awaiting results, expected on 22/10/2026");
+    }
+
+    #[test]
+    fn answered_orders_pass() {
+        let mut findings = Findings::default();
+        analysis_orders(&mut findings, &[order(2, STATUS_RECEIVED), order(1, STATUS_CANCELLED)]);
+
+        assert!(findings.errors.is_empty());
+    }
+}
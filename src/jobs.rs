@@ -0,0 +1,297 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use std::fs;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use crate::config::Config;
+use crate::database::{self, PostgresPool};
+use crate::errors::{AppError, AppResult};
+use crate::events::{self, WorksiteEvent, EVENT_REPORT_READY};
+use crate::exports;
+use crate::mail::{self, Attachment, Email};
+use crate::models::jobs::{
+    ClientExportPayload, ClientImportPayload, CreditNoteDocumentPayload, InvoiceDocumentPayload, Job,
+    QuoteDocumentPayload, ReportEmailPayload, WorksiteReportPayload, HEARTBEAT_SECONDS, KIND_CLIENT_EXPORT,
+    KIND_CLIENT_IMPORT, KIND_CREDIT_NOTE_DOCUMENT, KIND_INVOICE_DOCUMENT, KIND_PURGE_SESSIONS, KIND_QUOTE_DOCUMENT,
+    KIND_REPORT_EMAIL, KIND_WORKSITE_REPORT, STATUS_DEAD,
+};
+use crate::models::invoices::{CreditNote, Invoice};
+use crate::models::quotes::Quote;
+use crate::models::sessions::Session;
+use crate::models::worksite_versions::WorksiteRevision;
+use crate::models::worksites::Worksite;
+use crate::monitoring;
+use crate::shutdown;
+
+// Workers of the job queue run inside the server process. They poll the jobs
+// table, and are woken up at once when a request queues a job.
+lazy_static! {
+    static ref WAKE: Notify = Notify::new();
+}
+
+// Tell an idle worker there is a new job, call it once the job is committed
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+pub fn start(config: Arc<Config>, pool: PostgresPool) -> Vec<JoinHandle<()>> {
+    if config.jobs.workers == 0 {
+        warn!("No job worker, queued jobs won't run on this instance");
+    }
+
+    (0..config.jobs.workers)
+        .map(|worker| actix_web::rt::spawn(work(worker, config.clone(), pool.clone())))
+        .collect()
+}
+
+// Wait for the workers to finish their job and exit once stopping, at most
+// until `deadline`. Each one holds a clone of the pool.
+pub async fn join(workers: Vec<JoinHandle<()>>, deadline: Instant) {
+    if tokio::time::timeout_at(deadline.into(), futures::future::join_all(workers))
+        .await
+        .is_err()
+    {
+        warn!("Job workers still running at the shutdown deadline, stopping anyway");
+    }
+}
+
+async fn work(worker: usize, config: Arc<Config>, pool: PostgresPool) {
+    let interval = Duration::from_secs(config.jobs.poll_interval);
+
+    while !shutdown::is_stopping() {
+        let job_config = config.clone();
+        let job_pool = pool.clone();
+
+        match database::run(&pool, move |conn| run_next(conn, &job_config, job_pool)).await {
+            // Look for the next one straight away, there may be a backlog
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => error!("Job worker {}: {}", worker, err),
+        }
+
+        // Stopping wakes the worker too, it doesn't wait for the next poll
+        let woken = futures::future::select(Box::pin(WAKE.notified()), Box::pin(shutdown::stopping()));
+        let _ = tokio::time::timeout(interval, woken).await;
+    }
+}
+
+// Claim and run one job, false when none is due
+fn run_next(conn: &PgConnection, config: &Config, pool: PostgresPool) -> AppResult<bool> {
+    let job = match Job::claim(conn)? {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+
+    // Shutdown waits for the job to be finished and recorded
+    let _task = shutdown::track("job");
+    let _heartbeat = Heartbeat::start(pool, job.id);
+
+    info!("Running job {} ({}), attempt {}/{}", job.id, job.kind, job.attempts, job.max_attempts);
+
+    match perform(conn, config, &job) {
+        Ok(outcome) => {
+            job.succeed(conn, &outcome)?;
+            monitoring::JOBS.with_label_values(&[&job.kind, "succeeded"]).inc();
+        }
+        Err(err) => {
+            // Details of internal errors stay in the logs
+            if let AppError::Internal(details) = &err {
+                error!("Job {} ({}): {}", job.id, job.kind, details);
+            }
+
+            let failed = job.fail(conn, &describe(&err), is_permanent(&err))?;
+            let outcome = if failed.status == STATUS_DEAD { "dead" } else { "retried" };
+
+            warn!("Job {} ({}) failed, {}: {}", job.id, job.kind, outcome, err);
+            monitoring::JOBS.with_label_values(&[&job.kind, outcome]).inc();
+        }
+    }
+
+    Ok(true)
+}
+
+// Renews the lease of a running job until dropped, from its own thread and
+// connection since the job holds the worker's
+struct Heartbeat {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    fn start(pool: PostgresPool, job_id: i32) -> Heartbeat {
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(Duration::from_secs(HEARTBEAT_SECONDS)) {
+                let renewed = pool.get().map_err(AppError::from).and_then(|conn| Ok(Job::renew(&conn, job_id)?));
+
+                if let Err(err) = renewed {
+                    warn!("Cannot renew the lease of job {}: {}", job_id, err);
+                }
+            }
+        });
+
+        Heartbeat {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        // Disconnecting the channel ends the thread
+        self.stop.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Errors about the job itself, running it again would fail the same way
+fn is_permanent(err: &AppError) -> bool {
+    matches!(
+        err,
+        AppError::NotFound(_)
+            | AppError::Validation(_)
+            | AppError::Unauthorized
+            | AppError::Forbidden(_)
+            | AppError::Conflict(_)
+    )
+}
+
+// Message kept as `last_error`, validation errors name their fields
+fn describe(err: &AppError) -> String {
+    match err {
+        AppError::Validation(violations) => violations
+            .iter()
+            .map(|violation| format!("{}: {}", violation.field, violation.message))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => err.to_string(),
+    }
+}
+
+fn payload<T: serde::de::DeserializeOwned>(job: &Job) -> AppResult<T> {
+    serde_json::from_value(job.payload.clone())
+        .map_err(|err| AppError::invalid("payload", &format!("Not a {} payload: {}", job.kind, err)))
+}
+
+// Do the work of a job, its result is kept with the job for the clients
+// polling it
+pub fn perform(conn: &PgConnection, config: &Config, job: &Job) -> AppResult<Value> {
+    match job.kind.as_str() {
+        KIND_WORKSITE_REPORT => {
+            let payload: WorksiteReportPayload = payload(job)?;
+            let revision = WorksiteRevision::find(conn, payload.worksite_id, payload.revision)
+                .optional()?
+                .ok_or_else(|| AppError::NotFound("Revision".to_string()))?;
+
+            let revision = crate::reports::write_revision_report(conn, config, &revision)?;
+            let worksite = Worksite::find(conn, revision.worksite_id)?;
+
+            events::publish(WorksiteEvent::new(EVENT_REPORT_READY, &worksite, Some(revision.revision)));
+
+            Ok(json!({
+                "worksite_id": revision.worksite_id,
+                "revision": revision.revision,
+                "report_path": revision.report_path,
+            }))
+        }
+        KIND_CLIENT_EXPORT => {
+            let payload: ClientExportPayload = payload(job)?;
+            let data = exports::collect(conn, Some(payload.client_id))?;
+            let path = config
+                .storage
+                .exports_path
+                .join(format!("client-{}-job{}.json", payload.client_id, job.id));
+
+            exports::write(&data, &path)?;
+
+            Ok(json!({
+                "client_id": payload.client_id,
+                "worksites": data.worksites.len(),
+                "path": path.to_string_lossy(),
+            }))
+        }
+        KIND_PURGE_SESSIONS => Ok(json!({ "deleted": Session::purge_expired(conn)? })),
+        KIND_QUOTE_DOCUMENT => {
+            let payload: QuoteDocumentPayload = payload(job)?;
+            let quote = crate::billing::write_quote(conn, config, &Quote::find(conn, payload.quote_id)?)?;
+
+            Ok(json!({
+                "quote_id": quote.id,
+                "number": quote.number,
+                "document_path": quote.document_path,
+            }))
+        }
+        KIND_INVOICE_DOCUMENT => {
+            let payload: InvoiceDocumentPayload = payload(job)?;
+            let invoice = crate::billing::write_invoice(conn, config, &Invoice::find(conn, payload.invoice_id)?)?;
+
+            Ok(json!({
+                "invoice_id": invoice.id,
+                "number": invoice.number,
+                "document_path": invoice.document_path,
+            }))
+        }
+        KIND_CREDIT_NOTE_DOCUMENT => {
+            let payload: CreditNoteDocumentPayload = payload(job)?;
+            let credit_note = crate::billing::write_credit_note(conn, config, &CreditNote::find(conn, payload.credit_note_id)?)?;
+
+            Ok(json!({
+                "credit_note_id": credit_note.id,
+                "number": credit_note.number,
+                "document_path": credit_note.document_path,
+            }))
+        }
+        KIND_REPORT_EMAIL => {
+            let payload: ReportEmailPayload = payload(job)?;
+            let revision = WorksiteRevision::find(conn, payload.worksite_id, payload.revision)
+                .optional()?
+                .ok_or_else(|| AppError::NotFound("Revision".to_string()))?;
+
+            // Its report job may not have run yet, this one is retried
+            let path = revision.report_path.as_ref().ok_or_else(|| {
+                AppError::Unavailable(format!("the report of Rev {} is not written yet", revision.revision))
+            })?;
+
+            let email = Email {
+                to: payload.to.clone(),
+                subject: format!("Rapport chantier {} - Rev {}", revision.worksite_id, revision.revision),
+                body: format!(
+                    "Bonjour,\n\nVeuillez trouver ci-joint le rapport du chantier {}, Rev {}.\n\nCordialement,\n{}\n",
+                    revision.worksite_id, revision.revision, config.billing.company_name
+                ),
+                attachments: vec![Attachment {
+                    filename: format!("worksite-{}-rev{}.pdf", revision.worksite_id, revision.revision),
+                    content_type: "application/pdf",
+                    content: fs::read(path)?,
+                }],
+            };
+            mail::send(&config.mail, &email)?;
+
+            Ok(json!({
+                "worksite_id": revision.worksite_id,
+                "revision": revision.revision,
+                "to": payload.to,
+            }))
+        }
+        KIND_CLIENT_IMPORT => {
+            let payload: ClientImportPayload = payload(job)?;
+            let clients = exports::read_clients_csv(&payload.csv)?;
+            let client_ids = exports::import_clients(conn, clients, job.created_by)?;
+
+            Ok(json!({ "clients": client_ids.len(), "client_ids": client_ids }))
+        }
+        other => Err(AppError::invalid("kind", &format!("Unknown job kind {}", other))),
+    }
+}
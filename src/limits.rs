@@ -0,0 +1,443 @@
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use juniper::http::{GraphQLRequest, GraphQLResponse};
+use juniper::meta::MetaType;
+use juniper::{
+    DefaultScalarValue, Definition, InputValue, IntoFieldError, Operation, OperationType, SchemaType, Selection, Type,
+};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::config::LimitsConfig;
+use crate::errors::{AppError, AppResult};
+use crate::graphql::Schema;
+use crate::models::users::User;
+
+// Cost of the items of a list field without a `limit` or `first` argument,
+// the size of the pages returned by `fetchAll` and co
+const PAGE_SIZE: u64 = 20;
+
+// Allowances are only dropped from memory past this many clients, an idle
+// client has a full allowance anyway
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Cost {
+    depth: usize,
+    complexity: u64,
+}
+
+// Refuse an operation nesting its selections too deep or too expensive to
+// run. Introspection fields are free so GraphiQL and code generators always
+// work. Documents juniper can't parse or run are let through, it reports
+// them itself.
+pub fn check(config: &LimitsConfig, schema: &Schema, request: &GraphQLRequest) -> AppResult<()> {
+    if config.max_depth == 0 && config.max_complexity == 0 {
+        return Ok(());
+    }
+
+    // The query of a request is private to juniper
+    let request = serde_json::to_value(request).map_err(|err| AppError::Internal(format!("request: {}", err)))?;
+    let query = request["query"].as_str().unwrap_or_default();
+    let operation_name = request["operationName"].as_str();
+
+    let cost = match analyse(&schema.schema, query, operation_name, &request["variables"]) {
+        Some(cost) => cost,
+        None => return Ok(()),
+    };
+
+    if config.max_depth > 0 && cost.depth > config.max_depth {
+        return Err(AppError::QueryTooComplex(format!(
+            "depth {} exceeds the limit of {}",
+            cost.depth, config.max_depth
+        )));
+    }
+    if config.max_complexity > 0 && cost.complexity > config.max_complexity {
+        return Err(AppError::QueryTooComplex(format!(
+            "complexity {} exceeds the limit of {}",
+            cost.complexity, config.max_complexity
+        )));
+    }
+    Ok(())
+}
+
+fn analyse(
+    schema: &SchemaType<DefaultScalarValue>,
+    query: &str,
+    operation_name: Option<&str>,
+    variables: &Value,
+) -> Option<Cost> {
+    let document = juniper::parser::parse_document_source::<DefaultScalarValue>(query, schema).ok()?;
+
+    let mut operations: Vec<&Operation<DefaultScalarValue>> = vec![];
+    let mut fragments = HashMap::new();
+
+    for definition in &document {
+        match definition {
+            Definition::Operation(operation) => operations.push(&operation.item),
+            Definition::Fragment(fragment) => {
+                fragments.insert(
+                    fragment.item.name.item,
+                    (fragment.item.type_condition.item, &fragment.item.selection_set[..]),
+                );
+            }
+        }
+    }
+
+    let operation = match operation_name {
+        Some(name) => operations
+            .into_iter()
+            .find(|operation| operation.name.as_ref().map(|named| named.item) == Some(name))?,
+        None if operations.len() == 1 => operations[0],
+        None => return None,
+    };
+
+    let root = match operation.operation_type {
+        OperationType::Query => Some(schema.concrete_query_type()),
+        OperationType::Mutation => schema.concrete_mutation_type(),
+        OperationType::Subscription => schema.concrete_subscription_type(),
+    };
+
+    let mut analysis = Analysis {
+        schema,
+        variables,
+        fragments,
+        costs: HashMap::new(),
+        visiting: HashSet::new(),
+    };
+
+    Some(analysis.selections(&operation.selection_set, root))
+}
+
+struct Analysis<'a, 's> {
+    schema: &'s SchemaType<'s, DefaultScalarValue>,
+    variables: &'a Value,
+    // Type condition and selections of the fragments, by name
+    fragments: HashMap<&'a str, (&'a str, &'a [Selection<'a, DefaultScalarValue>])>,
+    costs: HashMap<&'a str, Cost>,
+    visiting: HashSet<&'a str>,
+}
+
+impl<'a, 's> Analysis<'a, 's> {
+    // Depth of the deepest selection and sum of the costs of the fields.
+    // A field costs 1 plus its selection, times the size of the page for a
+    // list. Fields unknown to the schema are counted but not weighted.
+    fn selections(
+        &mut self,
+        selections: &'a [Selection<'a, DefaultScalarValue>],
+        parent: Option<&'s MetaType<'s, DefaultScalarValue>>,
+    ) -> Cost {
+        let mut total = Cost::default();
+
+        for selection in selections {
+            let cost = match selection {
+                Selection::Field(field) if field.item.name.item.starts_with("__") => Cost::default(),
+                Selection::Field(field) => {
+                    let field = &field.item;
+                    let meta = parent.and_then(|parent| parent.field_by_name(field.name.item));
+                    let child = meta.and_then(|meta| self.schema.concrete_type_by_name(meta.field_type.innermost_name()));
+
+                    let inner = match &field.selection_set {
+                        Some(selections) => self.selections(selections, child),
+                        None => Cost::default(),
+                    };
+
+                    let items = match meta.map(|meta| &meta.field_type) {
+                        Some(Type::List(_)) | Some(Type::NonNullList(_)) => field
+                            .arguments
+                            .as_ref()
+                            .and_then(|arguments| {
+                                arguments
+                                    .item
+                                    .items
+                                    .iter()
+                                    .find(|(name, _)| name.item == "limit" || name.item == "first")
+                                    .and_then(|(_, value)| self.integer(&value.item))
+                            })
+                            .unwrap_or(PAGE_SIZE),
+                        _ => 1,
+                    };
+
+                    Cost {
+                        depth: inner.depth + 1,
+                        complexity: items.saturating_mul(inner.complexity.saturating_add(1)),
+                    }
+                }
+                Selection::FragmentSpread(spread) => self.fragment(spread.item.name.item),
+                Selection::InlineFragment(fragment) => {
+                    let on = fragment
+                        .item
+                        .type_condition
+                        .as_ref()
+                        .and_then(|condition| self.schema.concrete_type_by_name(condition.item))
+                        .or(parent);
+
+                    self.selections(&fragment.item.selection_set, on)
+                }
+            };
+
+            total.depth = total.depth.max(cost.depth);
+            total.complexity = total.complexity.saturating_add(cost.complexity);
+        }
+
+        total
+    }
+
+    // A fragment costs the same wherever it is spread, it is only walked
+    // once. Cycles are left to juniper's validation.
+    fn fragment(&mut self, name: &'a str) -> Cost {
+        if let Some(cost) = self.costs.get(name) {
+            return *cost;
+        }
+        let (on, selections) = match self.fragments.get(name) {
+            Some(fragment) => *fragment,
+            None => return Cost::default(),
+        };
+        if !self.visiting.insert(name) {
+            return Cost::default();
+        }
+
+        let cost = self.selections(selections, self.schema.concrete_type_by_name(on));
+
+        self.visiting.remove(name);
+        self.costs.insert(name, cost);
+        cost
+    }
+
+    // Value of a page size argument, literal or variable
+    fn integer(&self, value: &InputValue<DefaultScalarValue>) -> Option<u64> {
+        match value {
+            InputValue::Variable(name) => self.variables.get(name)?.as_u64(),
+            value => value.as_int_value().map(|size| size.max(0) as u64),
+        }
+    }
+}
+
+struct Allowance {
+    operations: f64,
+    updated: Instant,
+}
+
+lazy_static! {
+    static ref ALLOWANCES: Mutex<HashMap<String, Allowance>> = Mutex::new(HashMap::new());
+}
+
+// Who a request is counted against: its user, or its address when not
+// authenticated. Behind a reverse proxy every anonymous client shares the
+// address of the proxy.
+pub fn client_key(user: Option<&User>, address: Option<IpAddr>) -> String {
+    match (user, address) {
+        (Some(user), _) => format!("user:{}", user.id),
+        (None, Some(address)) => format!("ip:{}", address),
+        (None, None) => "ip:unknown".to_string(),
+    }
+}
+
+// Take `operations` from the allowance of a client. An allowance refills
+// continuously up to `per_minute` operations, so a burst of a minute worth
+// of operations is let through.
+pub fn throttle(per_minute: u32, client: &str, operations: usize) -> AppResult<()> {
+    if per_minute == 0 {
+        return Ok(());
+    }
+
+    let capacity = f64::from(per_minute);
+    let per_second = capacity / 60.0;
+    let now = Instant::now();
+
+    let mut allowances = ALLOWANCES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if allowances.len() >= MAX_CLIENTS {
+        allowances.retain(|_, allowance| now.duration_since(allowance.updated) < Duration::from_secs(60));
+    }
+
+    let allowance = allowances.entry(client.to_string()).or_insert(Allowance {
+        operations: capacity,
+        updated: now,
+    });
+
+    allowance.operations =
+        (allowance.operations + now.duration_since(allowance.updated).as_secs_f64() * per_second).min(capacity);
+    allowance.updated = now;
+
+    // A batch larger than the whole allowance takes all of it
+    let operations = (operations as f64).min(capacity);
+
+    if allowance.operations >= operations {
+        allowance.operations -= operations;
+        Ok(())
+    } else {
+        let retry_after = ((operations - allowance.operations) / per_second).ceil() as u64;
+        Err(AppError::RateLimited(retry_after.max(1)))
+    }
+}
+
+// Answer a request refused before running any of its operations, with a
+// GraphQL error for each of them
+pub fn refusal(err: AppError, batch: Option<usize>) -> HttpResponse {
+    let mut response = match &err {
+        AppError::RateLimited(retry_after) => {
+            let mut response = HttpResponse::TooManyRequests();
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+            response
+        }
+        AppError::PayloadTooLarge(_) => HttpResponse::PayloadTooLarge(),
+        _ => HttpResponse::BadRequest(),
+    };
+
+    let error = serde_json::to_value(GraphQLResponse::<DefaultScalarValue>::error(err.into_field_error()))
+        .unwrap_or_default();
+
+    match batch {
+        Some(operations) => response.json(vec![error; operations]),
+        None => response.json(error),
+    }
+}
+
+// Bodies over `max_body_size` get a GraphQL error too
+pub fn body_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let limit = match &err {
+        JsonPayloadError::OverflowKnownLength { limit, .. } | JsonPayloadError::Overflow { limit } => *limit,
+        _ => return err.into(),
+    };
+
+    InternalError::from_response(err, refusal(AppError::PayloadTooLarge(limit), None)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::create_schema;
+
+    fn cost(query: &str, operation_name: Option<&str>) -> Option<(usize, u64)> {
+        let schema = create_schema();
+
+        analyse(&schema.schema, query, operation_name, &Value::Null).map(|cost| (cost.depth, cost.complexity))
+    }
+
+    fn limits(max_depth: usize, max_complexity: u64) -> LimitsConfig {
+        LimitsConfig {
+            max_depth,
+            max_complexity,
+            ..LimitsConfig::default()
+        }
+    }
+
+    const NESTED_LISTS: &str = "{ clients { fetchAll(offset: 0) { id worksites { id } } } }";
+
+    #[test]
+    fn fields_cost_one_and_nest() {
+        assert_eq!(cost("{ worksite(worksiteId: 1) { id clientId } }", None), Some((2, 3)));
+    }
+
+    #[test]
+    fn lists_multiply_their_selection_by_the_page_size() {
+        // worksites: 20 × (1 + 1), fetchAll: 20 × (1 + 1 + 40), clients: 1 + 840
+        assert_eq!(cost(NESTED_LISTS, None), Some((4, 841)));
+    }
+
+    #[test]
+    fn fragments_cost_their_selection_where_spread() {
+        let query = "
+            { worksite(worksiteId: 1) { ...ids client { worksites { ...ids } } } }
+            fragment ids on Worksite { id clientId }
+        ";
+
+        // worksites: 20 × (2 + 1), client: 1 + 60, worksite: 1 + 2 + 61
+        assert_eq!(cost(query, None), Some((4, 64)));
+        assert_eq!(
+            cost("{ worksite(worksiteId: 1) { ... on Worksite { id clientId } } }", None),
+            Some((2, 3))
+        );
+    }
+
+    #[test]
+    fn introspection_is_free() {
+        assert_eq!(cost("{ __schema { types { name fields { name } } } }", None), Some((0, 0)));
+    }
+
+    #[test]
+    fn unknown_fields_are_counted_but_not_weighted() {
+        assert_eq!(cost("{ unknown { id } }", None), Some((2, 2)));
+    }
+
+    #[test]
+    fn documents_juniper_refuses_are_not_analysed() {
+        assert_eq!(cost("{ worksite(", None), None);
+        assert_eq!(cost("query A { users { me { id } } } query B { users { me { id } } }", None), None);
+        assert_eq!(cost("query A { worksite(worksiteId: 1) { id } } query B { __typename }", Some("C")), None);
+    }
+
+    #[test]
+    fn the_named_operation_is_analysed() {
+        let query = "query A { worksite(worksiteId: 1) { id } } query B { __typename }";
+
+        assert_eq!(cost(query, Some("A")), Some((2, 2)));
+        assert_eq!(cost(query, Some("B")), Some((0, 0)));
+    }
+
+    #[test]
+    fn operations_over_the_limits_are_refused() {
+        let schema = create_schema();
+        let request = GraphQLRequest::new(NESTED_LISTS.to_string(), None, None);
+
+        assert!(check(&limits(4, 841), &schema, &request).is_ok());
+        assert!(check(&limits(0, 0), &schema, &request).is_ok());
+        assert!(matches!(
+            check(&limits(3, 0), &schema, &request),
+            Err(AppError::QueryTooComplex(reason)) if reason == "depth 4 exceeds the limit of 3"
+        ));
+        assert!(matches!(
+            check(&limits(0, 840), &schema, &request),
+            Err(AppError::QueryTooComplex(reason)) if reason == "complexity 841 exceeds the limit of 840"
+        ));
+    }
+
+    // Each test throttles its own client, the allowances are shared
+    #[test]
+    fn a_minute_worth_of_operations_is_let_through() {
+        for _ in 0..3 {
+            assert!(throttle(3, "test:burst", 1).is_ok());
+        }
+
+        // One operation refills in 60 / 3 seconds
+        assert!(matches!(throttle(3, "test:burst", 1), Err(AppError::RateLimited(20))));
+        assert!(throttle(3, "test:other", 1).is_ok());
+    }
+
+    #[test]
+    fn allowances_refill_over_time() {
+        // 100 operations a second
+        assert!(throttle(6000, "test:refill", 6000).is_ok());
+        assert!(matches!(throttle(6000, "test:refill", 1), Err(AppError::RateLimited(1))));
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert!(throttle(6000, "test:refill", 2).is_ok());
+    }
+
+    #[test]
+    fn batches_larger_than_the_allowance_take_all_of_it() {
+        assert!(throttle(2, "test:batch", 5).is_ok());
+        assert!(matches!(throttle(2, "test:batch", 1), Err(AppError::RateLimited(30))));
+    }
+
+    #[test]
+    fn no_limit_never_throttles() {
+        for _ in 0..100 {
+            assert!(throttle(0, "test:unlimited", 10).is_ok());
+        }
+    }
+
+    #[test]
+    fn clients_are_their_user_or_their_address() {
+        let address = "192.0.2.1".parse().ok();
+
+        assert_eq!(client_key(None, address), "ip:192.0.2.1");
+        assert_eq!(client_key(None, None), "ip:unknown");
+    }
+}
@@ -0,0 +1,491 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::QueryResult;
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+use crate::database::{run, PostgresPool};
+use crate::errors::AppResult;
+use crate::models::clients::Client;
+use crate::models::invoices::Invoice;
+use crate::models::laboratories::AnalysisOrder;
+use crate::models::users::{Authorization, User};
+use crate::models::worksites::Worksite;
+
+// Fetch every row for a batch of keys in a single query
+type BatchFn<V> = fn(&PgConnection, &[i32]) -> QueryResult<HashMap<i32, V>>;
+
+struct State<V> {
+    cache: HashMap<i32, Option<Arc<V>>>,
+    queued: Vec<i32>,
+    loading: HashSet<i32>,
+}
+
+// Request scoped loader. Resolvers of sibling objects (every user of a list,
+// every client of a page...) run concurrently: each one queues its key,
+// yields once, and the first to resume fetches the whole queue in a single
+// query while the others wait for the result.
+pub struct Loader<V> {
+    name: &'static str,
+    pool: PostgresPool,
+    fetch: BatchFn<V>,
+    state: Mutex<State<V>>,
+    notify: Notify,
+    // Queries run by the loader, one per dispatched batch
+    queries: AtomicUsize,
+}
+
+impl<V: Send + Sync + 'static> Loader<V> {
+    pub fn new(name: &'static str, pool: &PostgresPool, fetch: BatchFn<V>) -> Loader<V> {
+        Loader {
+            name,
+            pool: pool.clone(),
+            fetch,
+            state: Mutex::new(State {
+                cache: HashMap::new(),
+                queued: Vec::new(),
+                loading: HashSet::new(),
+            }),
+            notify: Notify::new(),
+            queries: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State<V>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub async fn load(&self, key: i32) -> AppResult<Option<Arc<V>>> {
+        {
+            let mut state = self.state();
+
+            if let Some(value) = state.cache.get(&key) {
+                return Ok(value.clone());
+            }
+            if !state.loading.contains(&key) && !state.queued.contains(&key) {
+                state.queued.push(key);
+            }
+        }
+
+        // Let the sibling resolvers queue their keys before dispatching
+        tokio::task::yield_now().await;
+
+        loop {
+            let notified = self.notify.notified();
+
+            let batch = {
+                let mut state = self.state();
+
+                if let Some(value) = state.cache.get(&key) {
+                    return Ok(value.clone());
+                }
+
+                if state.loading.contains(&key) {
+                    None
+                } else {
+                    let mut batch = std::mem::take(&mut state.queued);
+                    if !batch.contains(&key) {
+                        batch.push(key);
+                    }
+                    state.loading.extend(batch.iter().copied());
+                    Some(batch)
+                }
+            };
+
+            match batch {
+                Some(batch) => self.dispatch(batch).await?,
+                None => notified.await,
+            }
+        }
+    }
+
+    async fn dispatch(&self, batch: Vec<i32>) -> AppResult<()> {
+        debug!("Loading {} {} in one query", batch.len(), self.name);
+        self.queries.fetch_add(1, Ordering::SeqCst);
+
+        let dispatching = Dispatching { loader: self, batch };
+        let fetch = self.fetch;
+        let keys = dispatching.batch.clone();
+        let result = run(&self.pool, move |conn| Ok(fetch(conn, &keys)?)).await;
+
+        // Keys without a row are cached as missing so they aren't fetched
+        // again by this request
+        result.map(|mut found| {
+            let mut state = self.state();
+
+            for key in &dispatching.batch {
+                let value = found.remove(key).map(Arc::new);
+                state.cache.insert(*key, value);
+            }
+        })
+    }
+}
+
+// Keys of a batch being fetched. They are released when the dispatch ends,
+// or when its future is dropped before the query returned, so the resolvers
+// waiting on them fetch them again instead of waiting forever.
+struct Dispatching<'a, V> {
+    loader: &'a Loader<V>,
+    batch: Vec<i32>,
+}
+
+impl<V> Drop for Dispatching<'_, V> {
+    fn drop(&mut self) {
+        {
+            let mut state = self.loader.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            for key in &self.batch {
+                state.loading.remove(key);
+            }
+        }
+
+        self.loader.notify.notify_waiters();
+    }
+}
+
+// Every loader available to resolvers, created with the request context
+pub struct Loaders {
+    pub analysis_orders_by_worksite: Loader<Vec<Arc<AnalysisOrder>>>,
+    pub authorizations: Loader<Authorization>,
+    pub clients: Loader<Client>,
+    pub invoices_by_client: Loader<Vec<Arc<Invoice>>>,
+    pub invoices_by_worksite: Loader<Vec<Arc<Invoice>>>,
+    pub users: Loader<User>,
+    pub worksites: Loader<Worksite>,
+    pub worksites_by_client: Loader<Vec<Arc<Worksite>>>,
+}
+
+impl Loaders {
+    pub fn new(pool: &PostgresPool) -> Loaders {
+        Loaders {
+            analysis_orders_by_worksite: Loader::new(
+                "analysis orders by worksite",
+                pool,
+                analysis_orders_by_worksite_id,
+            ),
+            authorizations: Loader::new("authorizations", pool, authorizations_by_id),
+            clients: Loader::new("clients", pool, clients_by_id),
+            invoices_by_client: Loader::new("invoices by client", pool, invoices_by_client_id),
+            invoices_by_worksite: Loader::new("invoices by worksite", pool, invoices_by_worksite_id),
+            users: Loader::new("users", pool, users_by_id),
+            worksites: Loader::new("worksites", pool, worksites_by_id),
+            worksites_by_client: Loader::new("worksites by client", pool, worksites_by_client_id),
+        }
+    }
+}
+
+// Analysis orders of each worksite, latest first
+fn analysis_orders_by_worksite_id(
+    conn: &PgConnection,
+    keys: &[i32],
+) -> QueryResult<HashMap<i32, Vec<Arc<AnalysisOrder>>>> {
+    use crate::schema::analysis_orders::dsl::*;
+
+    let mut grouped: HashMap<i32, Vec<Arc<AnalysisOrder>>> = keys.iter().map(|key| (*key, Vec::new())).collect();
+
+    for row in analysis_orders
+        .filter(worksite_id.eq_any(keys))
+        .order(id.desc())
+        .load::<AnalysisOrder>(conn)?
+    {
+        grouped.entry(row.worksite_id).or_default().push(Arc::new(row));
+    }
+
+    Ok(grouped)
+}
+
+fn authorizations_by_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Authorization>> {
+    use crate::schema::authorizations::dsl::*;
+
+    Ok(authorizations
+        .filter(id.eq_any(keys))
+        .load::<Authorization>(conn)?
+        .into_iter()
+        .map(|row| (row.id, row))
+        .collect())
+}
+
+fn clients_by_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Client>> {
+    use crate::schema::clients::dsl::*;
+
+    Ok(clients
+        .filter(id.eq_any(keys))
+        .load::<Client>(conn)?
+        .into_iter()
+        .map(|row| (row.id, row))
+        .collect())
+}
+
+fn users_by_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, User>> {
+    use crate::schema::users::dsl::*;
+
+    Ok(users
+        .filter(id.eq_any(keys))
+        .load::<User>(conn)?
+        .into_iter()
+        .map(|row| (row.id, row))
+        .collect())
+}
+
+fn worksites_by_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Worksite>> {
+    use crate::schema::worksites::dsl::*;
+
+    Ok(worksites
+        .filter(id.eq_any(keys))
+        .load::<Worksite>(conn)?
+        .into_iter()
+        .map(|row| (row.id, row))
+        .collect())
+}
+
+fn worksites_by_client_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Vec<Arc<Worksite>>>> {
+    use crate::schema::worksites::dsl::*;
+
+    let mut grouped: HashMap<i32, Vec<Arc<Worksite>>> = keys.iter().map(|key| (*key, Vec::new())).collect();
+
+    for row in worksites
+        .filter(client_id.eq_any(keys))
+        .filter(deleted_at.is_null())
+        .order(id.asc())
+        .load::<Worksite>(conn)?
+    {
+        grouped.entry(row.client_id).or_default().push(Arc::new(row));
+    }
+
+    Ok(grouped)
+}
+
+// Invoices of each client, latest first
+fn invoices_by_client_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Vec<Arc<Invoice>>>> {
+    use crate::schema::invoices::dsl::*;
+
+    let mut grouped: HashMap<i32, Vec<Arc<Invoice>>> = keys.iter().map(|key| (*key, Vec::new())).collect();
+
+    for row in invoices
+        .filter(client_id.eq_any(keys))
+        .order(id.desc())
+        .load::<Invoice>(conn)?
+    {
+        grouped.entry(row.client_id).or_default().push(Arc::new(row));
+    }
+
+    Ok(grouped)
+}
+
+// Invoices of each worksite, latest first
+fn invoices_by_worksite_id(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Vec<Arc<Invoice>>>> {
+    use crate::schema::invoices::dsl::*;
+
+    let mut grouped: HashMap<i32, Vec<Arc<Invoice>>> = keys.iter().map(|key| (*key, Vec::new())).collect();
+
+    for row in invoices
+        .filter(worksite_id.eq_any(keys))
+        .order(id.desc())
+        .load::<Invoice>(conn)?
+    {
+        grouped.entry(row.worksite_id).or_default().push(Arc::new(row));
+    }
+
+    Ok(grouped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use std::time::Duration;
+    use crate::config::{Config, DatabaseConfig};
+    use crate::context::GraphQLContext;
+    use crate::database::get_pool;
+    use crate::graphql::create_schema;
+    use crate::models::clients::{AddressInput, ClientInput};
+    use crate::models::worksites::WorksiteContent;
+
+    // The loaders query the database of DATABASE_URL, these tests only run
+    // with `cargo test -- --ignored`
+    fn pool() -> PostgresPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+
+        get_pool(&DatabaseConfig {
+            url,
+            ..DatabaseConfig::default()
+        })
+    }
+
+    static WORKSITE_QUERIES: AtomicUsize = AtomicUsize::new(0);
+    static CLIENT_QUERIES: AtomicUsize = AtomicUsize::new(0);
+    static CLIENT_WORKSITE_QUERIES: AtomicUsize = AtomicUsize::new(0);
+
+    fn counted_worksites(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Worksite>> {
+        WORKSITE_QUERIES.fetch_add(1, Ordering::SeqCst);
+        worksites_by_id(conn, keys)
+    }
+
+    fn counted_clients(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Client>> {
+        CLIENT_QUERIES.fetch_add(1, Ordering::SeqCst);
+        clients_by_id(conn, keys)
+    }
+
+    fn counted_client_worksites(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, Vec<Arc<Worksite>>>> {
+        CLIENT_WORKSITE_QUERIES.fetch_add(1, Ordering::SeqCst);
+        worksites_by_client_id(conn, keys)
+    }
+
+    fn slow_users(conn: &PgConnection, keys: &[i32]) -> QueryResult<HashMap<i32, User>> {
+        std::thread::sleep(Duration::from_millis(300));
+        users_by_id(conn, keys)
+    }
+
+    fn queries() -> [usize; 3] {
+        [
+            WORKSITE_QUERIES.load(Ordering::SeqCst),
+            CLIENT_QUERIES.load(Ordering::SeqCst),
+            CLIENT_WORKSITE_QUERIES.load(Ordering::SeqCst),
+        ]
+    }
+
+    // What `{ worksites { client { worksites } } }` does: every worksite of
+    // the list resolves its client, then the worksites of that client
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn nested_loads_query_once_per_level() {
+        let pool = pool();
+
+        let worksites = Loader::new("worksites", &pool, counted_worksites);
+        let clients = Loader::new("clients", &pool, counted_clients);
+        let client_worksites = Loader::new("worksites by client", &pool, counted_client_worksites);
+
+        let resolve = |worksite_id: i32| {
+            let (worksites, clients, client_worksites) = (&worksites, &clients, &client_worksites);
+
+            async move {
+                let worksite = worksites.load(worksite_id).await?;
+                let client_id = worksite.map_or(worksite_id, |worksite| worksite.client_id);
+                let client = clients.load(client_id).await?;
+                client_worksites.load(client.map_or(client_id, |client| client.id)).await
+            }
+        };
+
+        for result in join_all((1..=25).map(resolve)).await {
+            result.expect("load failed");
+        }
+        assert_eq!(queries(), [1, 1, 1]);
+
+        // Everything is cached for the rest of the request
+        for result in join_all((1..=25).map(resolve)).await {
+            result.expect("load failed");
+        }
+        assert_eq!(queries(), [1, 1, 1]);
+    }
+
+    // Clients with two worksites each, created by the first run and reused
+    // afterwards since worksite versions cannot be deleted
+    fn test_clients(conn: &PgConnection, count: usize) -> AppResult<Vec<i32>> {
+        use crate::schema::clients;
+
+        (0..count)
+            .map(|index| {
+                let name = format!("Loader test {}", index);
+                let existing = clients::table
+                    .filter(clients::name.eq(&name))
+                    .select(clients::id)
+                    .first::<i32>(conn)
+                    .optional()?;
+                if let Some(id) = existing {
+                    return Ok(id);
+                }
+
+                let input = ClientInput {
+                    name,
+                    address: Some(AddressInput {
+                        street: "rue des Lilas".to_string(),
+                        street_number: 12,
+                        postal_code: Some("75019".to_string()),
+                        city: Some("Paris".to_string()),
+                    }),
+                    interlocutors: None,
+                };
+                let client = Client::create(conn, input, None)?;
+
+                for _ in 0..2 {
+                    let content = WorksiteContent {
+                        worksite_information: None,
+                        leads: None,
+                        asbestos: None,
+                        lead_sessions: None,
+                    };
+                    Worksite::create(conn, client.id, content, None)?;
+                }
+                Ok(client.id)
+            })
+            .collect()
+    }
+
+    // Sibling clients resolve their worksites, then the invoices of every
+    // worksite, with a query per level whatever the number of rows
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn nested_graphql_fields_query_once_per_level() {
+        let pool = pool();
+        let ids = run(&pool, |conn| test_clients(conn, 3)).await.expect("cannot create the clients");
+
+        let context = GraphQLContext {
+            config: Arc::new(Config::default()),
+            pool: pool.clone(),
+            session_token: None,
+            user: Some(User {
+                id: 0,
+                authorization_id: 0,
+                name: "loader test".to_string(),
+                password: String::new(),
+            }),
+            loaders: Loaders::new(&pool),
+        };
+        let fields: Vec<String> = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| {
+                format!("c{}: clients {{ fetch(clientId: {}) {{ worksites {{ id invoices {{ id }} }} }} }}", index, id)
+            })
+            .collect();
+        let query = format!("{{ {} }}", fields.join(" "));
+        let schema = create_schema();
+
+        let (data, errors) = juniper::execute(&query, None, &schema, &juniper::Variables::new(), &context)
+            .await
+            .expect("the query is invalid");
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        let data = serde_json::to_value(&data).unwrap();
+        for index in 0..3 {
+            assert_eq!(data[format!("c{}", index)]["fetch"]["worksites"].as_array().map(Vec::len), Some(2));
+        }
+        assert_eq!(context.loaders.clients.queries(), 1);
+        assert_eq!(context.loaders.worksites_by_client.queries(), 1);
+        assert_eq!(context.loaders.invoices_by_worksite.queries(), 1);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn a_cancelled_dispatch_releases_its_keys() {
+        let pool = pool();
+
+        let users = Loader::new("users", &pool, slow_users);
+
+        // Dropped while its query runs
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), users.load(1)).await;
+        assert!(cancelled.is_err());
+
+        tokio::time::timeout(Duration::from_secs(5), users.load(1))
+            .await
+            .expect("the key of the cancelled dispatch is never loaded")
+            .expect("load failed");
+    }
+}
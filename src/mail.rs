@@ -0,0 +1,159 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use crate::config::MailConfig;
+use crate::errors::{AppError, AppResult};
+
+// Parts are base64 encoded, whose alphabet has no `_`, so this never shows
+// up in them
+const BOUNDARY: &str = "=_amiantes_part";
+
+pub struct Attachment {
+    // ASCII only, it is written as is in the headers
+    pub filename: String,
+    pub content_type: &'static str,
+    pub content: Vec<u8>,
+}
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub attachments: Vec<Attachment>,
+}
+
+// Hand an email to the sendmail command, which delivers or queues it. The
+// recipients are read from the headers.
+pub fn send(config: &MailConfig, email: &Email) -> AppResult<()> {
+    let from = config
+        .from
+        .as_deref()
+        .ok_or_else(|| AppError::Unavailable("emails are not configured, set MAIL_FROM".to_string()))?;
+
+    if email.to.contains(['\r', '\n']) || email.subject.contains(['\r', '\n']) {
+        return Err(AppError::invalid("to", "Must be a single line"));
+    }
+
+    let message = format_message(from, email, &chrono::offset::Utc::now().to_rfc2822());
+
+    let mut child = Command::new(&config.sendmail)
+        .args(["-t", "-i"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| AppError::Internal(format!("cannot run {}: {}", config.sendmail, err)))?;
+
+    // Written from another thread, sendmail may fill its output pipes
+    // before it has read the whole message
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| AppError::Internal(format!("no stdin for {}", config.sendmail)))?;
+    let writer = thread::spawn(move || stdin.write_all(message.as_bytes()));
+
+    let output = child.wait_with_output()?;
+    let written = writer
+        .join()
+        .map_err(|_| AppError::Internal(format!("writing to {} panicked", config.sendmail)))?;
+
+    if !output.status.success() {
+        return Err(AppError::Internal(format!(
+            "{} failed: {}",
+            config.sendmail,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    written?;
+
+    Ok(())
+}
+
+fn format_message(from: &str, email: &Email, date: &str) -> String {
+    let mut message = format!(
+        "From: {}\nTo: {}\nSubject: {}\nDate: {}\nMIME-Version: 1.0\nContent-Type: multipart/mixed; boundary=\"{}\"\n\n",
+        from,
+        email.to,
+        encode_header(&email.subject),
+        date,
+        BOUNDARY
+    );
+
+    message.push_str(&format!(
+        "--{}\nContent-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: base64\n\n{}",
+        BOUNDARY,
+        encode_body(email.body.as_bytes())
+    ));
+
+    for attachment in &email.attachments {
+        message.push_str(&format!(
+            "--{}\nContent-Type: {}; name=\"{}\"\nContent-Disposition: attachment; filename=\"{}\"\nContent-Transfer-Encoding: base64\n\n{}",
+            BOUNDARY,
+            attachment.content_type,
+            attachment.filename,
+            attachment.filename,
+            encode_body(&attachment.content)
+        ));
+    }
+
+    message.push_str(&format!("--{}--\n", BOUNDARY));
+    message
+}
+
+// Headers are ASCII, anything else is written as an RFC 2047 encoded word
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(value))
+    }
+}
+
+// Base64 in lines of 76 characters
+fn encode_body(content: &[u8]) -> String {
+    let encoded = base64::encode(content);
+    let mut lines = String::with_capacity(encoded.len() + encoded.len() / 76 + 1);
+
+    for line in encoded.as_bytes().chunks(76) {
+        lines.push_str(std::str::from_utf8(line).unwrap_or_default());
+        lines.push('\n');
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_mime_multiparts() {
+        let email = Email {
+            to: "client@example.fr".to_string(),
+            subject: "Rapport de repérage".to_string(),
+            body: "Bonjour".to_string(),
+            attachments: vec![Attachment {
+                filename: "rapport.pdf".to_string(),
+                content_type: "application/pdf",
+                content: vec![0; 100],
+            }],
+        };
+
+        let message = format_message("Amiantes <rapports@example.fr>", &email, "Mon, 19 Oct 2026 10:00:00 +0000");
+        let (headers, body) = message.split_once("\n\n").unwrap();
+
+        assert!(headers.contains("To: client@example.fr\n"));
+        assert!(headers.contains(&format!("Subject: =?UTF-8?B?{}?=\n", base64::encode("Rapport de repérage"))));
+        assert!(body.contains(&format!("--{}\nContent-Type: text/plain; charset=utf-8", BOUNDARY)));
+        assert!(body.contains(&format!("\n\n{}\n", base64::encode("Bonjour"))));
+        assert!(body.contains("Content-Disposition: attachment; filename=\"rapport.pdf\""));
+        assert!(body.ends_with(&format!("--{}--\n", BOUNDARY)));
+    }
+
+    #[test]
+    fn base64_lines_are_wrapped() {
+        let encoded = encode_body(&[0xff; 200]);
+
+        assert!(encoded.lines().all(|line| line.len() <= 76));
+        assert_eq!(base64::decode(encoded.replace('\n', "")).unwrap(), vec![0xff; 200]);
+    }
+}
@@ -1,7 +1,9 @@
 // #![deny(unused_imports, dead_code, unused_variables)]
 
 use std::env;
+use std::process;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[macro_use]
 extern crate serde;
@@ -18,73 +20,361 @@ use actix_web::{
 };
 
 use dotenv::dotenv;
-use juniper_actix::{graphiql_handler, graphql_handler};
-use log::info;
+use juniper::http::{GraphQLBatchResponse, GraphQLRequest, GraphQLResponse};
+use juniper::IntoFieldError;
+use juniper_actix::graphiql_handler;
+use log::{error, info};
 
+mod billing;
+mod cli;
+mod config;
 mod context;
 mod database;
+mod dates;
+mod diff;
+mod errors;
+mod events;
+mod exports;
 mod graphql;
+mod issue_checks;
+mod jobs;
+mod limits;
+mod loaders;
+mod mail;
+mod migrations;
+mod missions;
 mod models;
+mod monitoring;
+mod pdf;
+mod persisted_queries;
+mod pricing;
+mod reports;
 mod schema;
+mod shutdown;
+mod signing;
+mod subscriptions;
+mod validation;
 
+use crate::cli::Command;
+use crate::config::{Config, ServerConfig};
 use crate::context::GraphQLContext;
-use crate::database::get_pool;
+use crate::database::{get_pool, PostgresPool};
 use crate::graphql::{create_schema, Schema};
+use crate::loaders::Loaders;
+use crate::errors::{AppError, AppResult};
+use crate::models::sessions::Session;
+use crate::models::users::User;
+use crate::persisted_queries::{BatchRequest, PersistedQueries};
 
 async fn graphiql() -> Result<HttpResponse, Error> {
     graphiql_handler("/graphql", None).await
 }
 
+// The GraphQL schema language of the API, for code generators and editors
+async fn schema_language(schema: web::Data<Arc<Schema>>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(graphql::schema_language(&schema))
+}
+
+// Extract the session token of an `Authorization: Bearer <token>` header
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+// Execute one operation, counted and timed under its operation name
+async fn execute<'a>(
+    request: &'a GraphQLRequest,
+    schema: &'a Schema,
+    ctx: &'a GraphQLContext,
+) -> GraphQLResponse<'a> {
+    let operation = monitoring::operation_label(request.operation_name());
+
+    monitoring::GRAPHQL_REQUESTS.with_label_values(&[operation]).inc();
+    let timer = monitoring::GRAPHQL_DURATION.with_label_values(&[operation]).start_timer();
+
+    let response = request.execute(schema, ctx).await;
+    timer.observe_duration();
+
+    // Resolver errors are counted with their own code, this is a request
+    // juniper refused to run (syntax, unknown field, bad variables...)
+    if !response.is_ok() {
+        monitoring::GRAPHQL_ERRORS.with_label_values(&["BAD_REQUEST"]).inc();
+    }
+
+    response
+}
+
+// Execute one operation of a request, unless its persisted query couldn't
+// be resolved or it is too deep or too expensive
+async fn execute_within_limits<'a>(
+    request: &'a AppResult<GraphQLRequest>,
+    schema: &'a Schema,
+    ctx: &'a GraphQLContext,
+) -> GraphQLResponse<'a> {
+    let allowed = request.as_ref().map_err(AppError::clone).and_then(|request| {
+        limits::check(&ctx.config.limits, schema, request)?;
+        Ok(request)
+    });
+
+    match allowed {
+        Ok(request) => execute(request, schema, ctx).await,
+        Err(err) => GraphQLResponse::error(err.into_field_error()),
+    }
+}
+
 async fn graphql(
     req: HttpRequest,
-    ctx: web::Data<Arc<GraphQLContext>>,
-    payload: web::Payload,
+    config: web::Data<Arc<Config>>,
+    pool: web::Data<PostgresPool>,
+    request: web::Json<BatchRequest>,
     schema: web::Data<Arc<Schema>>,
+    persisted: web::Data<Arc<PersistedQueries>>,
 ) -> Result<HttpResponse, Error> {
-    graphql_handler(&schema, &ctx, req, payload).await
+    let session_token = bearer_token(&req);
+
+    let user = match session_token.clone() {
+        Some(token) => {
+            let pool = pool.get_ref().clone();
+
+            let found: AppResult<Option<User>> = web::block(move || {
+                let conn = pool.get()?;
+                Ok(Session::user_for_token(&conn, &token)?)
+            })
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            found.map_err(|err| match err {
+                AppError::Unavailable(_) => actix_web::error::ErrorServiceUnavailable(err),
+                _ => actix_web::error::ErrorInternalServerError(err),
+            })?
+        }
+        None => None,
+    };
+
+    let client = limits::client_key(user.as_ref(), req.peer_addr().map(|address| address.ip()));
+    let (requests, batch): (Vec<AppResult<GraphQLRequest>>, _) = match request.into_inner() {
+        BatchRequest::Single(single) => (vec![persisted.resolve(single)], None),
+        BatchRequest::Batch(batch) => {
+            let operations = batch.len();
+            (batch.into_iter().map(|single| persisted.resolve(single)).collect(), Some(operations))
+        }
+    };
+
+    if let Err(err) = limits::throttle(config.limits.rate_limit, &client, batch.unwrap_or(1)) {
+        return Ok(limits::refusal(err, batch));
+    }
+
+    let ctx = GraphQLContext {
+        config: config.get_ref().clone(),
+        pool: pool.get_ref().clone(),
+        session_token,
+        user,
+        loaders: Loaders::new(pool.get_ref()),
+    };
+
+    let mut responses =
+        futures::future::join_all(requests.iter().map(|single| execute_within_limits(single, &schema, &ctx))).await;
+
+    let response = match batch {
+        None => GraphQLBatchResponse::Single(responses.remove(0)),
+        Some(_) => GraphQLBatchResponse::Batch(responses),
+    };
+
+    let body = serde_json::to_string(&response)?;
+
+    Ok(if response.is_ok() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::BadRequest()
+    }
+    .content_type("application/json")
+    .body(body))
+}
+
+// `*` in the allowed origins lets any site call the API, which is what
+// development uses
+fn cors(config: &ServerConfig) -> Cors {
+    let cors = if config.cors_origins.iter().any(|origin| origin == "*") {
+        Cors::default().allow_any_origin()
+    } else {
+        config
+            .cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+    };
+
+    cors.allowed_methods(vec!["POST", "GET"])
+        .allowed_headers(vec![
+            header::AUTHORIZATION,
+            header::ACCEPT,
+            header::CONTENT_TYPE,
+        ])
+        .supports_credentials()
+        .max_age(3600)
 }
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
     dotenv().ok();
 
-    env::set_var("RUST_LOG", "debug,actix_web=debug");
-    let server_address: String = env::var("SERVER").expect("No database url set");
-    env_logger::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    // Works on the code alone, without configuration nor database
+    if let Command::GraphQLSchema { file, check } = &command {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+        cli::exit_on_error(cli::graphql_schema(file.as_deref(), *check));
+        return Ok(());
+    }
+
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    env_logger::Builder::new()
+        .parse_filters(&config.log.level)
+        .init();
 
     // Create the auto managed database pool
-    let context = Arc::new(GraphQLContext { pool: get_pool().clone() });
+    let pool = get_pool(&config.database);
+
+    match command {
+        Command::Serve => serve(config, pool).await,
+        command => {
+            cli::exit_on_error(cli::run(command, &config, &pool));
+            Ok(())
+        }
+    }
+}
+
+// Refuse to serve a database whose schema is behind the binary, unless
+// pending migrations are applied on startup
+fn ensure_migrated(config: &Config, pool: &PostgresPool) -> AppResult<()> {
+    if config.database.migrate_on_startup {
+        return cli::migrate(pool);
+    }
+
+    let pending = migrations::pending(&*pool.get()?)?;
+
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Internal(format!(
+            "the database schema is behind, pending migrations: {}. Run `migrate` or set DATABASE_MIGRATE_ON_STARTUP=true",
+            pending.join(", ")
+        )))
+    }
+}
+
+async fn serve(config: Arc<Config>, pool: PostgresPool) -> Result<(), std::io::Error> {
+    cli::exit_on_error(ensure_migrated(&config, &pool));
+
     let schema = Arc::new(create_schema());
+    let persisted = Arc::new(cli::exit_on_error(PersistedQueries::load(&config.graphql)));
+    let address = (config.server.host.clone(), config.server.port);
+    let timeout = Duration::from_secs(config.server.shutdown_timeout);
+    let app_pool = pool.clone();
+    let jobs_config = config.clone();
 
-    info!("Started backend server: {}:5050", server_address);
+    info!("Started backend server: {}:{}", address.0, address.1);
 
-    HttpServer::new(move || {
-        App::new()
+    let server = HttpServer::new(move || {
+        let app = App::new()
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
             .app_data(web::Data::new(schema.clone()))
-            .app_data(web::Data::new(context.clone()))
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allowed_methods(vec!["POST", "GET"])
-                    .allowed_headers(vec![
-                        header::AUTHORIZATION,
-                        header::ACCEPT,
-                        header::CONTENT_TYPE,
-                    ])
-                    .supports_credentials()
-                    .max_age(3600),
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(app_pool.clone()))
+            .app_data(web::Data::new(persisted.clone()))
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(config.limits.max_body_size)
+                    .error_handler(limits::body_error),
             )
+            .wrap(cors(&config.server))
             .service(
                 web::resource("/graphql")
                     .guard(guard::Header("content-type", "application/json"))
                     .route(web::post().to(graphql)),
             )
-            .service(web::resource("/graphiql").route(web::get().to(graphiql)))
+            .service(web::resource("/subscriptions").route(web::get().to(subscriptions::subscriptions)))
+            .service(
+                web::resource("/reports/verify")
+                    .app_data(web::PayloadConfig::new(signing::MAX_REPORT_SIZE))
+                    .route(web::post().to(signing::verify)),
+            )
+            .service(web::resource("/health").route(web::get().to(monitoring::health)))
+            .service(web::resource("/ready").route(web::get().to(monitoring::ready)))
+            .service(web::resource("/metrics").route(web::get().to(monitoring::metrics)));
+
+        // Development only, production runs the operations of the frontend
+        if config.graphql.graphiql {
+            app.service(web::resource("/graphiql").route(web::get().to(graphiql)))
+                .service(web::resource("/schema.graphql").route(web::get().to(schema_language)))
+        } else {
+            app
+        }
     })
-    .bind((server_address, 5050))
-    .unwrap()
-    .run()
-    .await
+    // In-flight requests get this long to finish once stopping
+    .shutdown_timeout(timeout.as_secs())
+    .disable_signals()
+    .bind(address.clone())
+    .map_err(|err| {
+        error!("Cannot listen on {}:{}: {}", address.0, address.1, err);
+        err
+    })?
+    .run();
+
+    // Stop accepting connections on SIGINT/SIGTERM and let the workers finish
+    // their requests
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown::signal().await;
+        shutdown::stop();
+        handle.stop(true).await;
+    });
+
+    let workers = jobs::start(jobs_config, pool.clone());
+
+    server.await?;
+
+    // Everything left shares what remains of the timeout given to the server
+    shutdown::stop();
+    let deadline = shutdown::deadline(timeout);
+
+    // Jobs being run, and reports still being written by requests that timed
+    // out
+    shutdown::drain(deadline).await;
+
+    // The workers hold clones of the pool, it is only closed once they are
+    // gone
+    jobs::join(workers, deadline).await;
+
+    info!("Closing the database pool");
+    drop(pool);
+
+    info!("Server stopped");
+    log::logger().flush();
+
+    Ok(())
 }
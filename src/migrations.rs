@@ -0,0 +1,236 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Text};
+use diesel_migrations::{setup_database, MigrationConnection};
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::errors::AppResult;
+
+// The migrations of `migrations/`, compiled into the binary so the server
+// needs neither the diesel CLI nor the sql files to run. This is what
+// `embed_migrations!` expands to, with the list of migrations kept
+// reachable so the pending ones are told without running them.
+#[allow(dead_code)]
+mod embedded_migrations {
+    use diesel_migrations::EmbedMigrations;
+
+    #[derive(EmbedMigrations)]
+    #[embed_migrations_options(migrations_path = "migrations")]
+    struct _Dummy;
+
+    pub fn all() -> &'static [&'static dyn Migration] {
+        ALL_MIGRATIONS
+    }
+}
+
+// Apply every migration the database hasn't run yet, returns their versions
+pub fn run(conn: &PgConnection) -> AppResult<Vec<&'static str>> {
+    let applied = pending(conn)?;
+
+    embedded_migrations::run(conn)?;
+
+    Ok(applied)
+}
+
+// Versions of the embedded migrations the database hasn't run yet
+pub fn pending(conn: &PgConnection) -> AppResult<Vec<&'static str>> {
+    setup_database(conn)?;
+    let already_run = conn.previously_run_migration_versions()?;
+
+    Ok(embedded_migrations::all()
+        .iter()
+        .map(|migration| migration.version())
+        .filter(|version| !already_run.contains(*version))
+        .collect())
+}
+
+// Type of a column as Postgres names it, `text` for every character type
+// since diesel reads them all as `Text`
+#[derive(Debug, PartialEq, Eq)]
+struct ColumnType {
+    name: String,
+    nullable: bool,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.nullable {
+            write!(f, "{} NULL", self.name)
+        } else {
+            write!(f, "{} NOT NULL", self.name)
+        }
+    }
+}
+
+// Postgres name of a diesel SQL type, unknown types are kept as they are
+// and show up as differences
+fn postgres_type(diesel_type: &str) -> &str {
+    match diesel_type {
+        "Bool" => "bool",
+        "Int2" | "SmallInt" => "int2",
+        "Int4" | "Integer" => "int4",
+        "Int8" | "BigInt" => "int8",
+        "Float4" | "Float" => "float4",
+        "Float8" | "Double" => "float8",
+        "Numeric" => "numeric",
+        "Text" | "Varchar" => "text",
+        "Date" => "date",
+        "Time" => "time",
+        "Timestamp" => "timestamp",
+        "Timestamptz" => "timestamptz",
+        "Json" => "json",
+        "Jsonb" => "jsonb",
+        "Bytea" | "Binary" => "bytea",
+        "Uuid" => "uuid",
+        other => other,
+    }
+}
+
+fn database_type(udt_name: &str) -> &str {
+    match udt_name {
+        "varchar" | "bpchar" => "text",
+        other => other,
+    }
+}
+
+// Columns of every table declared in `src/schema.rs`, read from its
+// `table!` declarations:
+//
+//     users (id) {
+//         id -> Int4,
+//         email -> Nullable<Varchar>,
+//     }
+fn declared_columns() -> BTreeMap<String, BTreeMap<String, ColumnType>> {
+    let mut tables: BTreeMap<String, BTreeMap<String, ColumnType>> = BTreeMap::new();
+    let mut table: Option<String> = None;
+
+    for line in include_str!("schema.rs").lines().map(str::trim) {
+        if line == "}" {
+            table = None;
+        } else if line.ends_with('{') && !line.starts_with("table!") {
+            table = line.split_whitespace().next().map(str::to_string);
+        } else if let (Some(table), Some((column, sql_type))) = (&table, line.split_once("->")) {
+            let sql_type = sql_type.trim().trim_end_matches(',');
+            let (sql_type, nullable) = match sql_type.strip_prefix("Nullable<").and_then(|inner| inner.strip_suffix('>')) {
+                Some(inner) => (inner, true),
+                None => (sql_type, false),
+            };
+
+            tables.entry(table.clone()).or_default().insert(
+                column.trim().to_string(),
+                ColumnType {
+                    name: postgres_type(sql_type).to_string(),
+                    nullable,
+                },
+            );
+        }
+    }
+
+    tables
+}
+
+#[derive(QueryableByName)]
+struct DatabaseColumn {
+    #[sql_type = "Text"]
+    table_name: String,
+    #[sql_type = "Text"]
+    column_name: String,
+    #[sql_type = "Text"]
+    udt_name: String,
+    #[sql_type = "Bool"]
+    nullable: bool,
+}
+
+// Compare `src/schema.rs` with the tables of the migrated database, returns
+// every difference found (missing tables, missing or extra columns, columns
+// of another type or nullability)
+pub fn schema_differences(conn: &PgConnection) -> AppResult<Vec<String>> {
+    let rows: Vec<DatabaseColumn> = diesel::sql_query(
+        "SELECT table_name::text AS table_name, column_name::text AS column_name, \
+         udt_name::text AS udt_name, is_nullable = 'YES' AS nullable \
+         FROM information_schema.columns \
+         WHERE table_schema = current_schema() AND table_name <> '__diesel_schema_migrations'",
+    )
+    .load(conn)?;
+
+    let mut database: BTreeMap<String, BTreeMap<String, ColumnType>> = BTreeMap::new();
+    for row in rows {
+        database.entry(row.table_name).or_default().insert(
+            row.column_name,
+            ColumnType {
+                name: database_type(&row.udt_name).to_string(),
+                nullable: row.nullable,
+            },
+        );
+    }
+
+    let declared = declared_columns();
+    let mut differences = Vec::new();
+
+    for (table, columns) in &declared {
+        let existing = match database.get(table) {
+            Some(existing) => existing,
+            None => {
+                differences.push(format!("table {} is declared in schema.rs but not in the database", table));
+                continue;
+            }
+        };
+
+        for (column, declared_type) in columns {
+            match existing.get(column) {
+                None => differences.push(format!("column {}.{} is missing from the database", table, column)),
+                Some(existing_type) if existing_type != declared_type => differences.push(format!(
+                    "column {}.{} is {} in schema.rs but {} in the database",
+                    table, column, declared_type, existing_type
+                )),
+                Some(_) => {}
+            }
+        }
+        for column in existing.keys().filter(|column| !columns.contains_key(*column)) {
+            differences.push(format!("column {}.{} is missing from schema.rs", table, column));
+        }
+    }
+
+    for table in database.keys().filter(|table| !declared.contains_key(*table)) {
+        differences.push(format!("table {} is missing from schema.rs", table));
+    }
+
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_declarations_are_read_with_their_types() {
+        let declared = declared_columns();
+        let users = &declared["users"];
+
+        assert_eq!(users["id"], ColumnType { name: "int4".to_string(), nullable: false });
+        assert!(declared["worksites"]["deleted_at"].nullable);
+        // `joinable!` lines are not taken for columns
+        assert!(declared.values().all(|columns| columns.keys().all(|column| !column.contains('('))));
+    }
+
+    // Migrates the database of DATABASE_URL in a transaction rolled back at
+    // the end, only run with `cargo test -- --ignored`
+    #[test]
+    #[ignore = "needs DATABASE_URL"]
+    fn schema_matches_the_migrated_database() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let conn = PgConnection::establish(&url).expect("cannot connect to DATABASE_URL");
+
+        conn.test_transaction::<_, crate::errors::AppError, _>(|| {
+            run(&conn)?;
+
+            let differences = schema_differences(&conn)?;
+            assert!(
+                differences.is_empty(),
+                "src/schema.rs differs from the migrations:\n{}",
+                differences.join("\n")
+            );
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,86 @@
+// Regulatory missions a client can order. The code is what the database
+// stores, the label what documents print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissionType {
+    #[graphql(description = "Dossier technique amiante")]
+    Dta,
+    #[graphql(description = "Repérage amiante avant vente")]
+    AsbestosBeforeSale,
+    #[graphql(description = "Repérage amiante avant travaux")]
+    AsbestosBeforeWorks,
+    #[graphql(description = "Repérage amiante avant démolition")]
+    AsbestosBeforeDemolition,
+    #[graphql(description = "Constat de risque d'exposition au plomb")]
+    Crep,
+    #[graphql(description = "Diagnostic du risque d'intoxication par le plomb des peintures")]
+    Dripp,
+}
+
+// What a mission looks for, it decides which sections a worksite has and
+// which certification the operator issuing the report needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Domain {
+    Asbestos,
+    Lead,
+}
+
+pub const DOMAINS: &[Domain] = &[Domain::Asbestos, Domain::Lead];
+
+pub const MISSION_TYPES: &[MissionType] = &[
+    MissionType::Dta,
+    MissionType::AsbestosBeforeSale,
+    MissionType::AsbestosBeforeWorks,
+    MissionType::AsbestosBeforeDemolition,
+    MissionType::Crep,
+    MissionType::Dripp,
+];
+
+impl MissionType {
+    pub fn code(self) -> &'static str {
+        match self {
+            MissionType::Dta => "dta",
+            MissionType::AsbestosBeforeSale => "asbestos_before_sale",
+            MissionType::AsbestosBeforeWorks => "asbestos_before_works",
+            MissionType::AsbestosBeforeDemolition => "asbestos_before_demolition",
+            MissionType::Crep => "crep",
+            MissionType::Dripp => "dripp",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<MissionType> {
+        MISSION_TYPES.iter().copied().find(|mission| mission.code() == code)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MissionType::Dta => "Dossier technique amiante",
+            MissionType::AsbestosBeforeSale => "Repérage amiante avant vente",
+            MissionType::AsbestosBeforeWorks => "Repérage amiante avant travaux",
+            MissionType::AsbestosBeforeDemolition => "Repérage amiante avant démolition",
+            MissionType::Crep => "Constat de risque d'exposition au plomb",
+            MissionType::Dripp => "Diagnostic du risque d'intoxication par le plomb",
+        }
+    }
+
+    pub fn domain(self) -> Domain {
+        match self {
+            MissionType::Crep | MissionType::Dripp => Domain::Lead,
+            _ => Domain::Asbestos,
+        }
+    }
+}
+
+impl Domain {
+    pub fn code(self) -> &'static str {
+        match self {
+            Domain::Asbestos => "asbestos",
+            Domain::Lead => "lead",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Domain> {
+        DOMAINS.iter().copied().find(|domain| domain.code() == code)
+    }
+}
@@ -0,0 +1,182 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::{DateRange, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::models::users::User;
+use crate::schema::audit_logs;
+use crate::validation::validate_argument;
+
+// Entities tracked by the audit trail
+pub const ENTITY_USER: &str = "user";
+pub const ENTITY_CLIENT: &str = "client";
+pub const ENTITY_WORKSITE: &str = "worksite";
+pub const ENTITY_QUOTE: &str = "quote";
+pub const ENTITY_INVOICE: &str = "invoice";
+pub const ENTITY_CREDIT_NOTE: &str = "credit_note";
+pub const ENTITY_CERTIFICATION: &str = "certification";
+pub const ENTITY_INSTRUMENT: &str = "instrument";
+pub const ENTITY_LABORATORY: &str = "laboratory";
+pub const ENTITY_ANALYSIS_ORDER: &str = "analysis_order";
+
+// Operations recorded against an entity
+pub const OPERATION_CREATE: &str = "create";
+pub const OPERATION_UPDATE: &str = "update";
+pub const OPERATION_ISSUE: &str = "issue";
+pub const OPERATION_PAYMENT: &str = "payment";
+pub const OPERATION_CALIBRATION: &str = "calibration";
+pub const OPERATION_PASSWORD_RESET: &str = "password_reset";
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct AuditLog {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub entity: String,
+    pub entity_id: i32,
+    pub operation: String,
+    pub diff: Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Immutable record of a change made to an entity")]
+impl AuditLog {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[graphql(description = "User who made the change, null for changes made outside a session")]
+    fn actor_id(&self) -> Option<i32> {
+        self.actor_id
+    }
+
+    async fn actor(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.actor_id {
+            Some(actor) => context.loaders.users.load(actor).await,
+            None => Ok(None),
+        }
+    }
+
+    fn entity(&self) -> &str {
+        self.entity.as_str()
+    }
+
+    fn entity_id(&self) -> i32 {
+        self.entity_id
+    }
+
+    fn operation(&self) -> &str {
+        self.operation.as_str()
+    }
+
+    #[graphql(description = "JSON object of changed fields: { \"<path>\": { \"before\": .., \"after\": .. } }")]
+    fn diff(&self) -> String {
+        self.diff.to_string()
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_logs"]
+pub struct NewAuditLog<'a> {
+    pub actor_id: Option<i32>,
+    pub entity: &'a str,
+    pub entity_id: &'a i32,
+    pub operation: &'a str,
+    pub diff: &'a Value,
+    pub created_at: &'a NaiveDateTime,
+}
+
+impl AuditLog {
+    // Append an entry to the audit trail. Call it inside the same transaction
+    // as the change itself so a mutation is never stored without its trace.
+    pub fn record<T: Serialize>(
+        conn: &PgConnection,
+        actor: Option<i32>,
+        entity: &str,
+        entity_id: i32,
+        operation: &str,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) -> AppResult<AuditLog> {
+        // An entry that can't say what changed is refused, never stored empty
+        let to_value = |value: Option<&T>| -> AppResult<Value> {
+            match value {
+                Some(value) => serde_json::to_value(value).map_err(|err| {
+                    AppError::Internal(format!("cannot serialize the {} {} for the audit trail: {}", entity, entity_id, err))
+                }),
+                None => Ok(Value::Null),
+            }
+        };
+
+        let changes = crate::diff::diff(&to_value(before)?, &to_value(after)?);
+
+        let new_log: NewAuditLog = NewAuditLog {
+            actor_id: actor,
+            entity,
+            entity_id: &entity_id,
+            operation,
+            diff: &changes,
+            created_at: &chrono::offset::Utc::now().naive_utc(),
+        };
+
+        Ok(diesel::insert_into(audit_logs::table)
+            .values(new_log)
+            .get_result(conn)?)
+    }
+
+    // Whether the trail of an entity names `actor`
+    pub fn acted_on(conn: &PgConnection, actor: i32, entity: &str, entity_id: i32) -> QueryResult<bool> {
+        diesel::select(diesel::dsl::exists(
+            audit_logs::table
+                .filter(audit_logs::actor_id.eq(actor))
+                .filter(audit_logs::entity.eq(entity))
+                .filter(audit_logs::entity_id.eq(entity_id)),
+        ))
+        .get_result(conn)
+    }
+}
+
+pub struct AuditLogQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl AuditLogQuery {
+    #[graphql(description = "Fetch the audit trail of an entity, optionally in a range of days, administrators only")]
+    async fn fetch(
+        context: &GraphQLContext,
+        entity: String,
+        entity_id: i32,
+        created_between: Option<DateRange>,
+    ) -> AppResult<Vec<AuditLog>> {
+        context.require_administrator().await?;
+        validate_argument("createdBetween", &created_between)?;
+
+        context
+            .run(move |conn| {
+                let mut query = audit_logs::table
+                    .filter(audit_logs::entity.eq(entity))
+                    .filter(audit_logs::entity_id.eq(entity_id))
+                    .into_boxed();
+
+                if let Some(range) = created_between {
+                    let (from, to) = range.bounds();
+
+                    if let Some(from) = from {
+                        query = query.filter(audit_logs::created_at.ge(from));
+                    }
+                    if let Some(to) = to {
+                        query = query.filter(audit_logs::created_at.lt(to));
+                    }
+                }
+
+                Ok(query.order(audit_logs::id.asc()).load::<AuditLog>(conn)?)
+            })
+            .await
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::{self, Date, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::missions::Domain;
+use crate::models::audit_logs::{AuditLog, ENTITY_CERTIFICATION, OPERATION_CREATE, OPERATION_UPDATE};
+use crate::models::users::User;
+use crate::schema::certifications;
+use crate::validation::{validate, Validate, Validator};
+
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+pub struct Certification {
+    pub id: i32,
+    pub user_id: i32,
+    pub domain: String,
+    pub body: String,
+    pub number: String,
+    pub valid_from: NaiveDate,
+    pub valid_until: NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Certification of an operator, needed to issue the reports of the missions of its domain")]
+impl Certification {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    async fn user(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        context.loaders.users.load(self.user_id).await
+    }
+
+    fn domain(&self) -> AppResult<Domain> {
+        self.domain_of()
+    }
+
+    #[graphql(description = "Accredited body which certified the operator")]
+    fn body(&self) -> &str {
+        self.body.as_str()
+    }
+
+    fn number(&self) -> &str {
+        self.number.as_str()
+    }
+
+    fn valid_from(&self) -> Date {
+        Date(self.valid_from)
+    }
+
+    fn valid_until(&self) -> Date {
+        Date(self.valid_until)
+    }
+
+    #[graphql(description = "Valid today")]
+    fn valid(&self) -> bool {
+        self.covers(dates::today())
+    }
+
+    #[graphql(description = "Days until its last day, negative once expired")]
+    fn days_left(&self) -> i32 {
+        self.days_until_end(dates::today()) as i32
+    }
+
+    #[graphql(description = "Valid today but ends within `CERTIFICATION_WARNING_DAYS`")]
+    fn expiring(&self, context: &GraphQLContext) -> bool {
+        let today = dates::today();
+
+        self.covers(today) && self.days_until_end(today) <= context.config.certifications.warning_days
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "certifications"]
+pub struct NewCertification<'a> {
+    pub user_id: &'a i32,
+    pub domain: &'a str,
+    pub body: &'a str,
+    pub number: &'a str,
+    pub valid_from: &'a NaiveDate,
+    pub valid_until: &'a NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, GraphQLInputObject)]
+#[graphql(description = "Certification of an operator. A renewed certification is created again with its new dates.")]
+pub struct CertificationInput {
+    pub user_id: i32,
+    pub domain: Domain,
+    pub body: String,
+    pub number: String,
+    pub valid_from: Date,
+    pub valid_until: Date,
+}
+
+impl Validate for CertificationInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("userId", self.user_id, 1, i32::MAX)
+            .not_blank("body", &self.body)
+            .length("body", &self.body, 1, 255)
+            .not_blank("number", &self.number)
+            .length("number", &self.number, 1, 64)
+            .ordered("validUntil", &Some(self.valid_from.0), &Some(self.valid_until.0));
+    }
+}
+
+impl Certification {
+    pub fn domain_of(&self) -> AppResult<Domain> {
+        Domain::from_code(&self.domain)
+            .ok_or_else(|| AppError::Internal(format!("certification {}: unknown domain {}", self.id, self.domain)))
+    }
+
+    pub fn covers(&self, day: NaiveDate) -> bool {
+        self.valid_from <= day && day <= self.valid_until
+    }
+
+    pub fn days_until_end(&self, today: NaiveDate) -> i64 {
+        (self.valid_until - today).num_days()
+    }
+
+    pub fn find(conn: &PgConnection, certification_id: i32) -> AppResult<Certification> {
+        certifications::table
+            .find(certification_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Certification".to_string()))
+    }
+
+    // Certifications of an operator, the latest ending first
+    pub fn of_user(conn: &PgConnection, user_id: i32) -> QueryResult<Vec<Certification>> {
+        certifications::table
+            .filter(certifications::user_id.eq(user_id))
+            .order((certifications::valid_until.desc(), certifications::id.desc()))
+            .load(conn)
+    }
+
+    // The certification of each operator and domain which ends last, when
+    // it ends within `warning_days` or has already ended. An operator
+    // holding a renewal is left out.
+    pub fn expiring(conn: &PgConnection, warning_days: i64) -> QueryResult<Vec<Certification>> {
+        let all = certifications::table.order(certifications::id.asc()).load::<Certification>(conn)?;
+
+        Ok(Certification::ending_by(all, dates::today() + chrono::Duration::days(warning_days)))
+    }
+
+    // Of `all`, the certifications ending last for their operator and
+    // domain, when they end by `limit`, the first to end first
+    pub fn ending_by(all: Vec<Certification>, limit: NaiveDate) -> Vec<Certification> {
+        let mut latest: BTreeMap<(i32, String), Certification> = BTreeMap::new();
+
+        for certification in all {
+            let key = (certification.user_id, certification.domain.clone());
+
+            match latest.get(&key) {
+                Some(known) if known.valid_until >= certification.valid_until => {}
+                _ => {
+                    latest.insert(key, certification);
+                }
+            }
+        }
+
+        let mut expiring: Vec<Certification> = latest
+            .into_values()
+            .filter(|certification| certification.valid_until <= limit)
+            .collect();
+        expiring.sort_by_key(|certification| (certification.valid_until, certification.id));
+
+        expiring
+    }
+
+    pub fn create(conn: &PgConnection, input: CertificationInput, actor: Option<i32>) -> AppResult<Certification> {
+        validate(&input)?;
+
+        conn.transaction::<Certification, AppError, _>(|| {
+            let user = User::find(conn, input.user_id)?;
+
+            let new_certification: NewCertification = NewCertification {
+                user_id: &user.id,
+                domain: input.domain.code(),
+                body: input.body.trim(),
+                number: input.number.trim(),
+                valid_from: &input.valid_from.0,
+                valid_until: &input.valid_until.0,
+                created_by: actor,
+                created_at: &chrono::offset::Utc::now().naive_utc(),
+            };
+
+            let certification: Certification = diesel::insert_into(certifications::table)
+                .values(new_certification)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_CERTIFICATION, certification.id, OPERATION_CREATE, None, Some(&certification))?;
+
+            Ok(certification)
+        })
+    }
+
+    // Correct a certification entered wrong
+    pub fn update(conn: &PgConnection, certification_id: i32, input: CertificationInput, actor: Option<i32>) -> AppResult<Certification> {
+        validate(&input)?;
+
+        conn.transaction::<Certification, AppError, _>(|| {
+            let previous = Certification::find(conn, certification_id)?;
+            let user = User::find(conn, input.user_id)?;
+
+            let certification: Certification = diesel::update(certifications::table.find(previous.id))
+                .set((
+                    certifications::user_id.eq(user.id),
+                    certifications::domain.eq(input.domain.code()),
+                    certifications::body.eq(input.body.trim()),
+                    certifications::number.eq(input.number.trim()),
+                    certifications::valid_from.eq(input.valid_from.0),
+                    certifications::valid_until.eq(input.valid_until.0),
+                ))
+                .get_result(conn)?;
+
+            AuditLog::record(
+                conn,
+                actor,
+                ENTITY_CERTIFICATION,
+                certification.id,
+                OPERATION_UPDATE,
+                Some(&previous),
+                Some(&certification),
+            )?;
+
+            Ok(certification)
+        })
+    }
+}
+
+pub struct CertificationQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl CertificationQuery {
+    #[graphql(description = "Fetch the certifications of an operator, the latest ending first")]
+    async fn fetch_all(context: &GraphQLContext, user_id: i32) -> AppResult<Vec<Certification>> {
+        context.require_user()?;
+
+        context.run(move |conn| Ok(Certification::of_user(conn, user_id)?)).await
+    }
+
+    #[graphql(description = "Certifications ending within `withinDays` (`CERTIFICATION_WARNING_DAYS` by default) or ended, the latest of each operator and domain only")]
+    async fn fetch_expiring(context: &GraphQLContext, within_days: Option<i32>) -> AppResult<Vec<Certification>> {
+        context.require_user()?;
+
+        let warning_days = match within_days {
+            Some(days) if days < 0 => return Err(AppError::invalid("withinDays", "Must not be negative")),
+            Some(days) => days.into(),
+            None => context.config.certifications.warning_days,
+        };
+
+        context.run(move |conn| Ok(Certification::expiring(conn, warning_days)?)).await
+    }
+}
+
+pub struct CertificationMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl CertificationMutation {
+    #[graphql(description = "Record a certification of an operator, administrators only")]
+    async fn create(context: &GraphQLContext, input: CertificationInput) -> AppResult<Certification> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| Certification::create(conn, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Correct a certification, administrators only")]
+    async fn update(context: &GraphQLContext, certification_id: i32, input: CertificationInput) -> AppResult<Certification> {
+        let actor = context.require_administrator().await?.id;
+
+        context
+            .run(move |conn| Certification::update(conn, certification_id, input, Some(actor)))
+            .await
+    }
+}
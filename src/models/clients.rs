@@ -1,11 +1,15 @@
-use std::borrow::Borrow;
 use crate::schema::{clients, clients::dsl::*};
 use diesel::prelude::*;
 use chrono::NaiveDateTime;
 use diesel_json::Json;
-use futures::SinkExt;
-use juniper::{FieldError, FieldResult, graphql_value};
+use std::sync::Arc;
 use crate::GraphQLContext;
+use crate::dates::{DateRange, DateTime};
+use crate::models::invoices::Invoice;
+use crate::models::worksites::Worksite;
+use crate::errors::{AppError, AppResult};
+use crate::validation::{validate, validate_argument, Validate, Validator};
+use crate::models::audit_logs::{AuditLog, ENTITY_CLIENT, OPERATION_CREATE};
 
 #[derive(Debug, Serialize, Queryable, Identifiable)]
 pub struct Client {
@@ -21,15 +25,23 @@ pub struct Client {
 pub struct Address {
     pub street: String,
     pub street_number: i32,
+    #[serde(default)]
+    pub postal_code: Option<String>,
+    #[serde(default)]
+    pub city: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
 pub struct Interlocutor {
     pub name: String,
     pub position: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
 }
 
-#[juniper::graphql_object]
+#[juniper::graphql_object(Context = GraphQLContext)]
 impl Client {
     fn id(&self) -> i32 {
         self.id
@@ -43,16 +55,31 @@ impl Client {
         self.address.as_ref()
     }
 
-    fn interlocutors(&self) -> &Vec<Interlocutor> {
-        self.interlocutors.as_deref().unwrap()
+    fn interlocutors(&self) -> Vec<Interlocutor> {
+        self.interlocutors.as_deref().cloned().unwrap_or_default()
     }
 
-    fn created_at(&self) -> String {
-        self.created_at.format("%d-%m-%Y %M:%S:%f").to_string()
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
     }
 
-    fn edited_at(&self) -> String {
-        self.edited_at.format("%d-%m-%Y %M:%S:%f").to_string()
+    fn edited_at(&self) -> DateTime {
+        self.edited_at.into()
+    }
+
+    #[graphql(description = "Worksites of the client that aren't deleted")]
+    async fn worksites(&self, context: &GraphQLContext) -> AppResult<Vec<Arc<Worksite>>> {
+        let found = context.loaders.worksites_by_client.load(self.id).await?;
+
+        Ok(found.map(|list| list.as_ref().clone()).unwrap_or_default())
+    }
+
+    #[graphql(description = "Invoices of the client, latest first")]
+    async fn invoices(&self, context: &GraphQLContext) -> AppResult<Vec<Arc<Invoice>>> {
+        context.require_user()?;
+        let found = context.loaders.invoices_by_client.load(self.id).await?;
+
+        Ok(found.map(|list| list.as_ref().clone()).unwrap_or_default())
     }
 }
 
@@ -61,40 +88,47 @@ pub struct ClientQuery;
 #[juniper::graphql_object(Context = GraphQLContext)]
 impl ClientQuery {
     #[graphql(description = "Fetch a client")]
-    fn fetch(context: &GraphQLContext, client_id: i32) -> FieldResult<Client> {
-        let conn = context.pool.get()?;
-
-        let client = clients.find(client_id).get_result::<Client>(&conn);
-
-        if client.is_ok() {
-            Ok(client.unwrap())
-        } else {
-            Err(FieldError::new(
-                "Could not get client",
-                graphql_value!({ "authentication_error": "Invalid value" }),
-            ))
-        }
-
+    async fn fetch(context: &GraphQLContext, client_id: i32) -> AppResult<Arc<Client>> {
+        context
+            .loaders
+            .clients
+            .load(client_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Client".to_string()))
     }
 
-    #[graphql(description = "Fetch a clients")]
-    fn fetch_all(context: &GraphQLContext, mut offset: i32) -> FieldResult<Vec<Client>> {
-        let conn = context.pool.get()?;
-
-        let client = clients
-            .limit(10)
-            .offset(offset.into())
-            .get_results::<Client>(&conn);
-
-        if client.is_ok() {
-            Ok(client.unwrap())
-        } else {
-            Err(FieldError::new(
-                "Could not get client",
-                graphql_value!({ "authentication_error": "Invalid value" }),
-            ))
+    #[graphql(description = "Fetch a clients, optionally created in a range of days")]
+    async fn fetch_all(
+        context: &GraphQLContext,
+        offset: i32,
+        created_between: Option<DateRange>,
+    ) -> AppResult<Vec<Client>> {
+        if offset < 0 {
+            return Err(AppError::invalid("offset", "Must not be negative"));
         }
-
+        validate_argument("createdBetween", &created_between)?;
+
+        context
+            .run(move |conn| {
+                let mut query = clients.into_boxed();
+
+                if let Some(range) = created_between {
+                    let (from, to) = range.bounds();
+
+                    if let Some(from) = from {
+                        query = query.filter(created_at.ge(from));
+                    }
+                    if let Some(to) = to {
+                        query = query.filter(created_at.lt(to));
+                    }
+                }
+
+                Ok(query
+                    .limit(10)
+                    .offset(offset.into())
+                    .get_results::<Client>(conn)?)
+            })
+            .await
     }
 }
 
@@ -116,16 +150,49 @@ pub struct ClientInput {
 }
 
 
+impl Validate for ClientInput {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name)
+            .length("name", &self.name, 1, 255)
+            .required("address", &self.address)
+            .nested("address", &self.address)
+            .each("interlocutors", &self.interlocutors);
+    }
+}
+
 #[derive(Debug, GraphQLInputObject, Serialize)]
 pub struct AddressInput {
     pub street: String,
     pub street_number: i32,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+}
+
+impl Validate for AddressInput {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("street", &self.street)
+            .length("street", &self.street, 1, 255)
+            .range("streetNumber", self.street_number, 1, 99999)
+            .postal_code("postalCode", &self.postal_code);
+    }
 }
 
 #[derive(Debug, GraphQLInputObject, Serialize, Clone)]
 pub struct InterlocutorInput {
     pub name: String,
     pub position: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+impl Validate for InterlocutorInput {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name)
+            .length("name", &self.name, 1, 255)
+            .length("position", &self.position, 0, 255)
+            .email("email", &self.email)
+            .phone("phone", &self.phone);
+    }
 }
 
 pub struct ClientMutation;
@@ -133,36 +200,43 @@ pub struct ClientMutation;
 #[juniper::graphql_object(Context = GraphQLContext)]
 impl ClientMutation {
     #[graphql(description = "create a new client")]
-    fn create(context: &GraphQLContext, input: ClientInput) -> FieldResult<Client> {
-        let received_interlocutors: Option<Json<&Vec<InterlocutorInput>>> = if input.interlocutors.is_none() {
-            None
-        } else {
-            Some(Json::new(input.interlocutors.as_ref().unwrap()))
-        };
+    async fn create(context: &GraphQLContext, input: ClientInput) -> AppResult<Client> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| Client::create(conn, input, Some(actor))).await
+    }
+}
+
+impl Client {
+    pub fn create(conn: &PgConnection, input: ClientInput, actor: Option<i32>) -> AppResult<Client> {
+        validate(&input)?;
+
+        let received_interlocutors: Option<Json<&Vec<InterlocutorInput>>> = input.interlocutors.as_ref().map(Json::new);
+
+        let received_address = input
+            .address
+            .ok_or_else(|| AppError::invalid("address", "Is required"))?;
 
         let new_client: NewClient = NewClient {
             name: &input.name,
-            address: &Json::new(input.address.unwrap()),
+            address: &Json::new(received_address),
             interlocutors:  received_interlocutors,
-            // interlocutors: &diesel_json::Json::new(new_interlocutors),
             created_at: &chrono::offset::Utc::now().naive_utc(),
             edited_at: &chrono::offset::Utc::now().naive_utc(),
         };
 
-        let conn = context.pool.get()?;
+        conn.transaction::<Client, AppError, _>(|| {
+            let client: Client = diesel::insert_into(clients)
+                .values(new_client)
+                .get_result(conn)?;
 
-        let created_client = diesel::insert_into(clients)
-            .values(new_client)
-            .get_result(&conn);
+            AuditLog::record(conn, actor, ENTITY_CLIENT, client.id, OPERATION_CREATE, None, Some(&client))?;
 
-        if created_client.is_ok() {
-            Ok(created_client.unwrap())
-        } else {
-            Err(FieldError::new(
-                "Could not create the client",
-                graphql_value!({ "authentication_error": "Invalid value" }),
-            ))
-        }
+            Ok(client)
+        })
     }
-}
 
+    pub fn all(conn: &PgConnection) -> AppResult<Vec<Client>> {
+        Ok(clients.order(id.asc()).load(conn)?)
+    }
+}
@@ -0,0 +1,445 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::{self, Date, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::models::audit_logs::{
+    AuditLog, ENTITY_INSTRUMENT, OPERATION_CALIBRATION, OPERATION_CREATE, OPERATION_UPDATE,
+};
+use crate::models::users::User;
+use crate::models::worksites::LeadSession;
+use crate::schema::{calibration_checks, instruments};
+use crate::validation::{validate, Validate, Validator};
+
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+pub struct Instrument {
+    pub id: i32,
+    pub manufacturer: String,
+    pub model: String,
+    pub serial_number: String,
+    pub radionuclide: String,
+    pub source_date: NaiveDate,
+    pub retired_on: Option<NaiveDate>,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "XRF analyser measuring lead in paints")]
+impl Instrument {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn manufacturer(&self) -> &str {
+        self.manufacturer.as_str()
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_str()
+    }
+
+    fn serial_number(&self) -> &str {
+        self.serial_number.as_str()
+    }
+
+    #[graphql(description = "Radionuclide of the source, such as Cd-109")]
+    fn radionuclide(&self) -> &str {
+        self.radionuclide.as_str()
+    }
+
+    #[graphql(description = "Date of the radioactive source")]
+    fn source_date(&self) -> Date {
+        Date(self.source_date)
+    }
+
+    #[graphql(description = "Last day the instrument may be used")]
+    fn retired_on(&self) -> Option<Date> {
+        self.retired_on.map(Date)
+    }
+
+    #[graphql(description = "Calibration checks, the latest first")]
+    async fn calibration_checks(&self, context: &GraphQLContext) -> AppResult<Vec<CalibrationCheck>> {
+        let instrument_id = self.id;
+
+        context.run(move |conn| Ok(CalibrationCheck::of_instrument(conn, instrument_id)?)).await
+    }
+
+    #[graphql(description = "In service with a passed check covering today")]
+    async fn usable(&self, context: &GraphQLContext) -> AppResult<bool> {
+        let instrument = self.clone();
+
+        context
+            .run(move |conn| {
+                let record = InstrumentRecord {
+                    checks: CalibrationCheck::of_instrument(conn, instrument.id)?,
+                    instrument,
+                };
+
+                Ok(record.usable_on(dates::today()).is_ok())
+            })
+            .await
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "instruments"]
+pub struct NewInstrument<'a> {
+    pub manufacturer: &'a str,
+    pub model: &'a str,
+    pub serial_number: &'a str,
+    pub radionuclide: &'a str,
+    pub source_date: &'a NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+pub struct CalibrationCheck {
+    pub id: i32,
+    pub instrument_id: i32,
+    pub checked_on: NaiveDate,
+    pub valid_until: NaiveDate,
+    pub body: String,
+    pub passed: bool,
+    pub certificate_number: Option<String>,
+    pub recorded_by: Option<i32>,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Periodic check of an instrument")]
+impl CalibrationCheck {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn instrument_id(&self) -> i32 {
+        self.instrument_id
+    }
+
+    fn checked_on(&self) -> Date {
+        Date(self.checked_on)
+    }
+
+    fn valid_until(&self) -> Date {
+        Date(self.valid_until)
+    }
+
+    #[graphql(description = "Who checked the instrument")]
+    fn body(&self) -> &str {
+        self.body.as_str()
+    }
+
+    fn passed(&self) -> bool {
+        self.passed
+    }
+
+    fn certificate_number(&self) -> Option<&str> {
+        self.certificate_number.as_deref()
+    }
+
+    async fn recorded_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.recorded_by {
+            Some(recorder) => context.loaders.users.load(recorder).await,
+            None => Ok(None),
+        }
+    }
+
+    fn recorded_at(&self) -> DateTime {
+        self.recorded_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "calibration_checks"]
+pub struct NewCalibrationCheck<'a> {
+    pub instrument_id: &'a i32,
+    pub checked_on: &'a NaiveDate,
+    pub valid_until: &'a NaiveDate,
+    pub body: &'a str,
+    pub passed: &'a bool,
+    pub certificate_number: Option<&'a str>,
+    pub recorded_by: Option<i32>,
+    pub recorded_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct InstrumentInput {
+    pub manufacturer: String,
+    pub model: String,
+    pub serial_number: String,
+    #[graphql(description = "Radionuclide of the source, such as Cd-109")]
+    pub radionuclide: String,
+    pub source_date: Date,
+}
+
+impl Validate for InstrumentInput {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("manufacturer", &self.manufacturer)
+            .length("manufacturer", &self.manufacturer, 1, 128)
+            .not_blank("model", &self.model)
+            .length("model", &self.model, 1, 128)
+            .not_blank("serialNumber", &self.serial_number)
+            .length("serialNumber", &self.serial_number, 1, 64)
+            .not_blank("radionuclide", &self.radionuclide)
+            .length("radionuclide", &self.radionuclide, 1, 32)
+            .check("sourceDate", self.source_date.0 <= dates::today(), "Must not be in the future");
+    }
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct CalibrationCheckInput {
+    pub instrument_id: i32,
+    pub checked_on: Date,
+    pub valid_until: Date,
+    #[graphql(description = "Who checked the instrument")]
+    pub body: String,
+    pub passed: bool,
+    pub certificate_number: Option<String>,
+}
+
+impl Validate for CalibrationCheckInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("instrumentId", self.instrument_id, 1, i32::MAX)
+            .check("checkedOn", self.checked_on.0 <= dates::today(), "Must not be in the future")
+            .ordered("validUntil", &Some(self.checked_on.0), &Some(self.valid_until.0))
+            .not_blank("body", &self.body)
+            .length("body", &self.body, 1, 255);
+
+        if let Some(number) = &self.certificate_number {
+            v.length("certificateNumber", number, 0, 64);
+        }
+    }
+}
+
+// An instrument with its calibration checks, as needed to tell whether a
+// lead session could use it
+pub struct InstrumentRecord {
+    pub instrument: Instrument,
+    pub checks: Vec<CalibrationCheck>,
+}
+
+impl InstrumentRecord {
+    // Instruments of the sessions of a worksite, unknown ones left out
+    pub fn of_sessions(conn: &PgConnection, sessions: &[LeadSession]) -> AppResult<Vec<InstrumentRecord>> {
+        let ids: Vec<i32> = sessions.iter().map(|session| session.instrument_id).collect();
+        let found: Vec<Instrument> = instruments::table
+            .filter(instruments::id.eq_any(ids))
+            .order(instruments::id.asc())
+            .load(conn)?;
+
+        found
+            .into_iter()
+            .map(|instrument| {
+                Ok(InstrumentRecord {
+                    checks: CalibrationCheck::of_instrument(conn, instrument.id)?,
+                    instrument,
+                })
+            })
+            .collect()
+    }
+
+    // The passed check covering a day, or why the instrument can't be used
+    pub fn usable_on(&self, day: NaiveDate) -> Result<&CalibrationCheck, String> {
+        let serial_number = &self.instrument.serial_number;
+
+        if let Some(retired_on) = self.instrument.retired_on.filter(|retired_on| *retired_on < day) {
+            return Err(format!("Instrument {} was retired on {}", serial_number, retired_on.format("%d/%m/%Y")));
+        }
+
+        self.checks
+            .iter()
+            .find(|check| check.passed && check.checked_on <= day && day <= check.valid_until)
+            .ok_or_else(|| format!("Instrument {} has no passed calibration check on {}", serial_number, day.format("%d/%m/%Y")))
+    }
+}
+
+impl Instrument {
+    pub fn find(conn: &PgConnection, instrument_id: i32) -> AppResult<Instrument> {
+        instruments::table
+            .find(instrument_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Instrument".to_string()))
+    }
+
+    pub fn create(conn: &PgConnection, input: InstrumentInput, actor: Option<i32>) -> AppResult<Instrument> {
+        validate(&input)?;
+
+        let new_instrument: NewInstrument = NewInstrument {
+            manufacturer: input.manufacturer.trim(),
+            model: input.model.trim(),
+            serial_number: input.serial_number.trim(),
+            radionuclide: input.radionuclide.trim(),
+            source_date: &input.source_date.0,
+            created_by: actor,
+            created_at: &chrono::offset::Utc::now().naive_utc(),
+        };
+
+        conn.transaction::<Instrument, AppError, _>(|| {
+            let instrument: Instrument = diesel::insert_into(instruments::table)
+                .values(new_instrument)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_INSTRUMENT, instrument.id, OPERATION_CREATE, None, Some(&instrument))?;
+
+            Ok(instrument)
+        })
+    }
+
+    // Correct an instrument, or record its new source once replaced
+    pub fn update(conn: &PgConnection, instrument_id: i32, input: InstrumentInput, actor: Option<i32>) -> AppResult<Instrument> {
+        validate(&input)?;
+
+        conn.transaction::<Instrument, AppError, _>(|| {
+            let previous = Instrument::find(conn, instrument_id)?;
+
+            let instrument: Instrument = diesel::update(instruments::table.find(previous.id))
+                .set((
+                    instruments::manufacturer.eq(input.manufacturer.trim()),
+                    instruments::model.eq(input.model.trim()),
+                    instruments::serial_number.eq(input.serial_number.trim()),
+                    instruments::radionuclide.eq(input.radionuclide.trim()),
+                    instruments::source_date.eq(input.source_date.0),
+                ))
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_INSTRUMENT, instrument.id, OPERATION_UPDATE, Some(&previous), Some(&instrument))?;
+
+            Ok(instrument)
+        })
+    }
+
+    // Take an instrument out of service after `on`, or back in without a day
+    pub fn retire(conn: &PgConnection, instrument_id: i32, on: Option<NaiveDate>, actor: Option<i32>) -> AppResult<Instrument> {
+        conn.transaction::<Instrument, AppError, _>(|| {
+            let previous = Instrument::find(conn, instrument_id)?;
+
+            let instrument: Instrument = diesel::update(instruments::table.find(previous.id))
+                .set(instruments::retired_on.eq(on))
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_INSTRUMENT, instrument.id, OPERATION_UPDATE, Some(&previous), Some(&instrument))?;
+
+            Ok(instrument)
+        })
+    }
+}
+
+impl CalibrationCheck {
+    // Checks of an instrument, the latest first
+    pub fn of_instrument(conn: &PgConnection, instrument_id: i32) -> QueryResult<Vec<CalibrationCheck>> {
+        calibration_checks::table
+            .filter(calibration_checks::instrument_id.eq(instrument_id))
+            .order((calibration_checks::checked_on.desc(), calibration_checks::id.desc()))
+            .load(conn)
+    }
+
+    pub fn record(conn: &PgConnection, input: CalibrationCheckInput, actor: Option<i32>) -> AppResult<CalibrationCheck> {
+        validate(&input)?;
+
+        conn.transaction::<CalibrationCheck, AppError, _>(|| {
+            let instrument = Instrument::find(conn, input.instrument_id)?;
+
+            let new_check: NewCalibrationCheck = NewCalibrationCheck {
+                instrument_id: &instrument.id,
+                checked_on: &input.checked_on.0,
+                valid_until: &input.valid_until.0,
+                body: input.body.trim(),
+                passed: &input.passed,
+                certificate_number: input.certificate_number.as_deref().map(str::trim),
+                recorded_by: actor,
+                recorded_at: &chrono::offset::Utc::now().naive_utc(),
+            };
+
+            let check: CalibrationCheck = diesel::insert_into(calibration_checks::table)
+                .values(new_check)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_INSTRUMENT, instrument.id, OPERATION_CALIBRATION, None, Some(&check))?;
+
+            Ok(check)
+        })
+    }
+}
+
+pub struct InstrumentQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl InstrumentQuery {
+    #[graphql(description = "Fetch an instrument")]
+    async fn fetch(context: &GraphQLContext, instrument_id: i32) -> AppResult<Instrument> {
+        context.require_user()?;
+
+        context.run(move |conn| Instrument::find(conn, instrument_id)).await
+    }
+
+    #[graphql(description = "Fetch the instruments, those retired only when asked")]
+    async fn fetch_all(context: &GraphQLContext, include_retired: Option<bool>) -> AppResult<Vec<Instrument>> {
+        context.require_user()?;
+
+        context
+            .run(move |conn| {
+                let mut query = instruments::table.into_boxed();
+
+                if include_retired != Some(true) {
+                    query = query.filter(
+                        instruments::retired_on
+                            .is_null()
+                            .or(instruments::retired_on.ge(dates::today())),
+                    );
+                }
+
+                Ok(query.order(instruments::serial_number.asc()).load::<Instrument>(conn)?)
+            })
+            .await
+    }
+}
+
+pub struct InstrumentMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl InstrumentMutation {
+    #[graphql(description = "Register an instrument, administrators only")]
+    async fn create(context: &GraphQLContext, input: InstrumentInput) -> AppResult<Instrument> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| Instrument::create(conn, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Correct an instrument or record its new source, administrators only")]
+    async fn update(context: &GraphQLContext, instrument_id: i32, input: InstrumentInput) -> AppResult<Instrument> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| Instrument::update(conn, instrument_id, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Take an instrument out of service after `on`, back in service without it, administrators only")]
+    async fn retire(context: &GraphQLContext, instrument_id: i32, on: Option<Date>) -> AppResult<Instrument> {
+        let actor = context.require_administrator().await?.id;
+
+        context
+            .run(move |conn| Instrument::retire(conn, instrument_id, on.map(|on| on.0), Some(actor)))
+            .await
+    }
+
+    #[graphql(description = "Record a calibration check of an instrument")]
+    async fn record_calibration_check(context: &GraphQLContext, input: CalibrationCheckInput) -> AppResult<CalibrationCheck> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| CalibrationCheck::record(conn, input, Some(actor))).await
+    }
+}
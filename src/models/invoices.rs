@@ -0,0 +1,1028 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+use diesel_json::Json;
+use std::ops::Deref;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::billing::euros;
+use crate::config::BillingConfig;
+use crate::dates::{self, Date, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::models::audit_logs::{
+    AuditLog, ENTITY_CREDIT_NOTE, ENTITY_INVOICE, OPERATION_CREATE, OPERATION_PAYMENT, OPERATION_UPDATE,
+};
+use crate::models::clients::Client;
+use crate::models::jobs::{
+    CreditNoteDocumentPayload, InvoiceDocumentPayload, Job, KIND_CREDIT_NOTE_DOCUMENT, KIND_INVOICE_DOCUMENT,
+};
+use crate::models::price_grids::PriceGrid;
+use crate::models::quotes::{Quote, QuoteParameters};
+use crate::models::users::User;
+use crate::models::worksite_versions::{WorksiteRevision, WorksiteVersion};
+use crate::models::worksites::Worksite;
+use crate::pricing::{self, PriceLine};
+use crate::schema::{credit_notes, invoices, payments};
+use crate::validation::{validate, Validate, Validator};
+
+// Series of `document_sequences`, also the prefix of the numbers
+const SERIES_INVOICE: &str = "F";
+const SERIES_CREDIT_NOTE: &str = "AV";
+
+// What is left to pay of an invoice decides its status. A fully credited
+// invoice is cancelled, whatever was paid is refunded outside the
+// application.
+pub const STATUS_UNPAID: &str = "unpaid";
+pub const STATUS_PARTIALLY_PAID: &str = "partially_paid";
+pub const STATUS_PAID: &str = "paid";
+pub const STATUS_CREDITED: &str = "credited";
+
+pub const PAYMENT_METHODS: &[&str] = &["transfer", "cheque", "card", "cash"];
+
+// Largest amount of a line or a payment, 100 000 €
+const MAX_AMOUNT: i32 = 10_000_000;
+
+#[derive(QueryableByName)]
+struct Sequence {
+    #[sql_type = "Integer"]
+    last_number: i32,
+}
+
+// Next number of a series for a year, `F2026-00042`. The sequence row stays
+// locked until the transaction ends, so numbers follow the order documents
+// are issued in and a rollback leaves no gap.
+fn next_number(conn: &PgConnection, series: &str, year: i32) -> QueryResult<String> {
+    let sequence: Sequence = diesel::sql_query(
+        "INSERT INTO document_sequences (series, year, last_number) VALUES ($1, $2, 1) \
+         ON CONFLICT (series, year) DO UPDATE SET last_number = document_sequences.last_number + 1 \
+         RETURNING last_number",
+    )
+    .bind::<Text, _>(series)
+    .bind::<Integer, _>(year)
+    .get_result(conn)?;
+
+    Ok(number(series, year, sequence.last_number))
+}
+
+fn number(series: &str, year: i32, sequence: i32) -> String {
+    format!("{}{}-{:05}", series, year, sequence)
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct Invoice {
+    pub id: i32,
+    pub number: String,
+    pub client_id: i32,
+    pub worksite_id: i32,
+    pub quote_id: Option<i32>,
+    pub lines: Json<Vec<PriceLine>>,
+    pub total_excluding_tax: i32,
+    pub vat_rate: i32,
+    pub vat_amount: i32,
+    pub total_including_tax: i32,
+    pub amount_paid: i32,
+    pub amount_credited: i32,
+    pub status: String,
+    pub due_date: NaiveDate,
+    pub document_path: Option<String>,
+    pub issued_by: Option<i32>,
+    pub issued_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Invoice of an issued worksite, amounts in euro cents")]
+impl Invoice {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[graphql(description = "Number printed on the invoice, `F<year>-<sequence>` without gaps")]
+    fn number(&self) -> &str {
+        self.number.as_str()
+    }
+
+    fn client_id(&self) -> i32 {
+        self.client_id
+    }
+
+    async fn client(&self, context: &GraphQLContext) -> AppResult<Arc<Client>> {
+        context
+            .loaders
+            .clients
+            .load(self.client_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Client".to_string()))
+    }
+
+    fn worksite_id(&self) -> i32 {
+        self.worksite_id
+    }
+
+    async fn worksite(&self, context: &GraphQLContext) -> AppResult<Arc<Worksite>> {
+        context
+            .loaders
+            .worksites
+            .load(self.worksite_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Worksite".to_string()))
+    }
+
+    #[graphql(description = "Quote the lines were taken from")]
+    fn quote_id(&self) -> Option<i32> {
+        self.quote_id
+    }
+
+    fn lines(&self) -> &Vec<PriceLine> {
+        self.lines.deref()
+    }
+
+    fn total_excluding_tax(&self) -> i32 {
+        self.total_excluding_tax
+    }
+
+    #[graphql(description = "Percent")]
+    fn vat_rate(&self) -> f64 {
+        f64::from(self.vat_rate) / 100.0
+    }
+
+    fn vat_amount(&self) -> i32 {
+        self.vat_amount
+    }
+
+    fn total_including_tax(&self) -> i32 {
+        self.total_including_tax
+    }
+
+    fn amount_paid(&self) -> i32 {
+        self.amount_paid
+    }
+
+    #[graphql(description = "Total of the credit notes of the invoice, including tax")]
+    fn amount_credited(&self) -> i32 {
+        self.amount_credited
+    }
+
+    #[graphql(description = "What the client still owes")]
+    fn balance(&self) -> i32 {
+        self.balance()
+    }
+
+    #[graphql(description = "unpaid, partially_paid, paid or credited")]
+    fn status(&self) -> &str {
+        self.status.as_str()
+    }
+
+    fn due_date(&self) -> Date {
+        Date(self.due_date)
+    }
+
+    #[graphql(description = "Past its due date with a balance left")]
+    fn overdue(&self) -> bool {
+        self.balance() > 0 && self.due_date < dates::today()
+    }
+
+    async fn payments(&self, context: &GraphQLContext) -> AppResult<Vec<Payment>> {
+        let invoice_id = self.id;
+
+        context
+            .run(move |conn| {
+                Ok(payments::table
+                    .filter(payments::invoice_id.eq(invoice_id))
+                    .order(payments::id.asc())
+                    .load::<Payment>(conn)?)
+            })
+            .await
+    }
+
+    async fn credit_notes(&self, context: &GraphQLContext) -> AppResult<Vec<CreditNote>> {
+        let invoice_id = self.id;
+
+        context
+            .run(move |conn| {
+                Ok(credit_notes::table
+                    .filter(credit_notes::invoice_id.eq(invoice_id))
+                    .order(credit_notes::id.asc())
+                    .load::<CreditNote>(conn)?)
+            })
+            .await
+    }
+
+    #[graphql(description = "PDF of the invoice, set once rendered in the background")]
+    fn document_path(&self) -> Option<&str> {
+        self.document_path.as_deref()
+    }
+
+    async fn issued_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.issued_by {
+            Some(issuer) => context.loaders.users.load(issuer).await,
+            None => Ok(None),
+        }
+    }
+
+    fn issued_at(&self) -> DateTime {
+        self.issued_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "invoices"]
+pub struct NewInvoice<'a> {
+    pub number: &'a str,
+    pub client_id: &'a i32,
+    pub worksite_id: &'a i32,
+    pub quote_id: Option<i32>,
+    pub lines: &'a Json<Vec<PriceLine>>,
+    pub total_excluding_tax: &'a i32,
+    pub vat_rate: &'a i32,
+    pub vat_amount: &'a i32,
+    pub total_including_tax: &'a i32,
+    pub due_date: &'a NaiveDate,
+    pub issued_by: Option<i32>,
+    pub issued_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct CreditNote {
+    pub id: i32,
+    pub number: String,
+    pub invoice_id: i32,
+    pub reason: String,
+    pub lines: Json<Vec<PriceLine>>,
+    pub total_excluding_tax: i32,
+    pub vat_rate: i32,
+    pub vat_amount: i32,
+    pub total_including_tax: i32,
+    pub document_path: Option<String>,
+    pub issued_by: Option<i32>,
+    pub issued_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Credit note cancelling all or part of an invoice, amounts in euro cents")]
+impl CreditNote {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[graphql(description = "Number printed on the credit note, `AV<year>-<sequence>` without gaps")]
+    fn number(&self) -> &str {
+        self.number.as_str()
+    }
+
+    fn invoice_id(&self) -> i32 {
+        self.invoice_id
+    }
+
+    async fn invoice(&self, context: &GraphQLContext) -> AppResult<Invoice> {
+        let invoice_id = self.invoice_id;
+
+        context.run(move |conn| Invoice::find(conn, invoice_id)).await
+    }
+
+    fn reason(&self) -> &str {
+        self.reason.as_str()
+    }
+
+    fn lines(&self) -> &Vec<PriceLine> {
+        self.lines.deref()
+    }
+
+    fn total_excluding_tax(&self) -> i32 {
+        self.total_excluding_tax
+    }
+
+    #[graphql(description = "Percent")]
+    fn vat_rate(&self) -> f64 {
+        f64::from(self.vat_rate) / 100.0
+    }
+
+    fn vat_amount(&self) -> i32 {
+        self.vat_amount
+    }
+
+    fn total_including_tax(&self) -> i32 {
+        self.total_including_tax
+    }
+
+    #[graphql(description = "PDF of the credit note, set once rendered in the background")]
+    fn document_path(&self) -> Option<&str> {
+        self.document_path.as_deref()
+    }
+
+    async fn issued_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.issued_by {
+            Some(issuer) => context.loaders.users.load(issuer).await,
+            None => Ok(None),
+        }
+    }
+
+    fn issued_at(&self) -> DateTime {
+        self.issued_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "credit_notes"]
+pub struct NewCreditNote<'a> {
+    pub number: &'a str,
+    pub invoice_id: &'a i32,
+    pub reason: &'a str,
+    pub lines: &'a Json<Vec<PriceLine>>,
+    pub total_excluding_tax: &'a i32,
+    pub vat_rate: &'a i32,
+    pub vat_amount: &'a i32,
+    pub total_including_tax: &'a i32,
+    pub issued_by: Option<i32>,
+    pub issued_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct Payment {
+    pub id: i32,
+    pub invoice_id: i32,
+    pub amount: i32,
+    pub paid_on: NaiveDate,
+    pub method: String,
+    pub reference: Option<String>,
+    pub recorded_by: Option<i32>,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Payment received for an invoice, in euro cents")]
+impl Payment {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn invoice_id(&self) -> i32 {
+        self.invoice_id
+    }
+
+    fn amount(&self) -> i32 {
+        self.amount
+    }
+
+    fn paid_on(&self) -> Date {
+        Date(self.paid_on)
+    }
+
+    #[graphql(description = "transfer, cheque, card or cash")]
+    fn method(&self) -> &str {
+        self.method.as_str()
+    }
+
+    #[graphql(description = "Cheque number or transfer reference")]
+    fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    async fn recorded_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.recorded_by {
+            Some(recorder) => context.loaders.users.load(recorder).await,
+            None => Ok(None),
+        }
+    }
+
+    fn recorded_at(&self) -> DateTime {
+        self.recorded_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "payments"]
+pub struct NewPayment<'a> {
+    pub invoice_id: &'a i32,
+    pub amount: &'a i32,
+    pub paid_on: &'a NaiveDate,
+    pub method: &'a str,
+    pub reference: Option<&'a str>,
+    pub recorded_by: Option<i32>,
+    pub recorded_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, GraphQLInputObject)]
+#[graphql(description = "Line written by hand, in euro cents excluding tax, negative for a discount")]
+pub struct InvoiceLineInput {
+    pub label: String,
+    pub quantity: i32,
+    pub unit_price: i32,
+}
+
+impl Validate for InvoiceLineInput {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("label", &self.label)
+            .length("label", &self.label, 1, 255)
+            .range("quantity", self.quantity, 1, 10_000)
+            .range("unitPrice", self.unit_price, -MAX_AMOUNT, MAX_AMOUNT);
+    }
+}
+
+fn priced(lines: Vec<InvoiceLineInput>) -> AppResult<Vec<PriceLine>> {
+    lines
+        .into_iter()
+        .map(|line| pricing::line(line.label, line.quantity.into(), line.unit_price))
+        .collect()
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct InvoiceInput {
+    pub worksite_id: i32,
+    #[graphql(description = "Added after the lines of the quote of the worksite, the only lines without a quote")]
+    pub extra_lines: Option<Vec<InvoiceLineInput>>,
+}
+
+impl Validate for InvoiceInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("worksiteId", self.worksite_id, 1, i32::MAX)
+            .each("extraLines", &self.extra_lines);
+    }
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct CreditNoteInput {
+    pub invoice_id: i32,
+    pub reason: String,
+    #[graphql(description = "Lines credited, every line of the invoice when not given")]
+    pub lines: Option<Vec<InvoiceLineInput>>,
+}
+
+impl Validate for CreditNoteInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("invoiceId", self.invoice_id, 1, i32::MAX)
+            .not_blank("reason", &self.reason)
+            .length("reason", &self.reason, 1, 255)
+            .each("lines", &self.lines);
+    }
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct PaymentInput {
+    pub invoice_id: i32,
+    #[graphql(description = "Euro cents")]
+    pub amount: i32,
+    pub paid_on: Date,
+    #[graphql(description = "transfer, cheque, card or cash")]
+    pub method: String,
+    pub reference: Option<String>,
+}
+
+impl Validate for PaymentInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("invoiceId", self.invoice_id, 1, i32::MAX)
+            .range("amount", self.amount, 1, MAX_AMOUNT)
+            .one_of("method", &self.method, PAYMENT_METHODS);
+
+        if let Some(reference) = &self.reference {
+            v.length("reference", reference, 0, 255);
+        }
+    }
+}
+
+fn status_of(total: i32, paid: i32, credited: i32) -> &'static str {
+    if credited >= total {
+        STATUS_CREDITED
+    } else if paid + credited >= total {
+        STATUS_PAID
+    } else if paid > 0 {
+        STATUS_PARTIALLY_PAID
+    } else {
+        STATUS_UNPAID
+    }
+}
+
+impl Invoice {
+    pub fn balance(&self) -> i32 {
+        (self.total_including_tax - self.amount_paid - self.amount_credited).max(0)
+    }
+
+    pub fn find(conn: &PgConnection, invoice_id: i32) -> AppResult<Invoice> {
+        invoices::table
+            .find(invoice_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Invoice".to_string()))
+    }
+
+    // The invoice, locked until the end of the transaction so payments and
+    // credit notes are added one at a time
+    fn lock(conn: &PgConnection, invoice_id: i32) -> AppResult<Invoice> {
+        invoices::table
+            .find(invoice_id)
+            .for_update()
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Invoice".to_string()))
+    }
+
+    // Invoice a worksite once its report is issued. The lines are those of
+    // its quote, priced again with the samples of the issued report, then
+    // the extra lines.
+    pub fn create(conn: &PgConnection, input: InvoiceInput, billing: &BillingConfig, actor: Option<i32>) -> AppResult<Invoice> {
+        use crate::schema::{quotes, worksite_versions};
+
+        validate(&input)?;
+        if let Some(variable) = billing.missing_seller_mention() {
+            return Err(AppError::Unavailable(format!("billing is not configured, set {}", variable)));
+        }
+
+        let vat_rate = billing.vat_rate_basis_points();
+        let today = dates::today();
+
+        conn.transaction::<Invoice, AppError, _>(|| {
+            // Locked so two invoices of the worksite are not created at once
+            let worksite = Worksite::lock(conn, input.worksite_id)?;
+            let revision = WorksiteRevision::latest(conn, worksite.id)?.ok_or_else(|| {
+                AppError::Conflict(format!("the report of worksite {} has not been issued", worksite.id))
+            })?;
+
+            let invoiced: Option<String> = invoices::table
+                .filter(invoices::worksite_id.eq(worksite.id))
+                .filter(invoices::status.ne(STATUS_CREDITED))
+                .select(invoices::number)
+                .first(conn)
+                .optional()?;
+            if let Some(number) = invoiced {
+                return Err(AppError::Conflict(format!(
+                    "worksite {} is invoiced by {}, credit it first",
+                    worksite.id, number
+                )));
+            }
+
+            let version: WorksiteVersion = worksite_versions::table.find(revision.version_id).first(conn)?;
+            let quote: Option<Quote> = quotes::table
+                .filter(quotes::worksite_id.eq(worksite.id))
+                .first(conn)
+                .optional()?;
+
+            let mut lines = match &quote {
+                Some(quote) => quote_lines(conn, quote, version.worksite.sample_count())?,
+                None => vec![],
+            };
+            lines.extend(priced(input.extra_lines.unwrap_or_default())?);
+
+            if lines.is_empty() {
+                return Err(AppError::invalid("extraLines", "Are required for a worksite without a quote"));
+            }
+
+            let totals = pricing::totals(&lines, vat_rate)?;
+            if totals.including_tax <= 0 {
+                return Err(AppError::invalid("extraLines", "The total of the invoice must be positive"));
+            }
+
+            let number = next_number(conn, SERIES_INVOICE, today.year())?;
+
+            let new_invoice: NewInvoice = NewInvoice {
+                number: &number,
+                client_id: &worksite.client_id,
+                worksite_id: &worksite.id,
+                quote_id: quote.as_ref().map(|quote| quote.id),
+                lines: &Json::new(lines),
+                total_excluding_tax: &totals.excluding_tax,
+                vat_rate: &vat_rate,
+                vat_amount: &totals.vat_amount,
+                total_including_tax: &totals.including_tax,
+                due_date: &(today + Duration::days(billing.payment_terms_days)),
+                issued_by: actor,
+                issued_at: &chrono::offset::Utc::now().naive_utc(),
+            };
+
+            let invoice: Invoice = diesel::insert_into(invoices::table)
+                .values(new_invoice)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_INVOICE, invoice.id, OPERATION_CREATE, None, Some(&invoice))?;
+
+            Ok(invoice)
+        })
+    }
+
+    // Record a payment, up to what is left to pay
+    pub fn record_payment(conn: &PgConnection, input: PaymentInput, actor: Option<i32>) -> AppResult<Payment> {
+        validate(&input)?;
+
+        if input.paid_on.0 > dates::today() {
+            return Err(AppError::invalid("paidOn", "Must not be in the future"));
+        }
+
+        conn.transaction::<Payment, AppError, _>(|| {
+            let previous = Invoice::lock(conn, input.invoice_id)?;
+
+            if input.amount > previous.balance() {
+                return Err(AppError::invalid(
+                    "amount",
+                    &format!("Must not exceed the balance of {}", euros(previous.balance())),
+                ));
+            }
+
+            let new_payment: NewPayment = NewPayment {
+                invoice_id: &previous.id,
+                amount: &input.amount,
+                paid_on: &input.paid_on.0,
+                method: &input.method,
+                reference: input.reference.as_deref(),
+                recorded_by: actor,
+                recorded_at: &chrono::offset::Utc::now().naive_utc(),
+            };
+
+            let payment: Payment = diesel::insert_into(payments::table)
+                .values(new_payment)
+                .get_result(conn)?;
+
+            let updated = previous.settle(conn, previous.amount_paid + payment.amount, previous.amount_credited)?;
+            AuditLog::record(conn, actor, ENTITY_INVOICE, updated.id, OPERATION_PAYMENT, Some(&previous), Some(&updated))?;
+
+            Ok(payment)
+        })
+    }
+
+    // Credit all the invoice, or some lines of it, up to what is left of it
+    pub fn issue_credit_note(conn: &PgConnection, input: CreditNoteInput, actor: Option<i32>) -> AppResult<CreditNote> {
+        validate(&input)?;
+
+        conn.transaction::<CreditNote, AppError, _>(|| {
+            let previous = Invoice::lock(conn, input.invoice_id)?;
+
+            let lines = match input.lines {
+                Some(lines) => priced(lines)?,
+                None if previous.amount_credited == 0 => previous.lines.0.clone(),
+                None => {
+                    return Err(AppError::invalid("lines", "Are required once the invoice is partly credited"));
+                }
+            };
+
+            let totals = pricing::totals(&lines, previous.vat_rate)?;
+            let creditable = previous.total_including_tax - previous.amount_credited;
+
+            if totals.including_tax <= 0 {
+                return Err(AppError::invalid("lines", "The total of the credit note must be positive"));
+            }
+            if totals.including_tax > creditable {
+                return Err(AppError::invalid(
+                    "lines",
+                    &format!("Credit more than the {} left of the invoice", euros(creditable)),
+                ));
+            }
+
+            let number = next_number(conn, SERIES_CREDIT_NOTE, dates::today().year())?;
+
+            let new_credit_note: NewCreditNote = NewCreditNote {
+                number: &number,
+                invoice_id: &previous.id,
+                reason: input.reason.trim(),
+                lines: &Json::new(lines),
+                total_excluding_tax: &totals.excluding_tax,
+                vat_rate: &previous.vat_rate,
+                vat_amount: &totals.vat_amount,
+                total_including_tax: &totals.including_tax,
+                issued_by: actor,
+                issued_at: &chrono::offset::Utc::now().naive_utc(),
+            };
+
+            let credit_note: CreditNote = diesel::insert_into(credit_notes::table)
+                .values(new_credit_note)
+                .get_result(conn)?;
+
+            let updated = previous.settle(conn, previous.amount_paid, previous.amount_credited + credit_note.total_including_tax)?;
+            AuditLog::record(conn, actor, ENTITY_CREDIT_NOTE, credit_note.id, OPERATION_CREATE, None, Some(&credit_note))?;
+            AuditLog::record(conn, actor, ENTITY_INVOICE, updated.id, OPERATION_UPDATE, Some(&previous), Some(&updated))?;
+
+            Ok(credit_note)
+        })
+    }
+
+    fn settle(&self, conn: &PgConnection, paid: i32, credited: i32) -> QueryResult<Invoice> {
+        diesel::update(invoices::table.find(self.id))
+            .set((
+                invoices::amount_paid.eq(paid),
+                invoices::amount_credited.eq(credited),
+                invoices::status.eq(status_of(self.total_including_tax, paid, credited)),
+            ))
+            .get_result(conn)
+    }
+
+    pub fn set_document_path(&self, conn: &PgConnection, path: &str) -> QueryResult<Invoice> {
+        diesel::update(invoices::table.find(self.id))
+            .set(invoices::document_path.eq(path))
+            .get_result(conn)
+    }
+
+    // Queue the rendering of the PDF of this invoice
+    pub fn enqueue_document(&self, conn: &PgConnection, max_attempts: i32, actor: Option<i32>) -> AppResult<Job> {
+        Job::enqueue(conn, KIND_INVOICE_DOCUMENT, &InvoiceDocumentPayload { invoice_id: self.id }, max_attempts, actor)
+    }
+}
+
+impl CreditNote {
+    pub fn find(conn: &PgConnection, credit_note_id: i32) -> AppResult<CreditNote> {
+        credit_notes::table
+            .find(credit_note_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Credit note".to_string()))
+    }
+
+    pub fn set_document_path(&self, conn: &PgConnection, path: &str) -> QueryResult<CreditNote> {
+        diesel::update(credit_notes::table.find(self.id))
+            .set(credit_notes::document_path.eq(path))
+            .get_result(conn)
+    }
+
+    // Queue the rendering of the PDF of this credit note
+    pub fn enqueue_document(&self, conn: &PgConnection, max_attempts: i32, actor: Option<i32>) -> AppResult<Job> {
+        let payload = CreditNoteDocumentPayload { credit_note_id: self.id };
+
+        Job::enqueue(conn, KIND_CREDIT_NOTE_DOCUMENT, &payload, max_attempts, actor)
+    }
+}
+
+// Lines of a quote priced again with the grid it was made with, for the
+// samples actually analysed instead of the expected ones
+fn quote_lines(conn: &PgConnection, quote: &Quote, samples: usize) -> AppResult<Vec<PriceLine>> {
+    let quoted_on = dates::paris(quote.created_at).naive_local().date();
+    let grid = PriceGrid::in_force(conn, quote.mission()?, quoted_on)?;
+
+    let parameters = QuoteParameters {
+        expected_samples: i32::try_from(samples).unwrap_or(i32::MAX),
+        ..quote.parameters.0.clone()
+    };
+
+    pricing::price(&grid, &parameters)
+}
+
+pub struct InvoiceQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl InvoiceQuery {
+    #[graphql(description = "Fetch an invoice")]
+    async fn fetch(context: &GraphQLContext, invoice_id: i32) -> AppResult<Invoice> {
+        context.require_user()?;
+
+        context.run(move |conn| Invoice::find(conn, invoice_id)).await
+    }
+
+    #[graphql(description = "Fetch the latest invoices, optionally of a client, of a status or overdue only")]
+    async fn fetch_all(
+        context: &GraphQLContext,
+        client_id: Option<i32>,
+        status: Option<String>,
+        overdue: Option<bool>,
+        offset: i32,
+    ) -> AppResult<Vec<Invoice>> {
+        context.require_user()?;
+
+        if offset < 0 {
+            return Err(AppError::invalid("offset", "Must not be negative"));
+        }
+
+        context
+            .run(move |conn| {
+                let mut query = invoices::table.into_boxed();
+
+                if let Some(client_id) = client_id {
+                    query = query.filter(invoices::client_id.eq(client_id));
+                }
+                if let Some(status) = status {
+                    query = query.filter(invoices::status.eq(status));
+                }
+                if overdue == Some(true) {
+                    query = query
+                        .filter(invoices::status.eq_any(vec![STATUS_UNPAID, STATUS_PARTIALLY_PAID]))
+                        .filter(invoices::due_date.lt(dates::today()));
+                }
+
+                Ok(query
+                    .order(invoices::id.desc())
+                    .limit(20)
+                    .offset(offset.into())
+                    .load::<Invoice>(conn)?)
+            })
+            .await
+    }
+
+    #[graphql(description = "Fetch a credit note")]
+    async fn fetch_credit_note(context: &GraphQLContext, credit_note_id: i32) -> AppResult<CreditNote> {
+        context.require_user()?;
+
+        context.run(move |conn| CreditNote::find(conn, credit_note_id)).await
+    }
+}
+
+pub struct InvoiceMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl InvoiceMutation {
+    #[graphql(description = "Invoice a worksite whose report is issued. Its PDF is rendered in the background, `documentPath` is set once it is written.")]
+    async fn create(context: &GraphQLContext, input: InvoiceInput) -> AppResult<Invoice> {
+        let actor = context.require_user()?.id;
+        let billing = context.config.billing.clone();
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let invoice = context
+            .run(move |conn| {
+                conn.transaction::<Invoice, AppError, _>(|| {
+                    let invoice = Invoice::create(conn, input, &billing, Some(actor))?;
+                    invoice.enqueue_document(conn, max_attempts, Some(actor))?;
+
+                    Ok(invoice)
+                })
+            })
+            .await?;
+
+        crate::jobs::wake();
+        Ok(invoice)
+    }
+
+    #[graphql(description = "Record a payment of an invoice")]
+    async fn record_payment(context: &GraphQLContext, input: PaymentInput) -> AppResult<Payment> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| Invoice::record_payment(conn, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Credit all or part of an invoice, administrators only. Its PDF is rendered in the background.")]
+    async fn issue_credit_note(context: &GraphQLContext, input: CreditNoteInput) -> AppResult<CreditNote> {
+        let actor = context.require_administrator().await?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let credit_note = context
+            .run(move |conn| {
+                conn.transaction::<CreditNote, AppError, _>(|| {
+                    let credit_note = Invoice::issue_credit_note(conn, input, Some(actor))?;
+                    credit_note.enqueue_document(conn, max_attempts, Some(actor))?;
+
+                    Ok(credit_note)
+                })
+            })
+            .await?;
+
+        crate::jobs::wake();
+        Ok(credit_note)
+    }
+
+    #[graphql(description = "Render again the PDF of an invoice, in the background")]
+    async fn generate_document(context: &GraphQLContext, invoice_id: i32) -> AppResult<Job> {
+        let actor = context.require_user()?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let job = context
+            .run(move |conn| Invoice::find(conn, invoice_id)?.enqueue_document(conn, max_attempts, Some(actor)))
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+
+    #[graphql(description = "Render again the PDF of a credit note, in the background")]
+    async fn generate_credit_note_document(context: &GraphQLContext, credit_note_id: i32) -> AppResult<Job> {
+        let actor = context.require_user()?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let job = context
+            .run(move |conn| CreditNote::find(conn, credit_note_id)?.enqueue_document(conn, max_attempts, Some(actor)))
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::models::clients::{AddressInput, ClientInput};
+    use crate::models::worksite_versions::NewWorksiteRevision;
+    use crate::models::worksites::WorksiteContent;
+    use crate::schema::worksite_revisions;
+
+    // The invoices are created in the database of DATABASE_URL, these tests
+    // only run with `cargo test -- --ignored`
+    fn connection() -> PgConnection {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+
+        PgConnection::establish(&url).expect("cannot connect to DATABASE_URL")
+    }
+
+    fn billing() -> BillingConfig {
+        BillingConfig {
+            company_name: "Amiantes test".to_string(),
+            company_siret: "12345678900011".to_string(),
+            company_vat_number: "FR12123456789".to_string(),
+            ..BillingConfig::default()
+        }
+    }
+
+    // A new worksite with its report issued. Invoices are never deleted, so
+    // every run invoices a worksite of its own.
+    fn issued_worksite(conn: &PgConnection) -> AppResult<i32> {
+        let client = Client::create(
+            conn,
+            ClientInput {
+                name: "Invoice test".to_string(),
+                address: Some(AddressInput {
+                    street: "rue des Lilas".to_string(),
+                    street_number: 12,
+                    postal_code: Some("75019".to_string()),
+                    city: Some("Paris".to_string()),
+                }),
+                interlocutors: None,
+            },
+            None,
+        )?;
+        let content = WorksiteContent {
+            worksite_information: None,
+            leads: None,
+            asbestos: None,
+            lead_sessions: None,
+        };
+        let worksite = Worksite::create(conn, client.id, content, None)?;
+        let version = WorksiteVersion::latest(conn, worksite.id)?;
+
+        diesel::insert_into(worksite_revisions::table)
+            .values(NewWorksiteRevision {
+                worksite_id: &worksite.id,
+                version_id: &version.id,
+                revision: &0,
+                issued_by: None,
+                issued_at: &chrono::offset::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+        Ok(worksite.id)
+    }
+
+    // Both creations read the worksite uninvoiced without the lock, the index
+    // would still reject the second one
+    #[test]
+    #[ignore = "needs DATABASE_URL"]
+    fn a_worksite_is_invoiced_once() {
+        let worksite_id = issued_worksite(&connection()).expect("cannot create the worksite");
+
+        let creations: Vec<_> = (0..2)
+            .map(|_| {
+                thread::spawn(move || {
+                    let input = InvoiceInput {
+                        worksite_id,
+                        extra_lines: Some(vec![InvoiceLineInput {
+                            label: "Repérage".to_string(),
+                            quantity: 1,
+                            unit_price: 30_000,
+                        }]),
+                    };
+                    Invoice::create(&connection(), input, &billing(), None)
+                })
+            })
+            .collect();
+        let results: Vec<AppResult<Invoice>> = creations.into_iter().map(|creation| creation.join().unwrap()).collect();
+
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1, "{:?}", results);
+        assert!(results.iter().any(|result| matches!(result, Err(AppError::Conflict(_)))), "{:?}", results);
+    }
+
+    #[test]
+    fn numbers_are_the_series_the_year_and_five_digits() {
+        assert_eq!(number(SERIES_INVOICE, 2026, 2), "F2026-00002");
+        assert_eq!(number(SERIES_CREDIT_NOTE, 2026, 12_345), "AV2026-12345");
+        assert_eq!(number(SERIES_INVOICE, 2027, 123_456), "F2027-123456");
+    }
+
+    // Rolled back, the numbers taken are given again by the next run
+    #[test]
+    #[ignore = "needs DATABASE_URL"]
+    fn numbers_follow_each_other_within_a_year() {
+        let conn = connection();
+
+        conn.test_transaction::<_, diesel::result::Error, _>(|| {
+            let first = next_number(&conn, SERIES_INVOICE, 1999)?;
+            let second = next_number(&conn, SERIES_INVOICE, 1999)?;
+
+            assert_eq!(first, "F1999-00001");
+            assert_eq!(second, "F1999-00002");
+            assert_eq!(next_number(&conn, SERIES_CREDIT_NOTE, 1999)?, "AV1999-00001");
+            assert_eq!(next_number(&conn, SERIES_INVOICE, 2000)?, "F2000-00001");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn payments_and_credit_notes_decide_the_status() {
+        assert_eq!(status_of(12_000, 0, 0), STATUS_UNPAID);
+        assert_eq!(status_of(12_000, 1, 0), STATUS_PARTIALLY_PAID);
+        assert_eq!(status_of(12_000, 11_999, 0), STATUS_PARTIALLY_PAID);
+        assert_eq!(status_of(12_000, 12_000, 0), STATUS_PAID);
+        // Overpaid by a transfer, still paid
+        assert_eq!(status_of(12_000, 15_000, 0), STATUS_PAID);
+    }
+
+    #[test]
+    fn a_credit_note_settles_what_is_left_to_pay() {
+        // Partly credited, the rest unpaid
+        assert_eq!(status_of(12_000, 0, 2_000), STATUS_UNPAID);
+        assert_eq!(status_of(12_000, 4_000, 2_000), STATUS_PARTIALLY_PAID);
+        // Credited down to what was paid
+        assert_eq!(status_of(12_000, 10_000, 2_000), STATUS_PAID);
+        // Fully credited cancels the invoice, whatever was paid
+        assert_eq!(status_of(12_000, 0, 12_000), STATUS_CREDITED);
+        assert_eq!(status_of(12_000, 5_000, 12_000), STATUS_CREDITED);
+    }
+}
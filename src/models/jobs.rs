@@ -0,0 +1,439 @@
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::sql_types::{Text, Timestamp};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::{DateRange, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::models::users::User;
+use crate::models::worksite_versions::WorksiteRevision;
+use crate::schema::jobs;
+use crate::validation::{validate_argument, Validator};
+
+// Kinds of background work, see `crate::jobs::perform`
+pub const KIND_WORKSITE_REPORT: &str = "worksite_report";
+pub const KIND_CLIENT_EXPORT: &str = "client_export";
+pub const KIND_PURGE_SESSIONS: &str = "purge_sessions";
+pub const KIND_QUOTE_DOCUMENT: &str = "quote_document";
+pub const KIND_INVOICE_DOCUMENT: &str = "invoice_document";
+pub const KIND_CREDIT_NOTE_DOCUMENT: &str = "credit_note_document";
+pub const KIND_REPORT_EMAIL: &str = "report_email";
+pub const KIND_CLIENT_IMPORT: &str = "client_import";
+
+// Lifecycle of a job: pending -> running -> succeeded, or back to pending
+// until its attempts are exhausted and it is left dead for an administrator
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_SUCCEEDED: &str = "succeeded";
+pub const STATUS_DEAD: &str = "dead";
+
+// A worker renews the lease of the job it runs every `HEARTBEAT_SECONDS`. A
+// job whose lease wasn't renewed for `LEASE_MINUTES` belongs to a worker
+// that died, it is handed to another one.
+const LEASE_MINUTES: i64 = 15;
+pub const HEARTBEAT_SECONDS: u64 = 60;
+
+// Retry delays double from 10 seconds, up to an hour
+const BACKOFF_SECONDS: i64 = 10;
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+#[derive(Serialize, Deserialize)]
+pub struct WorksiteReportPayload {
+    pub worksite_id: i32,
+    pub revision: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientExportPayload {
+    pub client_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QuoteDocumentPayload {
+    pub quote_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InvoiceDocumentPayload {
+    pub invoice_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreditNoteDocumentPayload {
+    pub credit_note_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportEmailPayload {
+    pub worksite_id: i32,
+    pub revision: i32,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientImportPayload {
+    pub csv: String,
+}
+
+#[derive(Debug, Serialize, Queryable, QueryableByName, Identifiable)]
+#[table_name = "jobs"]
+pub struct Job {
+    pub id: i32,
+    pub kind: String,
+    pub payload: Value,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub result: Option<Value>,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Long-running work done in the background, poll it until it succeeded or is dead")]
+impl Job {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn kind(&self) -> &str {
+        self.kind.as_str()
+    }
+
+    #[graphql(description = "pending, running, succeeded or dead")]
+    fn status(&self) -> &str {
+        self.status.as_str()
+    }
+
+    fn attempts(&self) -> i32 {
+        self.attempts
+    }
+
+    fn max_attempts(&self) -> i32 {
+        self.max_attempts
+    }
+
+    #[graphql(description = "When the job is due, later than its creation while a failed attempt is backing off")]
+    fn run_at(&self) -> DateTime {
+        self.run_at.into()
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    #[graphql(description = "JSON object describing what the job produced, once it succeeded")]
+    fn result(&self) -> Option<String> {
+        self.result.as_ref().map(Value::to_string)
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+
+    fn finished_at(&self) -> Option<DateTime> {
+        self.finished_at.map(DateTime::from)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "jobs"]
+pub struct NewJob<'a> {
+    pub kind: &'a str,
+    pub payload: &'a Value,
+    pub max_attempts: &'a i32,
+    pub run_at: &'a NaiveDateTime,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+impl Job {
+    // Queue a job, due at once. Workers are not woken up here so it can be
+    // called inside a transaction, see `crate::jobs::wake`.
+    pub fn enqueue<T: Serialize>(
+        conn: &PgConnection,
+        kind: &str,
+        payload: &T,
+        max_attempts: i32,
+        created_by: Option<i32>,
+    ) -> AppResult<Job> {
+        let payload = serde_json::to_value(payload)
+            .map_err(|err| AppError::Internal(format!("job payload: {}", err)))?;
+        let now = chrono::offset::Utc::now().naive_utc();
+
+        let new_job: NewJob = NewJob {
+            kind,
+            payload: &payload,
+            max_attempts: &max_attempts,
+            run_at: &now,
+            created_by,
+            created_at: &now,
+        };
+
+        Ok(diesel::insert_into(jobs::table)
+            .values(new_job)
+            .get_result(conn)?)
+    }
+
+    pub fn find(conn: &PgConnection, job_id: i32) -> AppResult<Job> {
+        jobs::table
+            .find(job_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Job".to_string()))
+    }
+
+    // Take the next due job and mark it running. Concurrent workers skip the
+    // rows already locked by each other so a job is never claimed twice.
+    pub fn claim(conn: &PgConnection) -> QueryResult<Option<Job>> {
+        let now = chrono::offset::Utc::now().naive_utc();
+
+        diesel::sql_query(
+            "UPDATE jobs SET status = $3, attempts = attempts + 1, started_at = $1 \
+             WHERE id = (\
+                 SELECT id FROM jobs \
+                 WHERE (status = $4 AND run_at <= $1) OR (status = $3 AND started_at < $2) \
+                 ORDER BY run_at, id \
+                 LIMIT 1 \
+                 FOR UPDATE SKIP LOCKED\
+             ) \
+             RETURNING *",
+        )
+        .bind::<Timestamp, _>(now)
+        .bind::<Timestamp, _>(now - Duration::minutes(LEASE_MINUTES))
+        .bind::<Text, _>(STATUS_RUNNING)
+        .bind::<Text, _>(STATUS_PENDING)
+        .get_result(conn)
+        .optional()
+    }
+
+    // Extend the lease of a running job, `started_at` is when it was last
+    // renewed
+    pub fn renew(conn: &PgConnection, job_id: i32) -> QueryResult<usize> {
+        diesel::update(jobs::table.find(job_id).filter(jobs::status.eq(STATUS_RUNNING)))
+            .set(jobs::started_at.eq(chrono::offset::Utc::now().naive_utc()))
+            .execute(conn)
+    }
+
+    pub fn succeed(&self, conn: &PgConnection, outcome: &Value) -> QueryResult<Job> {
+        diesel::update(self)
+            .set((
+                jobs::status.eq(STATUS_SUCCEEDED),
+                jobs::result.eq(outcome),
+                jobs::last_error.eq(None::<String>),
+                jobs::finished_at.eq(chrono::offset::Utc::now().naive_utc()),
+            ))
+            .get_result(conn)
+    }
+
+    // Schedule the next attempt with an exponential backoff, or give up when
+    // the error won't go away by retrying or no attempt is left
+    pub fn fail(&self, conn: &PgConnection, error: &str, permanent: bool) -> QueryResult<Job> {
+        let now = chrono::offset::Utc::now().naive_utc();
+
+        if permanent || self.attempts >= self.max_attempts {
+            return diesel::update(self)
+                .set((
+                    jobs::status.eq(STATUS_DEAD),
+                    jobs::last_error.eq(error),
+                    jobs::finished_at.eq(now),
+                ))
+                .get_result(conn);
+        }
+
+        let delay = BACKOFF_SECONDS
+            .saturating_mul(1 << (self.attempts - 1).clamp(0, 20))
+            .min(MAX_BACKOFF_SECONDS);
+
+        diesel::update(self)
+            .set((
+                jobs::status.eq(STATUS_PENDING),
+                jobs::last_error.eq(error),
+                jobs::run_at.eq(now + Duration::seconds(delay)),
+                jobs::started_at.eq(None::<NaiveDateTime>),
+            ))
+            .get_result(conn)
+    }
+
+    // Give a dead job a fresh set of attempts
+    pub fn retry(conn: &PgConnection, job_id: i32) -> AppResult<Job> {
+        let job = Job::find(conn, job_id)?;
+
+        if job.status != STATUS_DEAD {
+            return Err(AppError::Conflict(format!("job {} is {}, only dead jobs can be retried", job.id, job.status)));
+        }
+
+        Ok(diesel::update(&job)
+            .set((
+                jobs::status.eq(STATUS_PENDING),
+                jobs::attempts.eq(0),
+                jobs::run_at.eq(chrono::offset::Utc::now().naive_utc()),
+                jobs::started_at.eq(None::<NaiveDateTime>),
+                jobs::finished_at.eq(None::<NaiveDateTime>),
+            ))
+            .get_result(conn)?)
+    }
+}
+
+pub struct JobQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl JobQuery {
+    #[graphql(description = "Fetch a job, the jobs of other users are only visible to administrators")]
+    async fn fetch(context: &GraphQLContext, job_id: i32) -> AppResult<Job> {
+        let user_id = context.require_user()?.id;
+
+        let job = context.run(move |conn| Job::find(conn, job_id)).await?;
+
+        if job.created_by != Some(user_id) {
+            context.require_administrator().await?;
+        }
+
+        Ok(job)
+    }
+
+    #[graphql(description = "Fetch the latest jobs, optionally of a status or created in a range of days, administrators only")]
+    async fn fetch_all(
+        context: &GraphQLContext,
+        status: Option<String>,
+        offset: i32,
+        created_between: Option<DateRange>,
+    ) -> AppResult<Vec<Job>> {
+        context.require_administrator().await?;
+        validate_argument("createdBetween", &created_between)?;
+
+        context
+            .run(move |conn| {
+                let mut query = jobs::table.into_boxed();
+
+                if let Some(status) = status {
+                    query = query.filter(jobs::status.eq(status));
+                }
+                if let Some(range) = created_between {
+                    let (from, to) = range.bounds();
+
+                    if let Some(from) = from {
+                        query = query.filter(jobs::created_at.ge(from));
+                    }
+                    if let Some(to) = to {
+                        query = query.filter(jobs::created_at.lt(to));
+                    }
+                }
+
+                Ok(query
+                    .order(jobs::id.desc())
+                    .limit(20)
+                    .offset(offset.into())
+                    .load::<Job>(conn)?)
+            })
+            .await
+    }
+}
+
+pub struct JobMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl JobMutation {
+    #[graphql(description = "Export a client and its worksites to a JSON file in the background")]
+    async fn enqueue_client_export(context: &GraphQLContext, client_id: i32) -> AppResult<Job> {
+        use crate::schema::clients;
+
+        let user_id = context.require_user()?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let job = context
+            .run(move |conn| {
+                clients::table
+                    .find(client_id)
+                    .select(clients::id)
+                    .first::<i32>(conn)
+                    .optional()?
+                    .ok_or_else(|| AppError::NotFound("Client".to_string()))?;
+
+                Job::enqueue(conn, KIND_CLIENT_EXPORT, &ClientExportPayload { client_id }, max_attempts, Some(user_id))
+            })
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+
+    #[graphql(description = "Email the report of an issued revision, as a PDF attachment, in the background")]
+    async fn enqueue_report_email(context: &GraphQLContext, worksite_id: i32, revision: i32, to: String) -> AppResult<Job> {
+        let user_id = context.require_user()?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let mut validator = Validator::default();
+        validator.email("to", &Some(to.clone()));
+        validator.finish()?;
+
+        if context.config.mail.from.is_none() {
+            return Err(AppError::Unavailable("emails are not configured, set MAIL_FROM".to_string()));
+        }
+
+        let job = context
+            .run(move |conn| {
+                WorksiteRevision::find(conn, worksite_id, revision)
+                    .optional()?
+                    .ok_or_else(|| AppError::NotFound("Revision".to_string()))?;
+
+                let payload = ReportEmailPayload { worksite_id, revision, to };
+                Job::enqueue(conn, KIND_REPORT_EMAIL, &payload, max_attempts, Some(user_id))
+            })
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+
+    #[graphql(description = "Create the clients of a spreadsheet saved as CSV in the background. Its header names the columns: name, street_number, street, postal_code, city, and optionally interlocutor_name, interlocutor_position, interlocutor_email and interlocutor_phone. Every line is checked before the job is queued.")]
+    async fn enqueue_client_import(context: &GraphQLContext, csv: String) -> AppResult<Job> {
+        let user_id = context.require_user()?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        crate::exports::read_clients_csv(&csv)?;
+
+        let job = context
+            .run(move |conn| Job::enqueue(conn, KIND_CLIENT_IMPORT, &ClientImportPayload { csv }, max_attempts, Some(user_id)))
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+
+    #[graphql(description = "Delete the expired sessions, administrators only")]
+    async fn enqueue_session_purge(context: &GraphQLContext) -> AppResult<Job> {
+        let user_id = context.require_administrator().await?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let job = context
+            .run(move |conn| Job::enqueue(conn, KIND_PURGE_SESSIONS, &Value::Null, max_attempts, Some(user_id)))
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+
+    #[graphql(description = "Run again a dead job, administrators only")]
+    async fn retry(context: &GraphQLContext, job_id: i32) -> AppResult<Job> {
+        context.require_administrator().await?;
+
+        let job = context.run(move |conn| Job::retry(conn, job_id)).await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+}
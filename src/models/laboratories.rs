@@ -0,0 +1,586 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel_json::Json;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::{self, Date, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::events::{self, WorksiteEvent, EVENT_LAB_RESULTS};
+use crate::models::audit_logs::{
+    AuditLog, ENTITY_ANALYSIS_ORDER, ENTITY_LABORATORY, OPERATION_CREATE, OPERATION_UPDATE,
+};
+use crate::models::users::User;
+use crate::models::worksites::Worksite;
+use crate::schema::{analysis_orders, laboratories};
+use crate::validation::{validate, Validate, Validator};
+
+// Asbestos analyses: polarised light microscopy, analytical transmission
+// and scanning electron microscopy
+pub const ANALYSIS_TYPES: &[&str] = &["molp", "meta", "meba"];
+
+pub const URGENCY_STANDARD: &str = "standard";
+pub const URGENCY_URGENT: &str = "urgent";
+pub const URGENCIES: &[&str] = &[URGENCY_STANDARD, URGENCY_URGENT];
+
+// An order is sent until its results are received, or cancelled
+pub const STATUS_SENT: &str = "sent";
+pub const STATUS_RECEIVED: &str = "received";
+pub const STATUS_CANCELLED: &str = "cancelled";
+
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+#[table_name = "laboratories"]
+pub struct Laboratory {
+    pub id: i32,
+    pub name: String,
+    pub accreditation_number: String,
+    pub contact_name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub turnaround_days: i32,
+    pub urgent_turnaround_days: i32,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Laboratory analysing the asbestos samples")]
+impl Laboratory {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    #[graphql(description = "COFRAC accreditation")]
+    fn accreditation_number(&self) -> &str {
+        self.accreditation_number.as_str()
+    }
+
+    fn contact_name(&self) -> Option<&str> {
+        self.contact_name.as_deref()
+    }
+
+    fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    fn phone(&self) -> Option<&str> {
+        self.phone.as_deref()
+    }
+
+    #[graphql(description = "Days between sending samples and their results")]
+    fn turnaround_days(&self) -> i32 {
+        self.turnaround_days
+    }
+
+    #[graphql(description = "Days for the results of urgent orders")]
+    fn urgent_turnaround_days(&self) -> i32 {
+        self.urgent_turnaround_days
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "laboratories"]
+pub struct NewLaboratory<'a> {
+    pub name: &'a str,
+    pub accreditation_number: &'a str,
+    pub contact_name: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub phone: Option<&'a str>,
+    pub turnaround_days: &'a i32,
+    pub urgent_turnaround_days: &'a i32,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct AnalysisOrder {
+    pub id: i32,
+    pub laboratory_id: i32,
+    pub worksite_id: i32,
+    pub samples: Json<Vec<String>>,
+    pub analysis_type: String,
+    pub urgency: String,
+    pub sent_on: NaiveDate,
+    pub expected_on: NaiveDate,
+    pub received_on: Option<NaiveDate>,
+    pub status: String,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Samples of a worksite sent together to a laboratory")]
+impl AnalysisOrder {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn laboratory_id(&self) -> i32 {
+        self.laboratory_id
+    }
+
+    async fn laboratory(&self, context: &GraphQLContext) -> AppResult<Laboratory> {
+        let laboratory_id = self.laboratory_id;
+
+        context.run(move |conn| Laboratory::find(conn, laboratory_id)).await
+    }
+
+    fn worksite_id(&self) -> i32 {
+        self.worksite_id
+    }
+
+    #[graphql(description = "Codes of the samples, as in `sampling` of the worksite")]
+    fn samples(&self) -> &Vec<String> {
+        &self.samples.0
+    }
+
+    #[graphql(description = "molp, meta or meba")]
+    fn analysis_type(&self) -> &str {
+        self.analysis_type.as_str()
+    }
+
+    #[graphql(description = "standard or urgent")]
+    fn urgency(&self) -> &str {
+        self.urgency.as_str()
+    }
+
+    fn sent_on(&self) -> Date {
+        Date(self.sent_on)
+    }
+
+    #[graphql(description = "Day the results are due, after the turnaround of the laboratory")]
+    fn expected_on(&self) -> Date {
+        Date(self.expected_on)
+    }
+
+    fn received_on(&self) -> Option<Date> {
+        self.received_on.map(Date)
+    }
+
+    #[graphql(description = "sent, received or cancelled")]
+    fn status(&self) -> &str {
+        self.status.as_str()
+    }
+
+    #[graphql(description = "Still waiting for results past the expected day")]
+    fn overdue(&self) -> bool {
+        self.late_by(dates::today()) > 0
+    }
+
+    #[graphql(description = "Days past the expected day while the results are awaited")]
+    fn days_late(&self) -> i32 {
+        self.late_by(dates::today()) as i32
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "analysis_orders"]
+pub struct NewAnalysisOrder<'a> {
+    pub laboratory_id: &'a i32,
+    pub worksite_id: &'a i32,
+    pub samples: &'a Json<Vec<String>>,
+    pub analysis_type: &'a str,
+    pub urgency: &'a str,
+    pub sent_on: &'a NaiveDate,
+    pub expected_on: &'a NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct LaboratoryInput {
+    pub name: String,
+    #[graphql(description = "COFRAC accreditation")]
+    pub accreditation_number: String,
+    pub contact_name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub turnaround_days: i32,
+    pub urgent_turnaround_days: i32,
+}
+
+impl Validate for LaboratoryInput {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name)
+            .length("name", &self.name, 1, 255)
+            .not_blank("accreditationNumber", &self.accreditation_number)
+            .length("accreditationNumber", &self.accreditation_number, 1, 64)
+            .email("email", &self.email)
+            .phone("phone", &self.phone)
+            .range("turnaroundDays", self.turnaround_days, 1, 90)
+            .range("urgentTurnaroundDays", self.urgent_turnaround_days, 0, self.turnaround_days.max(0));
+
+        if let Some(contact_name) = &self.contact_name {
+            v.length("contactName", contact_name, 0, 255);
+        }
+    }
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct AnalysisOrderInput {
+    pub laboratory_id: i32,
+    pub worksite_id: i32,
+    #[graphql(description = "Codes of the samples, as in `sampling` of the worksite")]
+    pub samples: Vec<String>,
+    #[graphql(description = "molp, meta or meba")]
+    pub analysis_type: String,
+    #[graphql(description = "standard or urgent")]
+    pub urgency: String,
+    pub sent_on: Date,
+}
+
+impl Validate for AnalysisOrderInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("laboratoryId", self.laboratory_id, 1, i32::MAX)
+            .range("worksiteId", self.worksite_id, 1, i32::MAX)
+            .check("samples", !self.samples.is_empty(), "Must not be empty")
+            .one_of("analysisType", &self.analysis_type, ANALYSIS_TYPES)
+            .one_of("urgency", &self.urgency, URGENCIES)
+            .check("sentOn", self.sent_on.0 <= dates::today(), "Must not be in the future");
+
+        for (index, sample) in self.samples.iter().enumerate() {
+            v.not_blank(&format!("samples.{}", index), sample);
+        }
+    }
+}
+
+impl Laboratory {
+    pub fn find(conn: &PgConnection, laboratory_id: i32) -> AppResult<Laboratory> {
+        laboratories::table
+            .find(laboratory_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Laboratory".to_string()))
+    }
+
+    // Results are expected after this turnaround
+    pub fn turnaround(&self, urgency: &str) -> Duration {
+        let days = if urgency == URGENCY_URGENT { self.urgent_turnaround_days } else { self.turnaround_days };
+
+        Duration::days(days.into())
+    }
+
+    pub fn create(conn: &PgConnection, input: LaboratoryInput, actor: Option<i32>) -> AppResult<Laboratory> {
+        validate(&input)?;
+
+        let new_laboratory: NewLaboratory = NewLaboratory {
+            name: input.name.trim(),
+            accreditation_number: input.accreditation_number.trim(),
+            contact_name: input.contact_name.as_deref().map(str::trim),
+            email: input.email.as_deref().map(str::trim),
+            phone: input.phone.as_deref().map(str::trim),
+            turnaround_days: &input.turnaround_days,
+            urgent_turnaround_days: &input.urgent_turnaround_days,
+            created_by: actor,
+            created_at: &chrono::offset::Utc::now().naive_utc(),
+        };
+
+        conn.transaction::<Laboratory, AppError, _>(|| {
+            let laboratory: Laboratory = diesel::insert_into(laboratories::table)
+                .values(new_laboratory)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_LABORATORY, laboratory.id, OPERATION_CREATE, None, Some(&laboratory))?;
+
+            Ok(laboratory)
+        })
+    }
+
+    // Orders already sent keep the day they were expected on
+    pub fn update(conn: &PgConnection, laboratory_id: i32, input: LaboratoryInput, actor: Option<i32>) -> AppResult<Laboratory> {
+        validate(&input)?;
+
+        conn.transaction::<Laboratory, AppError, _>(|| {
+            let previous = Laboratory::find(conn, laboratory_id)?;
+
+            let laboratory: Laboratory = diesel::update(laboratories::table.find(previous.id))
+                .set((
+                    laboratories::name.eq(input.name.trim()),
+                    laboratories::accreditation_number.eq(input.accreditation_number.trim()),
+                    laboratories::contact_name.eq(input.contact_name.as_deref().map(str::trim)),
+                    laboratories::email.eq(input.email.as_deref().map(str::trim)),
+                    laboratories::phone.eq(input.phone.as_deref().map(str::trim)),
+                    laboratories::turnaround_days.eq(input.turnaround_days),
+                    laboratories::urgent_turnaround_days.eq(input.urgent_turnaround_days),
+                ))
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_LABORATORY, laboratory.id, OPERATION_UPDATE, Some(&previous), Some(&laboratory))?;
+
+            Ok(laboratory)
+        })
+    }
+}
+
+impl AnalysisOrder {
+    // Days past the expected day, 0 when the results are not late
+    pub fn late_by(&self, today: NaiveDate) -> i64 {
+        if self.status != STATUS_SENT {
+            return 0;
+        }
+
+        (today - self.expected_on).num_days().max(0)
+    }
+
+    pub fn find(conn: &PgConnection, order_id: i32) -> AppResult<AnalysisOrder> {
+        analysis_orders::table
+            .find(order_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Analysis order".to_string()))
+    }
+
+    // Send samples of a worksite to a laboratory. A sample is in one order
+    // at a time, unless that order is cancelled.
+    pub fn create(conn: &PgConnection, input: AnalysisOrderInput, actor: Option<i32>) -> AppResult<AnalysisOrder> {
+        validate(&input)?;
+
+        conn.transaction::<AnalysisOrder, AppError, _>(|| {
+            let laboratory = Laboratory::find(conn, input.laboratory_id)?;
+            let worksite = Worksite::find(conn, input.worksite_id)?;
+
+            let mut samples: Vec<String> = input.samples.iter().map(|sample| sample.trim().to_string()).collect();
+            samples.sort_unstable();
+            samples.dedup();
+
+            for sample in &samples {
+                let known = worksite
+                    .worksite
+                    .asbestos
+                    .iter()
+                    .flatten()
+                    .any(|entry| entry.sampling.trim() == sample);
+
+                if !known {
+                    return Err(AppError::invalid(
+                        "samples",
+                        &format!("Sample {} is not in worksite {}", sample, worksite.id),
+                    ));
+                }
+            }
+
+            let open: Vec<AnalysisOrder> = analysis_orders::table
+                .filter(analysis_orders::worksite_id.eq(worksite.id))
+                .filter(analysis_orders::status.ne(STATUS_CANCELLED))
+                .load(conn)?;
+            for order in &open {
+                if let Some(sample) = samples.iter().find(|sample| order.samples.0.contains(sample)) {
+                    return Err(AppError::Conflict(format!("sample {} is already in analysis order {}", sample, order.id)));
+                }
+            }
+
+            let sent_on = input.sent_on.0;
+            let new_order: NewAnalysisOrder = NewAnalysisOrder {
+                laboratory_id: &laboratory.id,
+                worksite_id: &worksite.id,
+                samples: &Json::new(samples),
+                analysis_type: &input.analysis_type,
+                urgency: &input.urgency,
+                sent_on: &sent_on,
+                expected_on: &(sent_on + laboratory.turnaround(&input.urgency)),
+                created_by: actor,
+                created_at: &chrono::offset::Utc::now().naive_utc(),
+            };
+
+            let order: AnalysisOrder = diesel::insert_into(analysis_orders::table)
+                .values(new_order)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_ANALYSIS_ORDER, order.id, OPERATION_CREATE, None, Some(&order))?;
+
+            Ok(order)
+        })
+    }
+
+    // Close an order awaiting its results, received or cancelled
+    fn close(conn: &PgConnection, order_id: i32, status: &str, received_on: Option<NaiveDate>, actor: Option<i32>) -> AppResult<AnalysisOrder> {
+        conn.transaction::<AnalysisOrder, AppError, _>(|| {
+            let previous: AnalysisOrder = analysis_orders::table
+                .find(order_id)
+                .for_update()
+                .first(conn)
+                .optional()?
+                .ok_or_else(|| AppError::NotFound("Analysis order".to_string()))?;
+
+            if previous.status != STATUS_SENT {
+                return Err(AppError::Conflict(format!("analysis order {} is {}", previous.id, previous.status)));
+            }
+            if let Some(received_on) = received_on {
+                if received_on < previous.sent_on {
+                    return Err(AppError::invalid("receivedOn", "Must not be before the samples were sent"));
+                }
+            }
+
+            let order: AnalysisOrder = diesel::update(analysis_orders::table.find(previous.id))
+                .set((analysis_orders::status.eq(status), analysis_orders::received_on.eq(received_on)))
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_ANALYSIS_ORDER, order.id, OPERATION_UPDATE, Some(&previous), Some(&order))?;
+
+            Ok(order)
+        })
+    }
+
+    pub fn receive(conn: &PgConnection, order_id: i32, received_on: NaiveDate, actor: Option<i32>) -> AppResult<AnalysisOrder> {
+        if received_on > dates::today() {
+            return Err(AppError::invalid("receivedOn", "Must not be in the future"));
+        }
+
+        AnalysisOrder::close(conn, order_id, STATUS_RECEIVED, Some(received_on), actor)
+    }
+
+    pub fn cancel(conn: &PgConnection, order_id: i32, actor: Option<i32>) -> AppResult<AnalysisOrder> {
+        AnalysisOrder::close(conn, order_id, STATUS_CANCELLED, None, actor)
+    }
+}
+
+// Analysis orders of a worksite, latest first
+pub fn orders_of(conn: &PgConnection, worksite_id: i32) -> AppResult<Vec<AnalysisOrder>> {
+    Ok(analysis_orders::table
+        .filter(analysis_orders::worksite_id.eq(worksite_id))
+        .order(analysis_orders::id.desc())
+        .load(conn)?)
+}
+
+pub struct LaboratoryQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl LaboratoryQuery {
+    #[graphql(description = "Fetch a laboratory")]
+    async fn fetch(context: &GraphQLContext, laboratory_id: i32) -> AppResult<Laboratory> {
+        context.require_user()?;
+
+        context.run(move |conn| Laboratory::find(conn, laboratory_id)).await
+    }
+
+    #[graphql(description = "Fetch the laboratories by name")]
+    async fn fetch_all(context: &GraphQLContext) -> AppResult<Vec<Laboratory>> {
+        context.require_user()?;
+
+        context
+            .run(move |conn| Ok(laboratories::table.order(laboratories::name.asc()).load::<Laboratory>(conn)?))
+            .await
+    }
+
+    #[graphql(description = "Fetch an analysis order")]
+    async fn fetch_order(context: &GraphQLContext, order_id: i32) -> AppResult<AnalysisOrder> {
+        context.require_user()?;
+
+        context.run(move |conn| AnalysisOrder::find(conn, order_id)).await
+    }
+
+    #[graphql(description = "Fetch the latest analysis orders, optionally of a laboratory, of a status or overdue only, the most late first")]
+    async fn fetch_orders(
+        context: &GraphQLContext,
+        laboratory_id: Option<i32>,
+        status: Option<String>,
+        overdue: Option<bool>,
+        offset: i32,
+    ) -> AppResult<Vec<AnalysisOrder>> {
+        context.require_user()?;
+
+        if offset < 0 {
+            return Err(AppError::invalid("offset", "Must not be negative"));
+        }
+
+        context
+            .run(move |conn| {
+                let mut query = analysis_orders::table.into_boxed();
+
+                if let Some(laboratory_id) = laboratory_id {
+                    query = query.filter(analysis_orders::laboratory_id.eq(laboratory_id));
+                }
+                if let Some(status) = status {
+                    query = query.filter(analysis_orders::status.eq(status));
+                }
+                if overdue == Some(true) {
+                    query = query
+                        .filter(analysis_orders::status.eq(STATUS_SENT))
+                        .filter(analysis_orders::expected_on.lt(dates::today()))
+                        .order(analysis_orders::expected_on.asc());
+                }
+
+                Ok(query
+                    .then_order_by(analysis_orders::id.desc())
+                    .limit(20)
+                    .offset(offset.into())
+                    .load::<AnalysisOrder>(conn)?)
+            })
+            .await
+    }
+}
+
+pub struct LaboratoryMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl LaboratoryMutation {
+    #[graphql(description = "Register a laboratory, administrators only")]
+    async fn create(context: &GraphQLContext, input: LaboratoryInput) -> AppResult<Laboratory> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| Laboratory::create(conn, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Update a laboratory, administrators only. Orders already sent keep their expected day.")]
+    async fn update(context: &GraphQLContext, laboratory_id: i32, input: LaboratoryInput) -> AppResult<Laboratory> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| Laboratory::update(conn, laboratory_id, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Send samples of a worksite to a laboratory, their results are expected after its turnaround")]
+    async fn create_order(context: &GraphQLContext, input: AnalysisOrderInput) -> AppResult<AnalysisOrder> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| AnalysisOrder::create(conn, input, Some(actor))).await
+    }
+
+    #[graphql(description = "Record that the results of an order were received")]
+    async fn receive_order(context: &GraphQLContext, order_id: i32, received_on: Date) -> AppResult<AnalysisOrder> {
+        let actor = context.require_user()?.id;
+
+        let (order, worksite) = context
+            .run(move |conn| {
+                let order = AnalysisOrder::receive(conn, order_id, received_on.0, Some(actor))?;
+                let worksite = Worksite::find(conn, order.worksite_id)?;
+
+                Ok((order, worksite))
+            })
+            .await?;
+
+        events::publish(WorksiteEvent::new(EVENT_LAB_RESULTS, &worksite, None));
+        Ok(order)
+    }
+
+    #[graphql(description = "Cancel an order awaiting its results, its samples can be ordered again")]
+    async fn cancel_order(context: &GraphQLContext, order_id: i32) -> AppResult<AnalysisOrder> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| AnalysisOrder::cancel(conn, order_id, Some(actor))).await
+    }
+}
@@ -1,3 +1,14 @@
 pub mod worksites;
 pub mod users;
-pub mod clients;
\ No newline at end of file
+pub mod clients;
+pub mod sessions;
+pub mod audit_logs;
+pub mod worksite_versions;
+pub mod jobs;
+pub mod price_grids;
+pub mod quotes;
+pub mod invoices;
+pub mod report_signatures;
+pub mod certifications;
+pub mod instruments;
+pub mod laboratories;
@@ -0,0 +1,244 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::{Date, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::missions::MissionType;
+use crate::models::users::User;
+use crate::schema::price_grids;
+use crate::validation::{validate, Validate, Validator};
+
+// Largest price of a grid, 100 000 €
+const MAX_PRICE: i32 = 10_000_000;
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct PriceGrid {
+    pub id: i32,
+    pub mission_type: String,
+    pub base_price: i32,
+    pub included_area: f64,
+    pub area_step: f64,
+    pub area_step_price: i32,
+    pub included_samples: i32,
+    pub sample_price: i32,
+    pub included_distance: i32,
+    pub distance_price: i32,
+    pub minimum_price: i32,
+    pub valid_from: NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Prices of a mission type from a day on, amounts in euro cents excluding tax")]
+impl PriceGrid {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn mission_type(&self) -> AppResult<MissionType> {
+        self.mission()
+    }
+
+    #[graphql(description = "Price of the mission up to the included area, samples and distance")]
+    fn base_price(&self) -> i32 {
+        self.base_price
+    }
+
+    #[graphql(description = "Square metres covered by the base price")]
+    fn included_area(&self) -> f64 {
+        self.included_area
+    }
+
+    #[graphql(description = "Square metres of each step charged past the included area")]
+    fn area_step(&self) -> f64 {
+        self.area_step
+    }
+
+    fn area_step_price(&self) -> i32 {
+        self.area_step_price
+    }
+
+    fn included_samples(&self) -> i32 {
+        self.included_samples
+    }
+
+    #[graphql(description = "Price of each sample analysed past the included ones")]
+    fn sample_price(&self) -> i32 {
+        self.sample_price
+    }
+
+    #[graphql(description = "Kilometres of travel covered by the base price")]
+    fn included_distance(&self) -> i32 {
+        self.included_distance
+    }
+
+    #[graphql(description = "Price of each kilometre past the included distance")]
+    fn distance_price(&self) -> i32 {
+        self.distance_price
+    }
+
+    #[graphql(description = "Least total of a quote, a line makes up the difference")]
+    fn minimum_price(&self) -> i32 {
+        self.minimum_price
+    }
+
+    fn valid_from(&self) -> Date {
+        Date(self.valid_from)
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "price_grids"]
+pub struct NewPriceGrid<'a> {
+    pub mission_type: &'a str,
+    pub base_price: &'a i32,
+    pub included_area: &'a f64,
+    pub area_step: &'a f64,
+    pub area_step_price: &'a i32,
+    pub included_samples: &'a i32,
+    pub sample_price: &'a i32,
+    pub included_distance: &'a i32,
+    pub distance_price: &'a i32,
+    pub minimum_price: &'a i32,
+    pub valid_from: &'a NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, GraphQLInputObject)]
+#[graphql(description = "Prices of a mission type, amounts in euro cents excluding tax")]
+pub struct PriceGridInput {
+    pub mission_type: MissionType,
+    pub base_price: i32,
+    pub included_area: f64,
+    pub area_step: f64,
+    pub area_step_price: i32,
+    pub included_samples: i32,
+    pub sample_price: i32,
+    pub included_distance: i32,
+    pub distance_price: i32,
+    pub minimum_price: i32,
+    #[graphql(description = "First day the grid applies, today when not given")]
+    pub valid_from: Option<Date>,
+}
+
+impl Validate for PriceGridInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("basePrice", self.base_price, 0, MAX_PRICE)
+            .range("areaStepPrice", self.area_step_price, 0, MAX_PRICE)
+            .range("includedSamples", self.included_samples, 0, 10_000)
+            .range("samplePrice", self.sample_price, 0, MAX_PRICE)
+            .range("includedDistance", self.included_distance, 0, 10_000)
+            .range("distancePrice", self.distance_price, 0, MAX_PRICE)
+            .range("minimumPrice", self.minimum_price, 0, MAX_PRICE)
+            .between("includedArea", self.included_area, 0.0, 1_000_000.0)
+            .between("areaStep", self.area_step, 1.0, 1_000_000.0);
+    }
+}
+
+impl PriceGrid {
+    pub fn mission(&self) -> AppResult<MissionType> {
+        MissionType::from_code(&self.mission_type)
+            .ok_or_else(|| AppError::Internal(format!("price grid {}: unknown mission type {}", self.id, self.mission_type)))
+    }
+
+    pub fn create(conn: &PgConnection, input: PriceGridInput, actor: Option<i32>) -> AppResult<PriceGrid> {
+        validate(&input)?;
+
+        let now = chrono::offset::Utc::now().naive_utc();
+        let valid_from = input
+            .valid_from
+            .map(|day| day.0)
+            .unwrap_or_else(crate::dates::today);
+
+        let new_grid: NewPriceGrid = NewPriceGrid {
+            mission_type: input.mission_type.code(),
+            base_price: &input.base_price,
+            included_area: &input.included_area,
+            area_step: &input.area_step,
+            area_step_price: &input.area_step_price,
+            included_samples: &input.included_samples,
+            sample_price: &input.sample_price,
+            included_distance: &input.included_distance,
+            distance_price: &input.distance_price,
+            minimum_price: &input.minimum_price,
+            valid_from: &valid_from,
+            created_by: actor,
+            created_at: &now,
+        };
+
+        Ok(diesel::insert_into(price_grids::table)
+            .values(new_grid)
+            .get_result(conn)?)
+    }
+
+    // Grid of a mission type on a day: the latest one started by then, the
+    // last created when several start the same day
+    pub fn in_force(conn: &PgConnection, mission: MissionType, day: NaiveDate) -> AppResult<PriceGrid> {
+        price_grids::table
+            .filter(price_grids::mission_type.eq(mission.code()))
+            .filter(price_grids::valid_from.le(day))
+            .order((price_grids::valid_from.desc(), price_grids::id.desc()))
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("Price grid of {}", mission.label())))
+    }
+}
+
+pub struct PriceGridQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl PriceGridQuery {
+    #[graphql(description = "Fetch the grids of every mission type, or of one, latest first")]
+    async fn fetch_all(context: &GraphQLContext, mission_type: Option<MissionType>) -> AppResult<Vec<PriceGrid>> {
+        context.require_user()?;
+
+        context
+            .run(move |conn| {
+                let mut query = price_grids::table.into_boxed();
+
+                if let Some(mission) = mission_type {
+                    query = query.filter(price_grids::mission_type.eq(mission.code()));
+                }
+
+                Ok(query
+                    .order((price_grids::valid_from.desc(), price_grids::id.desc()))
+                    .load::<PriceGrid>(conn)?)
+            })
+            .await
+    }
+
+    #[graphql(description = "Fetch the grid of a mission type in force today")]
+    async fn current(context: &GraphQLContext, mission_type: MissionType) -> AppResult<PriceGrid> {
+        context.require_user()?;
+
+        context
+            .run(move |conn| PriceGrid::in_force(conn, mission_type, crate::dates::today()))
+            .await
+    }
+}
+
+pub struct PriceGridMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl PriceGridMutation {
+    #[graphql(description = "Add a grid replacing the previous one of its mission type from its first day, administrators only")]
+    async fn create(context: &GraphQLContext, input: PriceGridInput) -> AppResult<PriceGrid> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| PriceGrid::create(conn, input, Some(actor))).await
+    }
+}
@@ -0,0 +1,538 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel_json::Json;
+use std::ops::Deref;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::config::BillingConfig;
+use crate::dates::{self, Date, DateTime};
+use crate::errors::{AppError, AppResult};
+use crate::events::{self, WorksiteEvent, EVENT_CREATED};
+use crate::missions::{Domain, MissionType};
+use crate::models::audit_logs::{AuditLog, ENTITY_QUOTE, OPERATION_CREATE, OPERATION_UPDATE};
+use crate::models::clients::Client;
+use crate::models::jobs::{Job, QuoteDocumentPayload, KIND_QUOTE_DOCUMENT};
+use crate::models::price_grids::PriceGrid;
+use crate::models::users::User;
+use crate::models::worksites::{Worksite, WorksiteContent, WorksiteInformation};
+use crate::pricing::{self, PriceLine};
+use crate::schema::quotes;
+use crate::validation::{validate, Validate, Validator};
+
+// A quote waits for the answer of the client. Once accepted it can be
+// turned into a worksite, once.
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_ACCEPTED: &str = "accepted";
+pub const STATUS_DECLINED: &str = "declined";
+
+#[derive(Debug, Clone, Serialize, Deserialize, GraphQLObject)]
+#[graphql(description = "What a quote is priced on")]
+pub struct QuoteParameters {
+    pub mission_type: MissionType,
+    #[graphql(description = "Square metres surveyed")]
+    pub surface_area: f64,
+    pub expected_samples: i32,
+    #[graphql(description = "Kilometres from the office to the property")]
+    pub distance: i32,
+    #[graphql(description = "Address of the surveyed property")]
+    pub property_address: String,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct Quote {
+    pub id: i32,
+    pub number: String,
+    pub client_id: i32,
+    pub mission_type: String,
+    pub parameters: Json<QuoteParameters>,
+    pub lines: Json<Vec<PriceLine>>,
+    pub total_excluding_tax: i32,
+    pub vat_rate: i32,
+    pub vat_amount: i32,
+    pub total_including_tax: i32,
+    pub status: String,
+    pub valid_until: NaiveDate,
+    pub worksite_id: Option<i32>,
+    pub document_path: Option<String>,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub edited_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Quote sent to a client before a worksite exists, amounts in euro cents")]
+impl Quote {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[graphql(description = "Number printed on the quote, `D<year>-<sequence>`")]
+    fn number(&self) -> &str {
+        self.number.as_str()
+    }
+
+    fn client_id(&self) -> i32 {
+        self.client_id
+    }
+
+    async fn client(&self, context: &GraphQLContext) -> AppResult<Arc<Client>> {
+        context
+            .loaders
+            .clients
+            .load(self.client_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Client".to_string()))
+    }
+
+    fn parameters(&self) -> &QuoteParameters {
+        self.parameters.deref()
+    }
+
+    fn lines(&self) -> &Vec<PriceLine> {
+        self.lines.deref()
+    }
+
+    fn total_excluding_tax(&self) -> i32 {
+        self.total_excluding_tax
+    }
+
+    #[graphql(description = "Percent")]
+    fn vat_rate(&self) -> f64 {
+        f64::from(self.vat_rate) / 100.0
+    }
+
+    fn vat_amount(&self) -> i32 {
+        self.vat_amount
+    }
+
+    fn total_including_tax(&self) -> i32 {
+        self.total_including_tax
+    }
+
+    #[graphql(description = "pending, accepted or declined")]
+    fn status(&self) -> &str {
+        self.status.as_str()
+    }
+
+    #[graphql(description = "Last day the client may accept the quote")]
+    fn valid_until(&self) -> Date {
+        Date(self.valid_until)
+    }
+
+    #[graphql(description = "Worksite the accepted quote was turned into")]
+    fn worksite_id(&self) -> Option<i32> {
+        self.worksite_id
+    }
+
+    async fn worksite(&self, context: &GraphQLContext) -> AppResult<Option<Arc<Worksite>>> {
+        match self.worksite_id {
+            Some(worksite) => context.loaders.worksites.load(worksite).await,
+            None => Ok(None),
+        }
+    }
+
+    #[graphql(description = "PDF of the quote, set once rendered in the background")]
+    fn document_path(&self) -> Option<&str> {
+        self.document_path.as_deref()
+    }
+
+    async fn created_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.created_by {
+            Some(creator) => context.loaders.users.load(creator).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+
+    fn edited_at(&self) -> DateTime {
+        self.edited_at.into()
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "Price of a mission with the grid in force today, amounts in euro cents")]
+pub struct QuoteEstimate {
+    pub lines: Vec<PriceLine>,
+    pub total_excluding_tax: i32,
+    #[graphql(description = "Percent")]
+    pub vat_rate: f64,
+    pub vat_amount: i32,
+    pub total_including_tax: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "quotes"]
+pub struct NewQuote<'a> {
+    pub number: &'a str,
+    pub client_id: &'a i32,
+    pub mission_type: &'a str,
+    pub parameters: &'a Json<QuoteParameters>,
+    pub lines: &'a Json<Vec<PriceLine>>,
+    pub total_excluding_tax: &'a i32,
+    pub vat_rate: &'a i32,
+    pub vat_amount: &'a i32,
+    pub total_including_tax: &'a i32,
+    pub valid_until: &'a NaiveDate,
+    pub created_by: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+    pub edited_at: &'a NaiveDateTime,
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct QuoteParametersInput {
+    pub mission_type: MissionType,
+    pub surface_area: f64,
+    pub expected_samples: i32,
+    pub distance: i32,
+    pub property_address: String,
+}
+
+impl Validate for QuoteParametersInput {
+    fn rules(&self, v: &mut Validator) {
+        v.between("surfaceArea", self.surface_area, 0.0, 1_000_000.0)
+            .range("expectedSamples", self.expected_samples, 0, 10_000)
+            .range("distance", self.distance, 0, 10_000)
+            .not_blank("propertyAddress", &self.property_address)
+            .length("propertyAddress", &self.property_address, 1, 255);
+    }
+}
+
+impl From<QuoteParametersInput> for QuoteParameters {
+    fn from(f: QuoteParametersInput) -> Self {
+        QuoteParameters {
+            mission_type: f.mission_type,
+            surface_area: f.surface_area,
+            expected_samples: f.expected_samples,
+            distance: f.distance,
+            property_address: f.property_address,
+        }
+    }
+}
+
+#[derive(Debug, GraphQLInputObject)]
+pub struct QuoteInput {
+    pub client_id: i32,
+    pub parameters: QuoteParametersInput,
+}
+
+impl Validate for QuoteInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("clientId", self.client_id, 1, i32::MAX)
+            .child("parameters", &self.parameters);
+    }
+}
+
+// Price parameters with the grid in force today
+pub fn estimate(conn: &PgConnection, parameters: &QuoteParameters, billing: &BillingConfig) -> AppResult<QuoteEstimate> {
+    let grid = PriceGrid::in_force(conn, parameters.mission_type, dates::today())?;
+    let lines = pricing::price(&grid, parameters)?;
+    let totals = pricing::totals(&lines, billing.vat_rate_basis_points())?;
+
+    Ok(QuoteEstimate {
+        lines,
+        total_excluding_tax: totals.excluding_tax,
+        vat_rate: billing.vat_rate,
+        vat_amount: totals.vat_amount,
+        total_including_tax: totals.including_tax,
+    })
+}
+
+impl Quote {
+    pub fn create(conn: &PgConnection, input: QuoteInput, billing: &BillingConfig, actor: Option<i32>) -> AppResult<Quote> {
+        validate(&input)?;
+        if let Some(variable) = billing.missing_seller_mention() {
+            return Err(AppError::Unavailable(format!("billing is not configured, set {}", variable)));
+        }
+
+        let parameters: QuoteParameters = input.parameters.into();
+        let today = dates::today();
+        let now = chrono::offset::Utc::now().naive_utc();
+        let vat_rate = billing.vat_rate_basis_points();
+
+        conn.transaction::<Quote, AppError, _>(|| {
+            crate::schema::clients::table
+                .find(input.client_id)
+                .select(crate::schema::clients::id)
+                .first::<i32>(conn)
+                .optional()?
+                .ok_or_else(|| AppError::NotFound("Client".to_string()))?;
+
+            let grid = PriceGrid::in_force(conn, parameters.mission_type, today)?;
+            let lines = pricing::price(&grid, &parameters)?;
+            let totals = pricing::totals(&lines, vat_rate)?;
+            let number = Quote::next_number(conn, today.year())?;
+
+            let new_quote: NewQuote = NewQuote {
+                number: &number,
+                client_id: &input.client_id,
+                mission_type: parameters.mission_type.code(),
+                parameters: &Json::new(parameters.clone()),
+                lines: &Json::new(lines),
+                total_excluding_tax: &totals.excluding_tax,
+                vat_rate: &vat_rate,
+                vat_amount: &totals.vat_amount,
+                total_including_tax: &totals.including_tax,
+                valid_until: &(today + Duration::days(billing.quote_validity_days)),
+                created_by: actor,
+                created_at: &now,
+                edited_at: &now,
+            };
+
+            let quote: Quote = diesel::insert_into(quotes::table)
+                .values(new_quote)
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_QUOTE, quote.id, OPERATION_CREATE, None, Some(&quote))?;
+
+            Ok(quote)
+        })
+    }
+
+    // Quotes are numbered from 1 every year. Two quotes created at once may
+    // get the same number, the second one fails on the unique constraint.
+    fn next_number(conn: &PgConnection, year: i32) -> AppResult<String> {
+        let prefix = format!("D{}-", year);
+
+        let count: i64 = quotes::table
+            .filter(quotes::number.like(format!("{}%", prefix)))
+            .count()
+            .get_result(conn)?;
+
+        Ok(format!("{}{:04}", prefix, count + 1))
+    }
+
+    pub fn find(conn: &PgConnection, quote_id: i32) -> AppResult<Quote> {
+        quotes::table
+            .find(quote_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Quote".to_string()))
+    }
+
+    pub fn mission(&self) -> AppResult<MissionType> {
+        MissionType::from_code(&self.mission_type)
+            .ok_or_else(|| AppError::Internal(format!("quote {}: unknown mission type {}", self.id, self.mission_type)))
+    }
+
+    // Record the answer of the client, a quote past its validity can only
+    // be declined
+    pub fn answer(conn: &PgConnection, quote_id: i32, accepted: bool, actor: Option<i32>) -> AppResult<Quote> {
+        conn.transaction::<Quote, AppError, _>(|| {
+            let previous = Quote::find(conn, quote_id)?;
+
+            if previous.status != STATUS_PENDING {
+                return Err(AppError::Conflict(format!("quote {} is already {}", previous.number, previous.status)));
+            }
+            if accepted && previous.valid_until < dates::today() {
+                return Err(AppError::Conflict(format!(
+                    "quote {} expired on {}",
+                    previous.number,
+                    previous.valid_until.format("%d/%m/%Y")
+                )));
+            }
+
+            let status = if accepted { STATUS_ACCEPTED } else { STATUS_DECLINED };
+
+            let updated: Quote = diesel::update(quotes::table.find(quote_id))
+                .set((
+                    quotes::status.eq(status),
+                    quotes::edited_at.eq(chrono::offset::Utc::now().naive_utc()),
+                ))
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_QUOTE, quote_id, OPERATION_UPDATE, Some(&previous), Some(&updated))?;
+
+            Ok(updated)
+        })
+    }
+
+    // Create the worksite of an accepted quote, filled with what the quote
+    // knows of the property, with the sections of its mission
+    pub fn convert(conn: &PgConnection, quote_id: i32, folder_number: Option<String>, actor: Option<i32>) -> AppResult<(Quote, Worksite)> {
+        conn.transaction::<(Quote, Worksite), AppError, _>(|| {
+            let previous = Quote::find(conn, quote_id)?;
+
+            if previous.status != STATUS_ACCEPTED {
+                return Err(AppError::Conflict(format!("quote {} is {}, not accepted", previous.number, previous.status)));
+            }
+            if let Some(worksite_id) = previous.worksite_id {
+                return Err(AppError::Conflict(format!("quote {} is already worksite {}", previous.number, worksite_id)));
+            }
+
+            let folder_number = folder_number.unwrap_or_else(|| previous.number.clone());
+            let domain = previous.mission()?.domain();
+
+            let content = WorksiteContent {
+                worksite_information: Some(WorksiteInformation {
+                    folder_number,
+                    property_address: Some(previous.parameters.property_address.clone()),
+                    surface_area: Some(previous.parameters.surface_area),
+                    mission_type: Some(previous.mission()?),
+                }),
+                leads: if domain == Domain::Lead { Some(vec![]) } else { None },
+                asbestos: if domain == Domain::Asbestos { Some(vec![]) } else { None },
+                lead_sessions: None,
+            };
+
+            let worksite = Worksite::create(conn, previous.client_id, content, actor)?;
+
+            let updated: Quote = diesel::update(quotes::table.find(quote_id))
+                .set((
+                    quotes::worksite_id.eq(worksite.id),
+                    quotes::edited_at.eq(chrono::offset::Utc::now().naive_utc()),
+                ))
+                .get_result(conn)?;
+
+            AuditLog::record(conn, actor, ENTITY_QUOTE, quote_id, OPERATION_UPDATE, Some(&previous), Some(&updated))?;
+
+            Ok((updated, worksite))
+        })
+    }
+
+    pub fn set_document_path(&self, conn: &PgConnection, path: &str) -> QueryResult<Quote> {
+        diesel::update(quotes::table.find(self.id))
+            .set(quotes::document_path.eq(path))
+            .get_result(conn)
+    }
+
+    // Queue the rendering of the PDF of this quote
+    pub fn enqueue_document(&self, conn: &PgConnection, max_attempts: i32, actor: Option<i32>) -> AppResult<Job> {
+        Job::enqueue(conn, KIND_QUOTE_DOCUMENT, &QuoteDocumentPayload { quote_id: self.id }, max_attempts, actor)
+    }
+}
+
+pub struct QuoteQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl QuoteQuery {
+    #[graphql(description = "Fetch a quote")]
+    async fn fetch(context: &GraphQLContext, quote_id: i32) -> AppResult<Quote> {
+        context.require_user()?;
+
+        context.run(move |conn| Quote::find(conn, quote_id)).await
+    }
+
+    #[graphql(description = "Fetch the latest quotes, optionally of a client or of a status")]
+    async fn fetch_all(
+        context: &GraphQLContext,
+        client_id: Option<i32>,
+        status: Option<String>,
+        offset: i32,
+    ) -> AppResult<Vec<Quote>> {
+        context.require_user()?;
+
+        if offset < 0 {
+            return Err(AppError::invalid("offset", "Must not be negative"));
+        }
+
+        context
+            .run(move |conn| {
+                let mut query = quotes::table.into_boxed();
+
+                if let Some(client_id) = client_id {
+                    query = query.filter(quotes::client_id.eq(client_id));
+                }
+                if let Some(status) = status {
+                    query = query.filter(quotes::status.eq(status));
+                }
+
+                Ok(query
+                    .order(quotes::id.desc())
+                    .limit(20)
+                    .offset(offset.into())
+                    .load::<Quote>(conn)?)
+            })
+            .await
+    }
+
+    #[graphql(description = "Price a mission without saving a quote")]
+    async fn estimate(context: &GraphQLContext, parameters: QuoteParametersInput) -> AppResult<QuoteEstimate> {
+        context.require_user()?;
+        validate(&parameters)?;
+
+        let billing = context.config.billing.clone();
+
+        context
+            .run(move |conn| estimate(conn, &parameters.into(), &billing))
+            .await
+    }
+}
+
+pub struct QuoteMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl QuoteMutation {
+    #[graphql(description = "Price a mission with the grid in force today and save it as a quote of the client. Its PDF is rendered in the background, `documentPath` is set once it is written.")]
+    async fn create(context: &GraphQLContext, input: QuoteInput) -> AppResult<Quote> {
+        let actor = context.require_user()?.id;
+        let billing = context.config.billing.clone();
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let quote = context
+            .run(move |conn| {
+                conn.transaction::<Quote, AppError, _>(|| {
+                    let quote = Quote::create(conn, input, &billing, Some(actor))?;
+                    quote.enqueue_document(conn, max_attempts, Some(actor))?;
+
+                    Ok(quote)
+                })
+            })
+            .await?;
+
+        crate::jobs::wake();
+        Ok(quote)
+    }
+
+    #[graphql(description = "Record that the client accepted the quote, before its validity ends")]
+    async fn accept(context: &GraphQLContext, quote_id: i32) -> AppResult<Quote> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| Quote::answer(conn, quote_id, true, Some(actor))).await
+    }
+
+    #[graphql(description = "Record that the client declined the quote")]
+    async fn decline(context: &GraphQLContext, quote_id: i32) -> AppResult<Quote> {
+        let actor = context.require_user()?.id;
+
+        context.run(move |conn| Quote::answer(conn, quote_id, false, Some(actor))).await
+    }
+
+    #[graphql(description = "Create the worksite of an accepted quote. Its folder number is the number of the quote unless given.")]
+    async fn convert_to_worksite(
+        context: &GraphQLContext,
+        quote_id: i32,
+        folder_number: Option<String>,
+    ) -> AppResult<Worksite> {
+        let actor = context.require_user()?.id;
+
+        if let Some(folder_number) = &folder_number {
+            let mut v = Validator::default();
+            v.not_blank("folderNumber", folder_number)
+                .length("folderNumber", folder_number, 1, 64);
+            v.finish()?;
+        }
+
+        let (_, worksite) = context
+            .run(move |conn| Quote::convert(conn, quote_id, folder_number, Some(actor)))
+            .await?;
+
+        events::publish(WorksiteEvent::new(EVENT_CREATED, &worksite, None));
+        Ok(worksite)
+    }
+
+    #[graphql(description = "Render again the PDF of a quote, in the background")]
+    async fn generate_document(context: &GraphQLContext, quote_id: i32) -> AppResult<Job> {
+        let actor = context.require_user()?.id;
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let job = context
+            .run(move |conn| Quote::find(conn, quote_id)?.enqueue_document(conn, max_attempts, Some(actor)))
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+}
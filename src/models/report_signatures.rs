@@ -0,0 +1,100 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::DateTime;
+use crate::errors::AppResult;
+use crate::models::users::User;
+use crate::models::worksite_versions::WorksiteRevision;
+use crate::schema::{report_signatures, worksite_revisions};
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct ReportSignature {
+    pub id: i32,
+    pub revision_id: i32,
+    pub content_hash: String,
+    pub signed_by: Option<i32>,
+    pub signer_name: String,
+    pub certificate_subject: Option<String>,
+    pub signed_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Report written for an issued revision, signed when a certificate is configured")]
+impl ReportSignature {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn revision_id(&self) -> i32 {
+        self.revision_id
+    }
+
+    #[graphql(description = "SHA-256 of the PDF, hexadecimal")]
+    fn content_hash(&self) -> &str {
+        self.content_hash.as_str()
+    }
+
+    #[graphql(description = "The report carries a digital signature")]
+    fn signed(&self) -> bool {
+        self.certificate_subject.is_some()
+    }
+
+    async fn signed_by(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.signed_by {
+            Some(signer) => context.loaders.users.load(signer).await,
+            None => Ok(None),
+        }
+    }
+
+    #[graphql(description = "Name printed in the signature")]
+    fn signer_name(&self) -> &str {
+        self.signer_name.as_str()
+    }
+
+    fn certificate_subject(&self) -> Option<&str> {
+        self.certificate_subject.as_deref()
+    }
+
+    fn signed_at(&self) -> DateTime {
+        self.signed_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "report_signatures"]
+pub struct NewReportSignature<'a> {
+    pub revision_id: &'a i32,
+    pub content_hash: &'a str,
+    pub signed_by: Option<i32>,
+    pub signer_name: &'a str,
+    pub certificate_subject: Option<&'a str>,
+    pub signed_at: &'a NaiveDateTime,
+}
+
+impl ReportSignature {
+    pub fn record(conn: &PgConnection, new_signature: NewReportSignature) -> QueryResult<ReportSignature> {
+        diesel::insert_into(report_signatures::table)
+            .values(new_signature)
+            .get_result(conn)
+    }
+
+    // Latest report written for a revision
+    pub fn latest(conn: &PgConnection, revision_id: i32) -> QueryResult<Option<ReportSignature>> {
+        report_signatures::table
+            .filter(report_signatures::revision_id.eq(revision_id))
+            .order(report_signatures::id.desc())
+            .first(conn)
+            .optional()
+    }
+
+    // The report we wrote with this hash, and its revision
+    pub fn find_by_hash(conn: &PgConnection, hash: &str) -> QueryResult<Option<(ReportSignature, WorksiteRevision)>> {
+        report_signatures::table
+            .inner_join(worksite_revisions::table)
+            .filter(report_signatures::content_hash.eq(hash))
+            .order(report_signatures::id.desc())
+            .first(conn)
+            .optional()
+    }
+}
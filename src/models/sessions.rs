@@ -0,0 +1,90 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use crate::dates::DateTime;
+use crate::models::users::User;
+use crate::schema::{sessions, sessions::dsl::*};
+
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub token: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object]
+#[graphql(description = "Authenticated session, send the token as `Authorization: Bearer <token>`")]
+impl Session {
+    fn token(&self) -> &str {
+        self.token.as_str()
+    }
+
+    fn user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    fn expires_at(&self) -> DateTime {
+        self.expires_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession<'a> {
+    pub user_id: &'a i32,
+    pub token: &'a String,
+    pub created_at: &'a NaiveDateTime,
+    pub expires_at: &'a NaiveDateTime,
+}
+
+impl Session {
+    // Open a new session for an already authenticated user, valid for
+    // `lifetime_hours` (`auth.session_lifetime_hours`)
+    pub fn open(conn: &PgConnection, user: &User, lifetime_hours: i64) -> QueryResult<Session> {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+
+        let new_token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let now = chrono::offset::Utc::now().naive_utc();
+
+        let new_session: NewSession = NewSession {
+            user_id: &user.id,
+            token: &new_token,
+            created_at: &now,
+            expires_at: &(now + Duration::hours(lifetime_hours)),
+        };
+
+        diesel::insert_into(sessions)
+            .values(new_session)
+            .get_result(conn)
+    }
+
+    // Resolve the user behind a bearer token, if the session is still valid
+    pub fn user_for_token(conn: &PgConnection, bearer: &str) -> QueryResult<Option<User>> {
+        use crate::schema::users;
+
+        sessions
+            .inner_join(users::table)
+            .filter(token.eq(bearer))
+            .filter(expires_at.gt(chrono::offset::Utc::now().naive_utc()))
+            .select(users::all_columns)
+            .first::<User>(conn)
+            .optional()
+    }
+
+    pub fn close(conn: &PgConnection, bearer: &str) -> QueryResult<usize> {
+        diesel::delete(sessions.filter(token.eq(bearer))).execute(conn)
+    }
+
+    // Sign a user out everywhere, e.g. after a password reset
+    pub fn close_all(conn: &PgConnection, of_user: i32) -> QueryResult<usize> {
+        diesel::delete(sessions.filter(user_id.eq(of_user))).execute(conn)
+    }
+
+    // Delete the sessions past their expiry, they can't be used anymore
+    pub fn purge_expired(conn: &PgConnection) -> QueryResult<usize> {
+        diesel::delete(sessions.filter(expires_at.le(chrono::offset::Utc::now().naive_utc()))).execute(conn)
+    }
+}
@@ -1,21 +1,29 @@
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
-use juniper::{FieldError, FieldResult, graphql_value};
 use crate::GraphQLContext;
+use crate::errors::{AppError, AppResult};
+use crate::validation::{validate, Validate, Validator};
+use crate::models::audit_logs::{AuditLog, ENTITY_USER, OPERATION_CREATE, OPERATION_PASSWORD_RESET};
+use crate::models::certifications::Certification;
+use crate::models::sessions::Session;
 use crate::schema::users;
 use crate::schema::users::dsl::*;
 use diesel::prelude::*;
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Queryable, Identifiable)]
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
 pub struct User {
     pub id: i32,
     pub authorization_id: i32,
     pub name: String,
+    // Never leaks the hash into the audit trail, nor to GraphQL which has no
+    // resolver for it
+    #[serde(skip_serializing)]
     pub password: String,
 }
 
-#[juniper::graphql_object]
+#[juniper::graphql_object(Context = GraphQLContext)]
 #[graphql(description = "User of the application")]
 impl User {
     fn id(&self) -> i32 {
@@ -30,22 +38,17 @@ impl User {
         self.name.as_str()
     }
 
-    fn password(&self) -> &str {
-        self.password.as_str()
-    }
+    async fn authorization(&self, context: &GraphQLContext) -> AppResult<Vec<Arc<Authorization>>> {
+        let authorization = context.loaders.authorizations.load(self.authorization_id).await?;
 
-    fn authorization(&self) -> Vec<Authorization> {
-        use crate::get_pool;
-        use crate::schema::authorizations::dsl::*;
-        use diesel::prelude::*;
+        Ok(authorization.into_iter().collect())
+    }
 
-        let query = authorizations
-            .filter(id.eq_all(self.authorization_id))
-            .limit(3)
-            .load::<Authorization>(&get_pool().get().expect("Database not working"))
-            .expect("Error loading Authorizations");
+    #[graphql(description = "Certifications of the operator, the latest ending first")]
+    async fn certifications(&self, context: &GraphQLContext) -> AppResult<Vec<Certification>> {
+        let user_id = self.id;
 
-        query
+        context.run(move |conn| Ok(Certification::of_user(conn, user_id)?)).await
     }
 }
 
@@ -55,6 +58,25 @@ pub struct Authorization {
     pub level: String,
 }
 
+impl Authorization {
+    // Role such as `editeur` or `administrateur`
+    pub fn find_by_level(conn: &PgConnection, role: &str) -> AppResult<Authorization> {
+        use crate::schema::authorizations::dsl::*;
+
+        authorizations
+            .filter(level.eq(role))
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::invalid("role", &format!("Unknown role {}", role)))
+    }
+
+    pub fn all(conn: &PgConnection) -> AppResult<Vec<Authorization>> {
+        use crate::schema::authorizations::dsl::*;
+
+        Ok(authorizations.order(id.asc()).load(conn)?)
+    }
+}
+
 
 #[derive(Debug, Insertable)]
 #[table_name = "users"]
@@ -78,40 +100,45 @@ pub struct UserInput {
     pub password: String,
 }
 
+impl Validate for UserInput {
+    fn rules(&self, v: &mut Validator) {
+        v.range("authorizationId", self.authorization_id, 1, i32::MAX)
+            .not_blank("name", &self.name)
+            .length("name", &self.name, 3, 64)
+            .length("password", &self.password, 8, 128);
+    }
+}
+
 
 pub struct UserQuery;
 
 #[juniper::graphql_object(Context = GraphQLContext)]
 impl UserQuery {
     #[graphql(description = "Fetch a user")]
-    pub fn fetch(&self, context: &GraphQLContext, user_id: i32) -> FieldResult<User> {
-        let conn = context.pool.get()?;
-
-        let user = users.find(user_id).first(&conn);
-
-        if user.is_ok() {
-            Ok(user.unwrap())
-        } else {
-            Err(FieldError::new(
-                "User does not not exist",
-                graphql_value!({ "authentication_error": "Invalid value" }),
-            ))
-        }
+    pub async fn fetch(&self, context: &GraphQLContext, user_id: i32) -> AppResult<User> {
+        context
+            .run(move |conn| {
+                users
+                    .find(user_id)
+                    .first(conn)
+                    .optional()?
+                    .ok_or_else(|| AppError::NotFound("User".to_string()))
+            })
+            .await
     }
 
     #[graphql(description = "Get user Authorization")]
-    fn authorization(context: &GraphQLContext) -> FieldResult<Vec<Authorization>> {
+    async fn authorization(context: &GraphQLContext) -> AppResult<Vec<Authorization>> {
         use crate::schema::authorizations::dsl::*;
         use diesel::prelude::*;
 
-        let conn = context.pool.get().expect("Data pool");
-
-        let query = authorizations
-            .limit(3)
-            .load::<Authorization>(&conn)
-            .expect("Error loading Authorizations");
-
-        Ok(query)
+        context
+            .run(|conn| {
+                Ok(authorizations
+                    .limit(3)
+                    .load::<Authorization>(conn)?)
+            })
+            .await
     }
 
 }
@@ -120,21 +147,57 @@ pub struct UserMutation;
 
 #[juniper::graphql_object(Context = GraphQLContext)]
 impl UserMutation {
-    #[graphql(description = "create a new user")]
-    fn create(context: &GraphQLContext, input: UserInput) -> FieldResult<User> {
-        use crate::schema::users::dsl::*;
-        use diesel::prelude::*;
+    #[graphql(description = "Create a new user, administrators only. The first administrator is created with the `create-user` command.")]
+    async fn create(context: &GraphQLContext, input: UserInput) -> AppResult<User> {
+        let actor = context.require_administrator().await?.id;
+
+        context.run(move |conn| User::create(conn, &input, Some(actor))).await
+    }
+
+    #[graphql(description = "Authenticate a user")]
+    async fn authenticate_user(context: &GraphQLContext, input: Authenticate) -> AppResult<User> {
+        context
+            .run(move |conn| User::find_by_credentials(conn, &input))
+            .await
+    }
+
+    #[graphql(description = "Authenticate a user and open a session, the token identifies the user on later requests")]
+    async fn open_session(context: &GraphQLContext, input: Authenticate) -> AppResult<Session> {
+        let lifetime_hours = context.config.auth.session_lifetime_hours;
+
+        context
+            .run(move |conn| {
+                let user = User::find_by_credentials(conn, &input)?;
+
+                Ok(Session::open(conn, &user, lifetime_hours)?)
+            })
+            .await
+    }
+
+    #[graphql(description = "Close the session of the current bearer token")]
+    async fn close_session(context: &GraphQLContext) -> AppResult<bool> {
+        match context.session_token.clone() {
+            Some(bearer) => context.run(move |conn| Ok(Session::close(conn, &bearer)? > 0)).await,
+            None => Ok(false),
+        }
+    }
+}
 
-        // Random salt for each password for better security
-        let salt = SaltString::generate(&mut OsRng);
+// Argon2 PHC string ($argon2id$v=19$...) of a password, with a random salt
+// for each password for better security
+fn hash_password(plain: &str) -> AppResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
 
-        // Argon2 with default params (Argon2id v19)
-        let argon2 = Argon2::default();
+    Ok(Argon2::default()
+        .hash_password(plain.as_bytes(), &salt)?
+        .to_string())
+}
 
-        // Hash password to PHC string ($argon2id$v=19$...)
-        let password_hash = argon2
-            .hash_password((input.password).as_bytes(), &salt)?
-            .to_string();
+impl User {
+    pub fn create(conn: &PgConnection, input: &UserInput, actor: Option<i32>) -> AppResult<User> {
+        validate(input)?;
+
+        let password_hash = hash_password(&input.password)?;
 
         let new_user: NewUser = NewUser {
             authorization_id: &input.authorization_id,
@@ -142,26 +205,66 @@ impl UserMutation {
             password: &password_hash,
         };
 
-        let conn = context.pool.get()?;
+        conn.transaction::<User, AppError, _>(|| {
+            let user: User = diesel::insert_into(users)
+                .values(new_user)
+                .get_result(conn)?;
 
-        let created_user = diesel::insert_into(users)
-            .values(new_user)
-            .get_result(&conn);
+            AuditLog::record(conn, actor, ENTITY_USER, user.id, OPERATION_CREATE, None, Some(&user))?;
 
-        Ok(created_user.expect("Could not create user"))
+            Ok(user)
+        })
     }
 
-    #[graphql(description = "Authenticate a user")]
-    fn authenticate_user(context: &GraphQLContext, input: Authenticate) -> FieldResult<User> {
-        use crate::schema::users::dsl::*;
-        use diesel::prelude::*;
+    pub fn find(conn: &PgConnection, user_id: i32) -> AppResult<User> {
+        users
+            .find(user_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("User".to_string()))
+    }
+
+    pub fn find_by_name(conn: &PgConnection, user_name: &str) -> AppResult<User> {
+        users
+            .filter(name.eq(user_name))
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("User {}", user_name)))
+    }
+
+    pub fn all(conn: &PgConnection) -> AppResult<Vec<User>> {
+        Ok(users.order(id.asc()).load(conn)?)
+    }
 
-        let conn = context.pool.get().expect("Data pool");
+    // Replace the password of a user and close its open sessions
+    pub fn reset_password(&self, conn: &PgConnection, new_password: &str, actor: Option<i32>) -> AppResult<User> {
+        let mut v = Validator::default();
+        v.length("password", new_password, 8, 128);
+        v.finish()?;
+
+        let password_hash = hash_password(new_password)?;
+
+        conn.transaction::<User, AppError, _>(|| {
+            let user: User = diesel::update(users.find(self.id))
+                .set(password.eq(&password_hash))
+                .get_result(conn)?;
+
+            Session::close_all(conn, user.id)?;
+            // The hash is never serialized, the entry only records the reset
+            AuditLog::record(conn, actor, ENTITY_USER, user.id, OPERATION_PASSWORD_RESET, Some(self), Some(&user))?;
+
+            Ok(user)
+        })
+    }
 
+    // An unknown name and a wrong password are reported the same way so the
+    // error doesn't reveal which accounts exist
+    pub fn find_by_credentials(conn: &PgConnection, input: &Authenticate) -> AppResult<User> {
         let user: User = users
-            .filter(name.eq_all(input.name))
-            .first::<User>(&conn)
-            .expect("No user found with this credentials");
+            .filter(name.eq_all(&input.name))
+            .first::<User>(conn)
+            .optional()?
+            .ok_or(AppError::Unauthorized)?;
 
         let parsed_hash = PasswordHash::new(&user.password)?;
 
@@ -171,10 +274,7 @@ impl UserMutation {
         {
             Ok(user)
         } else {
-            Err(FieldError::new(
-                "Could not match the name/password combination",
-                graphql_value!({ "authentication_error": "Invalid value" }),
-            ))
+            Err(AppError::Unauthorized)
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,388 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_json::Json;
+use std::ops::Deref;
+use std::sync::Arc;
+use crate::GraphQLContext;
+use crate::dates::DateTime;
+use crate::errors::{AppError, AppResult};
+use crate::events::{self, WorksiteEvent, EVENT_REVISION_ISSUED};
+use crate::issue_checks::{IssueCheck, Operator};
+use crate::models::audit_logs::{AuditLog, ENTITY_WORKSITE, OPERATION_ISSUE};
+use crate::models::clients::Client;
+use crate::models::instruments::InstrumentRecord;
+use crate::models::jobs::{Job, WorksiteReportPayload, KIND_WORKSITE_REPORT};
+use crate::models::laboratories::orders_of;
+use crate::models::report_signatures::ReportSignature;
+use crate::models::users::User;
+use crate::models::worksites::{Worksite, WorksiteContent};
+use crate::schema::{worksite_revisions, worksite_versions};
+
+#[derive(Serialize, Queryable, Identifiable, Debug)]
+pub struct WorksiteVersion {
+    pub id: i32,
+    pub worksite_id: i32,
+    pub version: i32,
+    pub worksite: Json<WorksiteContent>,
+    pub author_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Immutable snapshot of a worksite document")]
+impl WorksiteVersion {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn worksite_id(&self) -> i32 {
+        self.worksite_id
+    }
+
+    fn version(&self) -> i32 {
+        self.version
+    }
+
+    fn worksite(&self) -> &WorksiteContent {
+        self.worksite.deref()
+    }
+
+    fn author_id(&self) -> Option<i32> {
+        self.author_id
+    }
+
+    async fn author(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.author_id {
+            Some(author) => context.loaders.users.load(author).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "worksite_versions"]
+pub struct NewWorksiteVersion<'a> {
+    pub worksite_id: &'a i32,
+    pub version: &'a i32,
+    pub worksite: &'a Json<WorksiteContent>,
+    pub author_id: Option<i32>,
+    pub created_at: &'a NaiveDateTime,
+}
+
+impl WorksiteVersion {
+    // Snapshot the current document of a worksite as its next version
+    pub fn record(conn: &PgConnection, worksite: &Worksite, author: Option<i32>) -> QueryResult<WorksiteVersion> {
+        let last_version: Option<i32> = worksite_versions::table
+            .filter(worksite_versions::worksite_id.eq(worksite.id))
+            .select(diesel::dsl::max(worksite_versions::version))
+            .first(conn)?;
+
+        let new_version: NewWorksiteVersion = NewWorksiteVersion {
+            worksite_id: &worksite.id,
+            version: &(last_version.unwrap_or(0) + 1),
+            worksite: &worksite.worksite,
+            author_id: author,
+            created_at: &chrono::offset::Utc::now().naive_utc(),
+        };
+
+        diesel::insert_into(worksite_versions::table)
+            .values(new_version)
+            .get_result(conn)
+    }
+
+    pub fn find(conn: &PgConnection, of_worksite: i32, number: i32) -> QueryResult<WorksiteVersion> {
+        worksite_versions::table
+            .filter(worksite_versions::worksite_id.eq(of_worksite))
+            .filter(worksite_versions::version.eq(number))
+            .first(conn)
+    }
+
+    pub fn latest(conn: &PgConnection, of_worksite: i32) -> QueryResult<WorksiteVersion> {
+        worksite_versions::table
+            .filter(worksite_versions::worksite_id.eq(of_worksite))
+            .order(worksite_versions::version.desc())
+            .first(conn)
+    }
+}
+
+#[derive(Serialize, Queryable, Identifiable, Debug)]
+pub struct WorksiteRevision {
+    pub id: i32,
+    pub worksite_id: i32,
+    pub version_id: i32,
+    pub revision: i32,
+    pub issued_by: Option<i32>,
+    pub issued_at: NaiveDateTime,
+    pub report_path: Option<String>,
+}
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+#[graphql(description = "Version of a worksite frozen when its report was issued")]
+impl WorksiteRevision {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn worksite_id(&self) -> i32 {
+        self.worksite_id
+    }
+
+    async fn worksite(&self, context: &GraphQLContext) -> AppResult<Arc<Worksite>> {
+        context
+            .loaders
+            .worksites
+            .load(self.worksite_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Worksite".to_string()))
+    }
+
+    fn version_id(&self) -> i32 {
+        self.version_id
+    }
+
+    #[graphql(description = "Revision number printed on the report, 0 for the first issue")]
+    fn revision(&self) -> i32 {
+        self.revision
+    }
+
+    fn issued_by(&self) -> Option<i32> {
+        self.issued_by
+    }
+
+    async fn issuer(&self, context: &GraphQLContext) -> AppResult<Option<Arc<User>>> {
+        match self.issued_by {
+            Some(issuer) => context.loaders.users.load(issuer).await,
+            None => Ok(None),
+        }
+    }
+
+    fn issued_at(&self) -> DateTime {
+        self.issued_at.into()
+    }
+
+    fn report_path(&self) -> Option<&str> {
+        self.report_path.as_deref()
+    }
+
+    #[graphql(description = "Signature and hash of the latest report written, POST the PDF to /reports/verify to check a copy")]
+    async fn signature(&self, context: &GraphQLContext) -> AppResult<Option<ReportSignature>> {
+        let revision_id = self.id;
+
+        context.run(move |conn| Ok(ReportSignature::latest(conn, revision_id)?)).await
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "worksite_revisions"]
+pub struct NewWorksiteRevision<'a> {
+    pub worksite_id: &'a i32,
+    pub version_id: &'a i32,
+    pub revision: &'a i32,
+    pub issued_by: Option<i32>,
+    pub issued_at: &'a NaiveDateTime,
+}
+
+impl WorksiteRevision {
+    // Freeze the latest version of a worksite as its next revision
+    pub fn issue(conn: &PgConnection, of_worksite: i32, issuer: Option<i32>) -> AppResult<WorksiteRevision> {
+        use crate::schema::clients;
+
+        let version = WorksiteVersion::latest(conn, of_worksite)?;
+        let worksite = Worksite::find(conn, of_worksite)?;
+        let client: Client = clients::table.find(worksite.client_id).first(conn)?;
+        let orders = orders_of(conn, of_worksite)?;
+        let instruments = InstrumentRecord::of_sessions(conn, version.worksite.lead_sessions.as_deref().unwrap_or_default())?;
+        let operator = issuer.map(|issuer| Operator::load(conn, issuer)).transpose()?;
+
+        // The frozen version must pass the checks of `validateWorksiteForIssue`,
+        // warnings don't matter here
+        IssueCheck::run(of_worksite, &client, &version.worksite, &orders, &instruments, operator.as_ref(), 0).blocking()?;
+
+        let last_revision = WorksiteRevision::latest(conn, of_worksite)?;
+
+        if let Some(last_revision) = &last_revision {
+            if last_revision.version_id == version.id {
+                return Err(AppError::Conflict(format!(
+                    "the worksite has not changed since Rev {}",
+                    last_revision.revision
+                )));
+            }
+        }
+
+        let new_revision: NewWorksiteRevision = NewWorksiteRevision {
+            worksite_id: &of_worksite,
+            version_id: &version.id,
+            revision: &last_revision.map(|last| last.revision + 1).unwrap_or(0),
+            issued_by: issuer,
+            issued_at: &chrono::offset::Utc::now().naive_utc(),
+        };
+
+        Ok(diesel::insert_into(worksite_revisions::table)
+            .values(new_revision)
+            .get_result(conn)?)
+    }
+
+    pub fn find(conn: &PgConnection, of_worksite: i32, number: i32) -> QueryResult<WorksiteRevision> {
+        worksite_revisions::table
+            .filter(worksite_revisions::worksite_id.eq(of_worksite))
+            .filter(worksite_revisions::revision.eq(number))
+            .first(conn)
+    }
+
+    pub fn latest(conn: &PgConnection, of_worksite: i32) -> QueryResult<Option<WorksiteRevision>> {
+        worksite_revisions::table
+            .filter(worksite_revisions::worksite_id.eq(of_worksite))
+            .order(worksite_revisions::revision.desc())
+            .first(conn)
+            .optional()
+    }
+
+    // The revision this one corrects, if any
+    pub fn previous(&self, conn: &PgConnection) -> QueryResult<Option<WorksiteRevision>> {
+        worksite_revisions::table
+            .filter(worksite_revisions::worksite_id.eq(self.worksite_id))
+            .filter(worksite_revisions::revision.lt(self.revision))
+            .order(worksite_revisions::revision.desc())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn set_report_path(&self, conn: &PgConnection, path: &str) -> QueryResult<WorksiteRevision> {
+        diesel::update(worksite_revisions::table.find(self.id))
+            .set(worksite_revisions::report_path.eq(path))
+            .get_result(conn)
+    }
+
+    // Queue the rendering of the report of this revision
+    pub fn enqueue_report(&self, conn: &PgConnection, max_attempts: i32, actor: Option<i32>) -> AppResult<Job> {
+        let payload = WorksiteReportPayload {
+            worksite_id: self.worksite_id,
+            revision: self.revision,
+        };
+
+        Job::enqueue(conn, KIND_WORKSITE_REPORT, &payload, max_attempts, actor)
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "Field that differs between two versions, values are JSON encoded")]
+pub struct FieldChange {
+    pub path: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+// Field by field differences between two worksite documents
+pub fn compare(before: &WorksiteContent, after: &WorksiteContent) -> Vec<FieldChange> {
+    let encode = |value: serde_json::Value| {
+        if value.is_null() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    };
+
+    crate::diff::changes(
+        &serde_json::to_value(before).unwrap_or_default(),
+        &serde_json::to_value(after).unwrap_or_default(),
+    )
+    .into_iter()
+    .map(|change| FieldChange {
+        path: change.path,
+        before: encode(change.before),
+        after: encode(change.after),
+    })
+    .collect()
+}
+
+pub struct WorksiteVersionQuery;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl WorksiteVersionQuery {
+    #[graphql(description = "Fetch every version of a worksite, oldest first")]
+    async fn fetch_all(context: &GraphQLContext, worksite_id: i32) -> AppResult<Vec<WorksiteVersion>> {
+        context
+            .run(move |conn| {
+                Ok(worksite_versions::table
+                    .filter(worksite_versions::worksite_id.eq(worksite_id))
+                    .order(worksite_versions::version.asc())
+                    .load::<WorksiteVersion>(conn)?)
+            })
+            .await
+    }
+
+    #[graphql(description = "Compare two versions of a worksite field by field")]
+    async fn compare(context: &GraphQLContext, worksite_id: i32, from_version: i32, to_version: i32) -> AppResult<Vec<FieldChange>> {
+        context
+            .run(move |conn| {
+                let from = WorksiteVersion::find(conn, worksite_id, from_version)?;
+                let to = WorksiteVersion::find(conn, worksite_id, to_version)?;
+
+                Ok(compare(&from.worksite, &to.worksite))
+            })
+            .await
+    }
+
+    #[graphql(description = "Fetch the issued revisions of a worksite")]
+    async fn revisions(context: &GraphQLContext, worksite_id: i32) -> AppResult<Vec<WorksiteRevision>> {
+        context
+            .run(move |conn| {
+                Ok(worksite_revisions::table
+                    .filter(worksite_revisions::worksite_id.eq(worksite_id))
+                    .order(worksite_revisions::revision.asc())
+                    .load::<WorksiteRevision>(conn)?)
+            })
+            .await
+    }
+}
+
+pub struct WorksiteVersionMutation;
+
+#[juniper::graphql_object(Context = GraphQLContext)]
+impl WorksiteVersionMutation {
+    #[graphql(description = "Issue the report of a worksite, freezing its latest version as the next revision. The report is rendered in the background, `reportPath` is set once it is written.")]
+    async fn issue_revision(context: &GraphQLContext, worksite_id: i32) -> AppResult<WorksiteRevision> {
+        let actor = Some(context.require_user()?.id);
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let (revision, worksite) = context
+            .run(move |conn| {
+                conn.transaction::<(WorksiteRevision, Worksite), AppError, _>(|| {
+                    let revision = WorksiteRevision::issue(conn, worksite_id, actor)?;
+
+                    AuditLog::record(conn, actor, ENTITY_WORKSITE, worksite_id, OPERATION_ISSUE, None, Some(&revision))?;
+                    revision.enqueue_report(conn, max_attempts, actor)?;
+
+                    Ok((revision, Worksite::find(conn, worksite_id)?))
+                })
+            })
+            .await?;
+
+        crate::jobs::wake();
+        events::publish(WorksiteEvent::new(EVENT_REVISION_ISSUED, &worksite, Some(revision.revision)));
+        Ok(revision)
+    }
+
+    #[graphql(description = "Generate again the report of an issued revision, in the background")]
+    async fn generate_revision_report(context: &GraphQLContext, worksite_id: i32, revision: i32) -> AppResult<Job> {
+        let actor = Some(context.require_user()?.id);
+        let max_attempts = context.config.jobs.max_attempts;
+
+        let job = context
+            .run(move |conn| {
+                let revision = WorksiteRevision::find(conn, worksite_id, revision)
+                    .optional()?
+                    .ok_or_else(|| AppError::NotFound("Revision".to_string()))?;
+
+                revision.enqueue_report(conn, max_attempts, actor)
+            })
+            .await?;
+
+        crate::jobs::wake();
+        Ok(job)
+    }
+}
@@ -1,11 +1,29 @@
+use crate::GraphQLContext;
+use crate::dates::DateTime;
+use crate::errors::{AppError, AppResult};
+use crate::missions::{Domain, MissionType};
+use crate::models::audit_logs::{AuditLog, ENTITY_WORKSITE, OPERATION_CREATE};
+use crate::models::clients::Client;
+use crate::models::invoices::Invoice;
+use crate::models::laboratories::AnalysisOrder;
+use crate::models::worksite_versions::WorksiteVersion;
 use crate::schema::worksites;
+use crate::validation::{Validate, Validator};
 use chrono::NaiveDateTime;
+use diesel::prelude::*;
 use diesel_json::Json;
 use std::ops::Deref;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WorksiteInformation {
     pub folder_number: String,
+    #[serde(default)]
+    pub property_address: Option<String>,
+    #[serde(default)]
+    pub surface_area: Option<f64>,
+    #[serde(default)]
+    pub mission_type: Option<MissionType>,
 }
 
 #[juniper::graphql_object]
@@ -13,6 +31,21 @@ impl WorksiteInformation {
     pub fn folder_number(&self) -> &str {
         self.folder_number.as_str()
     }
+
+    #[graphql(description = "Address of the surveyed property")]
+    pub fn property_address(&self) -> Option<&str> {
+        self.property_address.as_deref()
+    }
+
+    #[graphql(description = "Square metres surveyed")]
+    pub fn surface_area(&self) -> Option<f64> {
+        self.surface_area
+    }
+
+    #[graphql(description = "Mission ordered, it decides the sections the report needs and its template")]
+    pub fn mission_type(&self) -> Option<MissionType> {
+        self.mission_type
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, GraphQLObject)]
@@ -45,6 +78,22 @@ pub struct Lead {
     pub measure: i32,
     pub incertitude: i32,
     pub result: String,
+    #[graphql(description = "Index of its session in `leadSessions`, the first when not given")]
+    #[serde(default)]
+    pub session: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, GraphQLObject)]
+#[graphql(description = "Session of lead measures with one XRF instrument, checked against the reference standard at its start and end")]
+pub struct LeadSession {
+    pub instrument_id: i32,
+    pub date: String,
+    #[graphql(description = "Lead of the reference standard, mg/cm²")]
+    pub reference_value: f64,
+    #[graphql(description = "Reading of the standard before the first measure, mg/cm²")]
+    pub start_reading: f64,
+    #[graphql(description = "Reading of the standard after the last measure, mg/cm²")]
+    pub end_reading: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -52,6 +101,30 @@ pub struct WorksiteContent {
     pub worksite_information: Option<WorksiteInformation>,
     pub leads: Option<Vec<Lead>>,
     pub asbestos: Option<Vec<Asbestos>>,
+    #[serde(default)]
+    pub lead_sessions: Option<Vec<LeadSession>>,
+}
+
+impl WorksiteContent {
+    // Asbestos samples sent for analysis, entries sharing a sample are
+    // counted once
+    pub fn sample_count(&self) -> usize {
+        let mut samples: Vec<&str> = self
+            .asbestos
+            .iter()
+            .flatten()
+            .map(|entry| entry.sampling.trim())
+            .filter(|sampling| !sampling.is_empty())
+            .collect();
+
+        samples.sort_unstable();
+        samples.dedup();
+        samples.len()
+    }
+
+    pub fn mission(&self) -> Option<MissionType> {
+        self.worksite_information.as_ref().and_then(|information| information.mission_type)
+    }
 }
 
 #[juniper::graphql_object]
@@ -79,6 +152,10 @@ impl WorksiteContent {
             self.asbestos.as_ref()
         }
     }
+
+    fn lead_sessions(&self) -> Option<&Vec<LeadSession>> {
+        self.lead_sessions.as_ref()
+    }
 }
 
 #[derive(Serialize, Queryable, Identifiable, Debug)]
@@ -91,7 +168,7 @@ pub struct Worksite {
     pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[juniper::graphql_object()]
+#[juniper::graphql_object(Context = GraphQLContext)]
 impl Worksite {
     fn id(&self) -> i32 {
         self.id
@@ -101,24 +178,45 @@ impl Worksite {
         self.client_id
     }
 
+    async fn client(&self, context: &GraphQLContext) -> AppResult<Arc<Client>> {
+        context
+            .loaders
+            .clients
+            .load(self.client_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Client".to_string()))
+    }
+
     fn worksite(&self) -> &WorksiteContent {
         self.worksite.deref().to_owned()
     }
 
-    fn created_at(&self) -> String {
-        self.created_at.format("%d-%m-%Y %M:%S:%f").to_string()
+    fn created_at(&self) -> DateTime {
+        self.created_at.into()
     }
 
-    fn edited_at(&self) -> String {
-        self.created_at.format("%d-%m-%Y %M:%S:%f").to_string()
+    fn edited_at(&self) -> DateTime {
+        self.edited_at.into()
     }
 
-    fn deleted_at(&self) -> Option<String> {
-        if !self.deleted_at.is_none() {
-            Some(self.created_at.format("%d-%m-%Y %M:%S:%f").to_string())
-        } else {
-            None
-        }
+    fn deleted_at(&self) -> Option<DateTime> {
+        self.deleted_at.map(DateTime::from)
+    }
+
+    #[graphql(description = "Invoices of the worksite, latest first")]
+    async fn invoices(&self, context: &GraphQLContext) -> AppResult<Vec<Arc<Invoice>>> {
+        context.require_user()?;
+        let found = context.loaders.invoices_by_worksite.load(self.id).await?;
+
+        Ok(found.map(|list| list.as_ref().clone()).unwrap_or_default())
+    }
+
+    #[graphql(description = "Samples sent to laboratories, latest orders first")]
+    async fn analysis_orders(&self, context: &GraphQLContext) -> AppResult<Vec<Arc<AnalysisOrder>>> {
+        context.require_user()?;
+        let found = context.loaders.analysis_orders_by_worksite.load(self.id).await?;
+
+        Ok(found.map(|list| list.as_ref().clone()).unwrap_or_default())
     }
 }
 
@@ -131,35 +229,148 @@ pub struct NewWorksite<'a> {
     pub edited_at: &'a NaiveDateTime,
 }
 
+impl Worksite {
+    // Insert a worksite with its first version
+    pub fn create(conn: &PgConnection, of_client: i32, content: WorksiteContent, actor: Option<i32>) -> AppResult<Worksite> {
+        use crate::schema::worksites::dsl::*;
+
+        let new_worksite: NewWorksite = NewWorksite {
+            client_id: &of_client,
+            worksite: &Json::new(content),
+            created_at: &chrono::offset::Utc::now().naive_utc(),
+            edited_at: &chrono::offset::Utc::now().naive_utc(),
+        };
+
+        conn.transaction::<Worksite, AppError, _>(|| {
+            let worksite_row: Worksite = diesel::insert_into(worksites)
+                .values(new_worksite)
+                .get_result(conn)?;
+
+            WorksiteVersion::record(conn, &worksite_row, actor)?;
+            AuditLog::record(conn, actor, ENTITY_WORKSITE, worksite_row.id, OPERATION_CREATE, None, Some(&worksite_row))?;
+
+            Ok(worksite_row)
+        })
+    }
+
+    pub fn find(conn: &PgConnection, worksite_id: i32) -> AppResult<Worksite> {
+        use crate::schema::worksites::dsl::*;
+
+        worksites
+            .find(worksite_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Worksite".to_string()))
+    }
+
+    // The worksite, locked until the end of the transaction
+    pub fn lock(conn: &PgConnection, worksite_id: i32) -> AppResult<Worksite> {
+        use crate::schema::worksites::dsl::*;
+
+        worksites
+            .find(worksite_id)
+            .for_update()
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("Worksite".to_string()))
+    }
+
+    // Worksites not deleted, oldest first
+    pub fn all(conn: &PgConnection) -> AppResult<Vec<Worksite>> {
+        use crate::schema::worksites::dsl::*;
+
+        Ok(worksites
+            .filter(deleted_at.is_null())
+            .order(id.asc())
+            .load(conn)?)
+    }
+}
+
 #[derive(GraphQLInputObject)]
 pub struct CreateNewWorksite {
     pub client_id: i32,
     pub worksite: Option<CreateWorksiteContent>,
 }
 
+impl Validate for CreateNewWorksite {
+    fn rules(&self, v: &mut Validator) {
+        v.range("clientId", self.client_id, 1, i32::MAX)
+            .required("worksite", &self.worksite)
+            .nested("worksite", &self.worksite);
+    }
+}
+
 #[derive(GraphQLInputObject, Serialize, Deserialize)]
 pub struct CreateWorksiteContent {
     pub worksite_information: Option<CreateWorksiteInformation>,
     pub leads: Option<Vec<CreateLead>>,
     pub asbestos: Option<Vec<CreateAsbestos>>,
+    #[graphql(description = "Sessions of the lead measures, required to issue a lead report")]
+    pub lead_sessions: Option<Vec<CreateLeadSession>>,
+}
+
+impl Validate for CreateWorksiteContent {
+    fn rules(&self, v: &mut Validator) {
+        v.nested("worksiteInformation", &self.worksite_information)
+            .each("leads", &self.leads)
+            .each("asbestos", &self.asbestos)
+            .each("leadSessions", &self.lead_sessions);
+
+        // A draft may miss sections, but not hold those of another mission
+        let mission = self.worksite_information.as_ref().and_then(|information| information.mission_type);
+        if let Some(mission) = mission {
+            let unexpected = match mission.domain() {
+                Domain::Asbestos => ("leads", self.leads.as_ref().is_none_or(Vec::is_empty)),
+                Domain::Lead => ("asbestos", self.asbestos.as_ref().is_none_or(Vec::is_empty)),
+            };
+
+            v.check(unexpected.0, unexpected.1, &format!("Not part of the mission {}", mission.label()));
+        }
+    }
 }
 
 impl From<CreateWorksiteContent> for WorksiteContent {
     fn from(f: CreateWorksiteContent) -> Self {
-        let serialised = serde_json::to_string(&f).unwrap();
-        serde_json::from_str::<WorksiteContent>(&serialised).unwrap()
+        WorksiteContent {
+            worksite_information: f.worksite_information.map(Into::into),
+            leads: f.leads.map(|leads| leads.into_iter().map(Into::into).collect()),
+            asbestos: f.asbestos.map(|asbestos| asbestos.into_iter().map(Into::into).collect()),
+            lead_sessions: f.lead_sessions.map(|sessions| sessions.into_iter().map(Into::into).collect()),
+        }
     }
 }
 
 #[derive(GraphQLInputObject, Serialize, Deserialize)]
 pub struct CreateWorksiteInformation {
     pub folder_number: String,
+    pub property_address: Option<String>,
+    pub surface_area: Option<f64>,
+    #[graphql(description = "Required to issue the report")]
+    pub mission_type: Option<MissionType>,
+}
+
+impl Validate for CreateWorksiteInformation {
+    fn rules(&self, v: &mut Validator) {
+        v.not_blank("folderNumber", &self.folder_number)
+            .length("folderNumber", &self.folder_number, 1, 64);
+
+        if let Some(address) = &self.property_address {
+            v.length("propertyAddress", address, 0, 255);
+        }
+        if let Some(area) = self.surface_area {
+            v.between("surfaceArea", area, 0.0, 1_000_000.0);
+        }
+    }
 }
 
 impl From<CreateWorksiteInformation> for WorksiteInformation {
     fn from(f: CreateWorksiteInformation) -> Self {
-        let serialised = serde_json::to_string(&f).unwrap();
-        serde_json::from_str::<WorksiteInformation>(&serialised).unwrap()
+        WorksiteInformation {
+            folder_number: f.folder_number,
+            property_address: f.property_address,
+            surface_area: f.surface_area,
+            mission_type: f.mission_type,
+        }
     }
 }
 
@@ -180,6 +391,18 @@ pub struct CreateAsbestos {
     pub picture_id: i32,
 }
 
+impl Validate for CreateAsbestos {
+    fn rules(&self, v: &mut Validator) {
+        v.range("unit", self.unit, 0, 9999)
+            .not_blank("area", &self.area)
+            .not_blank("localization", &self.localization)
+            .not_blank("surveyedElement", &self.surveyed_element)
+            .not_blank("sampling", &self.sampling)
+            .date("dateOfSampling", &self.date_of_sampling)
+            .range("pictureId", self.picture_id, 0, i32::MAX);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, GraphQLInputObject)]
 pub struct CreateLead {
     pub number: i32,
@@ -193,4 +416,97 @@ pub struct CreateLead {
     pub measure: i32,
     pub incertitude: i32,
     pub result: String,
+    #[graphql(description = "Index of its session in `leadSessions`, the first when not given")]
+    pub session: Option<i32>,
+}
+
+impl Validate for CreateLead {
+    fn rules(&self, v: &mut Validator) {
+        v.range("number", self.number, 1, 99999)
+            .not_blank("localization", &self.localization)
+            .not_blank("area", &self.area)
+            .range("numberUd", self.number_ud, 0, 9999)
+            .not_blank("diagnosticUnity", &self.diagnostic_unity)
+            .not_blank("substrate", &self.substrate)
+            .range("measure", self.measure, 0, i32::MAX)
+            .range("incertitude", self.incertitude, 0, i32::MAX)
+            .not_blank("result", &self.result);
+
+        if let Some(session) = self.session {
+            v.range("session", session, 0, 999);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub struct CreateLeadSession {
+    pub instrument_id: i32,
+    pub date: String,
+    #[graphql(description = "Lead of the reference standard, mg/cm²")]
+    pub reference_value: f64,
+    #[graphql(description = "Reading of the standard before the first measure, mg/cm²")]
+    pub start_reading: f64,
+    #[graphql(description = "Reading of the standard after the last measure, mg/cm²")]
+    pub end_reading: f64,
+}
+
+impl Validate for CreateLeadSession {
+    fn rules(&self, v: &mut Validator) {
+        v.range("instrumentId", self.instrument_id, 1, i32::MAX)
+            .date("date", &self.date)
+            .between("referenceValue", self.reference_value, 0.01, 100.0)
+            .between("startReading", self.start_reading, 0.0, 100.0)
+            .between("endReading", self.end_reading, 0.0, 100.0);
+    }
+}
+
+impl From<CreateLeadSession> for LeadSession {
+    fn from(f: CreateLeadSession) -> Self {
+        LeadSession {
+            instrument_id: f.instrument_id,
+            date: f.date,
+            reference_value: f.reference_value,
+            start_reading: f.start_reading,
+            end_reading: f.end_reading,
+        }
+    }
+}
+
+impl From<CreateAsbestos> for Asbestos {
+    fn from(f: CreateAsbestos) -> Self {
+        Asbestos {
+            unit: f.unit,
+            area: f.area,
+            equipments: f.equipments,
+            localization: f.localization,
+            surveyed_element: f.surveyed_element,
+            materials_description: f.materials_description,
+            sampling: f.sampling,
+            date_of_sampling: f.date_of_sampling,
+            fcr_result: f.fcr_result,
+            conservation_state: f.conservation_state,
+            equipment_volume: f.equipment_volume,
+            material_volume: f.material_volume,
+            picture_id: f.picture_id,
+        }
+    }
+}
+
+impl From<CreateLead> for Lead {
+    fn from(f: CreateLead) -> Self {
+        Lead {
+            number: f.number,
+            localization: f.localization,
+            area: f.area,
+            number_ud: f.number_ud,
+            diagnostic_unity: f.diagnostic_unity,
+            substrate: f.substrate,
+            exposed_coating: f.exposed_coating,
+            measure_localization: f.measure_localization,
+            measure: f.measure,
+            incertitude: f.incertitude,
+            result: f.result,
+            session: f.session,
+        }
+    }
 }
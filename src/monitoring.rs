@@ -0,0 +1,113 @@
+use actix_web::{web, HttpResponse};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder, HistogramVec,
+    IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::time::Duration;
+use crate::database::PostgresPool;
+use crate::errors::AppResult;
+use crate::migrations;
+
+lazy_static! {
+    pub static ref GRAPHQL_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "graphql_requests_total",
+        "GraphQL operations executed, by operation name",
+        &["operation"]
+    )
+    .unwrap();
+    pub static ref GRAPHQL_DURATION: HistogramVec = register_histogram_vec!(
+        "graphql_request_duration_seconds",
+        "Time spent executing GraphQL operations, by operation name",
+        &["operation"]
+    )
+    .unwrap();
+    pub static ref GRAPHQL_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "graphql_errors_total",
+        "Errors returned to clients, by error code",
+        &["code"]
+    )
+    .unwrap();
+    pub static ref JOBS: IntCounterVec = register_int_counter_vec!(
+        "jobs_total",
+        "Background job runs, by kind and outcome (succeeded, retried, dead)",
+        &["kind", "outcome"]
+    )
+    .unwrap();
+    static ref POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "database_pool_connections",
+        "Connections of the database pool, by state",
+        &["state"]
+    )
+    .unwrap();
+}
+
+// Label of an operation. Names come from clients so anything that doesn't
+// look like a GraphQL name is grouped to keep the series bounded.
+pub fn operation_label(name: Option<&str>) -> &str {
+    match name {
+        None => "anonymous",
+        Some(name)
+            if !name.is_empty()
+                && name.len() <= 64
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            name
+        }
+        Some(_) => "invalid",
+    }
+}
+
+// The process is up and answering
+pub async fn health() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+// The database is reachable and its schema is current, so requests can be
+// routed to this instance
+pub async fn ready(pool: web::Data<PostgresPool>) -> HttpResponse {
+    let pool = pool.get_ref().clone();
+
+    let pending: AppResult<Vec<&'static str>> = web::block(move || {
+        let conn = pool.get_timeout(Duration::from_secs(2))?;
+        migrations::pending(&conn)
+    })
+    .await
+    .unwrap_or_else(|err| Err(crate::errors::AppError::Internal(err.to_string())));
+
+    match pending {
+        Ok(pending) if pending.is_empty() => HttpResponse::Ok().json(serde_json::json!({ "status": "ready" })),
+        Ok(pending) => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "migrations pending",
+            "pending": pending,
+        })),
+        Err(err) => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "database unavailable",
+            "error": err.code(),
+        })),
+    }
+}
+
+// Prometheus text exposition of every metric
+pub async fn metrics(pool: web::Data<PostgresPool>) -> HttpResponse {
+    let state = pool.state();
+    POOL_CONNECTIONS
+        .with_label_values(&["idle"])
+        .set(state.idle_connections as i64);
+    POOL_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set((state.connections - state.idle_connections) as i64);
+    POOL_CONNECTIONS
+        .with_label_values(&["max"])
+        .set(pool.max_size() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
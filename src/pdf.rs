@@ -0,0 +1,227 @@
+use chrono::NaiveDateTime;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, Stream, StringFormat};
+
+// A4 in PDF points
+const PAGE_WIDTH: i64 = 595;
+const PAGE_HEIGHT: i64 = 842;
+const MARGIN: i64 = 50;
+
+const FONT_SIZE: i64 = 10;
+const HEADING_SIZE: i64 = 13;
+const LINE_HEIGHT: i64 = 15;
+
+// Helvetica at 10pt fits roughly this many characters between the margins
+const MAX_LINE_LENGTH: usize = 95;
+
+// One line of a plain text document. Headings are printed in bold and start
+// a new block, everything else is wrapped to the page width.
+pub enum Line {
+    Heading(String),
+    Text(String),
+}
+
+// Bytes kept in a signable document for the CMS signature, and the
+// placeholder of the offsets of its byte range
+pub const SIGNATURE_SIZE: usize = 8192;
+pub const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+// Who signs a document and when, printed in its signature dictionary
+pub struct SignatureField {
+    pub name: String,
+    pub reason: String,
+    pub signed_at: NaiveDateTime,
+}
+
+// Render lines of text into a paginated A4 PDF
+pub fn render(title: &str, lines: &[Line]) -> lopdf::Result<Vec<u8>> {
+    save(build(title, lines)?)
+}
+
+// Same, with an invisible signature field whose `/Contents` is
+// SIGNATURE_SIZE zero bytes and `/ByteRange` placeholders, for the signer
+// to fill in once the file is written
+pub fn render_signable(title: &str, lines: &[Line], field: &SignatureField) -> lopdf::Result<Vec<u8>> {
+    let mut doc = build(title, lines)?;
+
+    let signature_id = doc.add_object(dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "adbe.pkcs7.detached",
+        "ByteRange" => vec![
+            0.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+        ],
+        "Contents" => Object::String(vec![0; SIGNATURE_SIZE], StringFormat::Hexadecimal),
+        "Name" => Object::string_literal(latin1(&field.name)),
+        "Reason" => Object::string_literal(latin1(&field.reason)),
+        "M" => Object::string_literal(field.signed_at.format("D:%Y%m%d%H%M%S+00'00'").to_string()),
+    });
+
+    let first_page = *doc.get_pages().values().next().ok_or(lopdf::Error::PageNumberNotFound(1))?;
+    let field_id = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => Object::string_literal("Signature"),
+        "V" => signature_id,
+        "Rect" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        // Printed and locked
+        "F" => 132,
+        "P" => first_page,
+    });
+
+    doc.get_object_mut(first_page)?.as_dict_mut()?.set("Annots", vec![field_id.into()]);
+
+    let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+    doc.get_object_mut(catalog_id)?.as_dict_mut()?.set(
+        "AcroForm",
+        dictionary! {
+            "Fields" => vec![field_id.into()],
+            // Signatures exist, append only
+            "SigFlags" => 3,
+        },
+    );
+
+    save(doc)
+}
+
+fn build(title: &str, lines: &[Line]) -> lopdf::Result<Document> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+
+    let regular_id = doc.add_object(font("Helvetica"));
+    let bold_id = doc.add_object(font("Helvetica-Bold"));
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! {
+            "F1" => regular_id,
+            "F2" => bold_id,
+        },
+    });
+
+    let mut page_ids: Vec<Object> = Vec::new();
+
+    for page in paginate(lines) {
+        let mut operations = vec![Operation::new("BT", vec![])];
+        let mut y = PAGE_HEIGHT - MARGIN;
+
+        for (bold, text) in page {
+            let (font_name, size) = if bold { ("F2", HEADING_SIZE) } else { ("F1", FONT_SIZE) };
+
+            operations.push(Operation::new("Tf", vec![font_name.into(), size.into()]));
+            operations.push(Operation::new("Tm", vec![1.into(), 0.into(), 0.into(), 1.into(), MARGIN.into(), y.into()]));
+            operations.push(Operation::new("Tj", vec![Object::string_literal(latin1(&text))]));
+
+            y -= LINE_HEIGHT;
+        }
+
+        operations.push(Operation::new("ET", vec![]));
+
+        let content_id = doc.add_object(Stream::new(dictionary! {}, Content { operations }.encode()?));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        });
+
+        page_ids.push(page_id.into());
+    }
+
+    let pages = dictionary! {
+        "Type" => "Pages",
+        "Count" => page_ids.len() as i64,
+        "Kids" => page_ids,
+        "Resources" => resources_id,
+        "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH.into(), PAGE_HEIGHT.into()],
+    };
+    doc.objects.insert(pages_id, Object::Dictionary(pages));
+
+    let info_id = doc.add_object(dictionary! {
+        "Title" => Object::string_literal(latin1(title)),
+        "Producer" => "general_service_amiantes",
+    });
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.trailer.set("Info", info_id);
+
+    Ok(doc)
+}
+
+fn save(mut doc: Document) -> lopdf::Result<Vec<u8>> {
+    doc.compress();
+
+    let mut buffer = Vec::new();
+    doc.save_to(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+fn font(name: &str) -> lopdf::Dictionary {
+    dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => name.to_string(),
+        "Encoding" => "WinAnsiEncoding",
+    }
+}
+
+// Split the document into pages of (bold, text) rows, wrapping long lines
+fn paginate(lines: &[Line]) -> Vec<Vec<(bool, String)>> {
+    let rows_per_page = ((PAGE_HEIGHT - 2 * MARGIN) / LINE_HEIGHT) as usize;
+    let mut rows: Vec<(bool, String)> = Vec::new();
+
+    for line in lines {
+        match line {
+            Line::Heading(text) => {
+                if !rows.is_empty() {
+                    rows.push((false, String::new()));
+                }
+                rows.push((true, text.clone()));
+            }
+            Line::Text(text) => rows.extend(wrap(text).into_iter().map(|row| (false, row))),
+        }
+    }
+
+    if rows.is_empty() {
+        return vec![Vec::new()];
+    }
+
+    rows.chunks(rows_per_page).map(|chunk| chunk.to_vec()).collect()
+}
+
+fn wrap(text: &str) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > MAX_LINE_LENGTH {
+            rows.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+
+    rows.push(current);
+    rows
+}
+
+// The standard fonts use WinAnsiEncoding, which matches Latin-1 for the
+// accented characters of French text, and has the euro sign. Anything else
+// is replaced by `?`.
+fn latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '€' => 0x80,
+            c if (c as u32) < 256 => c as u8,
+            _ => b'?',
+        })
+        .collect()
+}
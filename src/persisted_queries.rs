@@ -0,0 +1,320 @@
+use juniper::http::GraphQLRequest;
+use juniper::InputValue;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use crate::config::GraphQLConfig;
+use crate::errors::{AppError, AppResult};
+
+// Queries registered by clients are forgotten past this many, clients
+// register them again on their next use
+const MAX_REGISTERED: usize = 1_000;
+
+// A GraphQL request in the automatic persisted queries protocol of Apollo:
+// the client sends the sha256 hash of its query in
+// `extensions.persistedQuery`, and the query itself only when the server
+// answered that it doesn't know the hash
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    query: Option<String>,
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+    extensions: Option<Extensions>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Extensions {
+    persisted_query: Option<PersistedQuery>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedQuery {
+    version: i32,
+    sha256_hash: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BatchRequest {
+    Single(Request),
+    Batch(Vec<Request>),
+}
+
+// Manifest of the operations of the frontend, in the format of Apollo's
+// persisted query manifests
+#[derive(Deserialize)]
+struct Manifest {
+    operations: Vec<ManifestOperation>,
+}
+
+#[derive(Deserialize)]
+struct ManifestOperation {
+    id: String,
+    name: String,
+    body: String,
+}
+
+pub struct PersistedQueries {
+    // Operations of the manifest, by hash
+    known: HashMap<String, String>,
+    // Only the operations of the manifest are executed
+    allow_list_only: bool,
+    registered: Mutex<HashMap<String, String>>,
+}
+
+pub fn hash(query: &str) -> String {
+    hex::encode(Sha256::digest(query.as_bytes()))
+}
+
+impl PersistedQueries {
+    pub fn load(config: &GraphQLConfig) -> AppResult<PersistedQueries> {
+        let mut known = HashMap::new();
+
+        if let Some(path) = &config.persisted_queries {
+            let content = fs::read_to_string(path)
+                .map_err(|err| AppError::Internal(format!("cannot read {}: {}", path.display(), err)))?;
+            let manifest: Manifest = serde_json::from_str(&content)
+                .map_err(|err| AppError::Internal(format!("cannot parse {}: {}", path.display(), err)))?;
+
+            for operation in manifest.operations {
+                // A manifest edited by hand, or written by another printer
+                if hash(&operation.body) != operation.id {
+                    return Err(AppError::Internal(format!(
+                        "{}: the id of operation {} is not the hash of its body",
+                        path.display(),
+                        operation.name
+                    )));
+                }
+                known.insert(operation.id, operation.body);
+            }
+        }
+
+        Ok(PersistedQueries {
+            known,
+            allow_list_only: config.allow_list_only,
+            registered: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // The request to execute. A query sent along with its hash is
+    // registered for the next requests. With the allow-list, every query
+    // must be one of the manifest, whether sent or referred to by hash.
+    pub fn resolve(&self, request: Request) -> AppResult<GraphQLRequest> {
+        let sent_hash = match request.extensions.and_then(|extensions| extensions.persisted_query) {
+            Some(persisted) if persisted.version != 1 => {
+                return Err(AppError::invalid(
+                    "extensions.persistedQuery.version",
+                    "Only version 1 of persisted queries is supported",
+                ))
+            }
+            Some(persisted) => Some(persisted.sha256_hash.to_ascii_lowercase()),
+            None => None,
+        };
+
+        let query = match (request.query, sent_hash) {
+            (Some(query), Some(sent_hash)) => {
+                if hash(&query) != sent_hash {
+                    return Err(AppError::invalid(
+                        "extensions.persistedQuery.sha256Hash",
+                        "Is not the hash of the query",
+                    ));
+                }
+                self.allow(&sent_hash)?;
+                self.register(sent_hash, &query);
+                query
+            }
+            (Some(query), None) => {
+                self.allow(&hash(&query))?;
+                query
+            }
+            (None, Some(sent_hash)) => self.find(&sent_hash).ok_or(AppError::PersistedQueryNotFound)?,
+            (None, None) => return Err(AppError::invalid("query", "Missing query")),
+        };
+
+        Ok(GraphQLRequest::new(query, request.operation_name, request.variables))
+    }
+
+    fn allow(&self, hash: &str) -> AppResult<()> {
+        if self.allow_list_only && !self.known.contains_key(hash) {
+            return Err(AppError::Forbidden("only the operations of the frontend are accepted".to_string()));
+        }
+        Ok(())
+    }
+
+    fn register(&self, hash: String, query: &str) {
+        if self.known.contains_key(&hash) {
+            return;
+        }
+
+        let mut registered = self.registered.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if registered.len() >= MAX_REGISTERED && !registered.contains_key(&hash) {
+            registered.clear();
+        }
+        registered.insert(hash, query.to_string());
+    }
+
+    fn find(&self, hash: &str) -> Option<String> {
+        if let Some(query) = self.known.get(hash) {
+            return Some(query.clone());
+        }
+
+        self.registered
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(hash)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+
+    const QUERY: &str = "query Me { users { me { id name } } }";
+
+    fn request(value: serde_json::Value) -> Request {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn persisted(hash: &str, query: Option<&str>) -> Request {
+        request(json!({
+            "query": query,
+            "operationName": "Me",
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } },
+        }))
+    }
+
+    // Query of the request to execute, private to juniper
+    fn resolved(queries: &PersistedQueries, request: Request) -> AppResult<String> {
+        let request = serde_json::to_value(queries.resolve(request)?).unwrap();
+        Ok(request["query"].as_str().unwrap().to_string())
+    }
+
+    fn invalid_field(result: AppResult<String>) -> String {
+        match result {
+            Err(AppError::Validation(violations)) => violations[0].field.clone(),
+            other => panic!("unexpected result {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
+
+    // Persisted queries loaded from a manifest of `operations`
+    fn with_manifest(operations: &[(&str, &str)], allow_list_only: bool) -> AppResult<PersistedQueries> {
+        let operations: Vec<_> = operations
+            .iter()
+            .map(|(id, body)| json!({ "id": id, "name": "Me", "type": "query", "body": body }))
+            .collect();
+        let manifest = json!({ "format": "apollo-persisted-query-manifest", "version": 1, "operations": operations });
+
+        // Tests run in parallel, each writes its own manifest
+        let path = env::temp_dir().join(format!(
+            "persisted-queries-{}-{}.json",
+            std::process::id(),
+            hash(&manifest.to_string())
+        ));
+        fs::write(&path, manifest.to_string()).unwrap();
+
+        let queries = PersistedQueries::load(&GraphQLConfig {
+            persisted_queries: Some(path.clone()),
+            allow_list_only,
+            ..GraphQLConfig::default()
+        });
+        fs::remove_file(path).unwrap();
+        queries
+    }
+
+    #[test]
+    fn hashes_are_lowercase_hexadecimal_sha256() {
+        assert_eq!(hash(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn queries_sent_with_their_hash_are_registered() {
+        let queries = PersistedQueries::load(&GraphQLConfig::default()).unwrap();
+        let sha256 = hash(QUERY);
+
+        assert!(matches!(
+            resolved(&queries, persisted(&sha256, None)),
+            Err(AppError::PersistedQueryNotFound)
+        ));
+        assert_eq!(resolved(&queries, persisted(&sha256, Some(QUERY))).unwrap(), QUERY);
+        assert_eq!(resolved(&queries, persisted(&sha256, None)).unwrap(), QUERY);
+        // Clients may send the hash in uppercase
+        assert_eq!(resolved(&queries, persisted(&sha256.to_uppercase(), None)).unwrap(), QUERY);
+    }
+
+    #[test]
+    fn queries_must_match_their_hash() {
+        let queries = PersistedQueries::load(&GraphQLConfig::default()).unwrap();
+        let other = hash("{ __typename }");
+
+        assert_eq!(
+            invalid_field(resolved(&queries, persisted(&other, Some(QUERY)))),
+            "extensions.persistedQuery.sha256Hash"
+        );
+        // The mismatched query is not registered under either hash
+        assert!(matches!(resolved(&queries, persisted(&other, None)), Err(AppError::PersistedQueryNotFound)));
+        assert!(matches!(
+            resolved(&queries, persisted(&hash(QUERY), None)),
+            Err(AppError::PersistedQueryNotFound)
+        ));
+    }
+
+    #[test]
+    fn requests_without_a_query_or_with_another_version_are_invalid() {
+        let queries = PersistedQueries::load(&GraphQLConfig::default()).unwrap();
+
+        assert_eq!(invalid_field(resolved(&queries, request(json!({})))), "query");
+        assert_eq!(
+            invalid_field(resolved(
+                &queries,
+                request(json!({ "extensions": { "persistedQuery": { "version": 2, "sha256Hash": hash(QUERY) } } }))
+            )),
+            "extensions.persistedQuery.version"
+        );
+        assert_eq!(resolved(&queries, request(json!({ "query": QUERY }))).unwrap(), QUERY);
+    }
+
+    #[test]
+    fn operations_of_the_manifest_are_known() {
+        let queries = with_manifest(&[(&hash(QUERY), QUERY)], false).unwrap();
+
+        assert_eq!(resolved(&queries, persisted(&hash(QUERY), None)).unwrap(), QUERY);
+        assert_eq!(resolved(&queries, request(json!({ "query": "{ __typename }" }))).unwrap(), "{ __typename }");
+    }
+
+    #[test]
+    fn manifests_must_use_the_hashes_of_the_bodies() {
+        assert!(matches!(
+            with_manifest(&[(&hash("{ __typename }"), QUERY)], false),
+            Err(AppError::Internal(_))
+        ));
+    }
+
+    #[test]
+    fn the_allow_list_only_executes_the_operations_of_the_manifest() {
+        let queries = with_manifest(&[(&hash(QUERY), QUERY)], true).unwrap();
+        let other = "{ __typename }";
+
+        assert_eq!(resolved(&queries, request(json!({ "query": QUERY }))).unwrap(), QUERY);
+        assert_eq!(resolved(&queries, persisted(&hash(QUERY), Some(QUERY))).unwrap(), QUERY);
+        assert!(matches!(
+            resolved(&queries, request(json!({ "query": other }))),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            resolved(&queries, persisted(&hash(other), Some(other))),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            resolved(&queries, persisted(&hash(other), None)),
+            Err(AppError::PersistedQueryNotFound)
+        ));
+    }
+}
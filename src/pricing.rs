@@ -0,0 +1,230 @@
+use crate::errors::{AppError, AppResult};
+use crate::models::price_grids::PriceGrid;
+use crate::models::quotes::QuoteParameters;
+
+// Line of a priced document. Amounts are in euro cents, excluding tax.
+#[derive(Debug, Clone, Serialize, Deserialize, GraphQLObject)]
+#[graphql(description = "Line of a quote, amounts in euro cents excluding tax")]
+pub struct PriceLine {
+    pub label: String,
+    pub quantity: i32,
+    pub unit_price: i32,
+    pub amount: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Totals {
+    pub excluding_tax: i32,
+    pub vat_amount: i32,
+    pub including_tax: i32,
+}
+
+// Price a mission with a grid: the base price, then every step of area,
+// sample and kilometre past what the base price includes, unless the grid
+// doesn't charge for it. A total under the minimum of the grid gets a line
+// making up the difference.
+pub fn price(grid: &PriceGrid, parameters: &QuoteParameters) -> AppResult<Vec<PriceLine>> {
+    let mut lines = vec![line(parameters.mission_type.label().to_string(), 1, grid.base_price)?];
+
+    let extra_area = parameters.surface_area - grid.included_area;
+    if extra_area > 0.0 && grid.area_step_price > 0 {
+        let steps = (extra_area / grid.area_step).ceil() as i64;
+
+        lines.push(line(
+            format!(
+                "Surface au-delà de {} m², par tranche de {} m²",
+                grid.included_area, grid.area_step
+            ),
+            steps,
+            grid.area_step_price,
+        )?);
+    }
+
+    let extra_samples = i64::from(parameters.expected_samples) - i64::from(grid.included_samples);
+    if extra_samples > 0 && grid.sample_price > 0 {
+        lines.push(line(
+            format!("Analyses au-delà de {} prélèvements", grid.included_samples),
+            extra_samples,
+            grid.sample_price,
+        )?);
+    }
+
+    let extra_distance = i64::from(parameters.distance) - i64::from(grid.included_distance);
+    if extra_distance > 0 && grid.distance_price > 0 {
+        lines.push(line(
+            format!("Déplacement au-delà de {} km", grid.included_distance),
+            extra_distance,
+            grid.distance_price,
+        )?);
+    }
+
+    let subtotal: i64 = lines.iter().map(|line| i64::from(line.amount)).sum();
+    if subtotal < i64::from(grid.minimum_price) {
+        lines.push(line(
+            "Complément au minimum de facturation".to_string(),
+            1,
+            (i64::from(grid.minimum_price) - subtotal) as i32,
+        )?);
+    }
+
+    Ok(lines)
+}
+
+pub fn line(label: String, quantity: i64, unit_price: i32) -> AppResult<PriceLine> {
+    Ok(PriceLine {
+        label,
+        quantity: amount(quantity)?,
+        unit_price,
+        amount: amount(quantity * i64::from(unit_price))?,
+    })
+}
+
+// Cents that fit in a GraphQL Int
+fn amount(cents: i64) -> AppResult<i32> {
+    i32::try_from(cents).map_err(|_| AppError::invalid("parameters", "The price is too large"))
+}
+
+// Sum the lines and add VAT at `vat_rate`, in hundredths of a percent.
+// VAT is rounded to the nearest cent on the total, as invoices do.
+pub fn totals(lines: &[PriceLine], vat_rate: i32) -> AppResult<Totals> {
+    let excluding_tax: i64 = lines.iter().map(|line| i64::from(line.amount)).sum();
+    let vat_amount = (excluding_tax * i64::from(vat_rate) + 5_000).div_euclid(10_000);
+
+    Ok(Totals {
+        excluding_tax: amount(excluding_tax)?,
+        vat_amount: amount(vat_amount)?,
+        including_tax: amount(excluding_tax + vat_amount)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::missions::MissionType;
+
+    // 300 € including 100 m², 2 samples and 20 km, then 50 € per 50 m²,
+    // 40 € per sample and 0,50 € per km, never under 350 €
+    fn grid() -> PriceGrid {
+        PriceGrid {
+            id: 1,
+            mission_type: MissionType::AsbestosBeforeSale.code().to_string(),
+            base_price: 30_000,
+            included_area: 100.0,
+            area_step: 50.0,
+            area_step_price: 5_000,
+            included_samples: 2,
+            sample_price: 4_000,
+            included_distance: 20,
+            distance_price: 50,
+            minimum_price: 35_000,
+            valid_from: NaiveDate::from_ymd(2026, 1, 1),
+            created_by: None,
+            created_at: NaiveDate::from_ymd(2026, 1, 1).and_hms(0, 0, 0),
+        }
+    }
+
+    fn parameters(surface_area: f64, expected_samples: i32, distance: i32) -> QuoteParameters {
+        QuoteParameters {
+            mission_type: MissionType::AsbestosBeforeSale,
+            surface_area,
+            expected_samples,
+            distance,
+            property_address: "12 rue des Lilas, 75019 Paris".to_string(),
+        }
+    }
+
+    fn amounts(lines: &[PriceLine]) -> Vec<(i32, i32, i32)> {
+        lines.iter().map(|line| (line.quantity, line.unit_price, line.amount)).collect()
+    }
+
+    #[test]
+    fn every_started_step_is_charged() {
+        let lines = price(&grid(), &parameters(201.0, 5, 30)).unwrap();
+
+        assert_eq!(
+            amounts(&lines),
+            vec![(1, 30_000, 30_000), (3, 5_000, 15_000), (3, 4_000, 12_000), (10, 50, 500)]
+        );
+    }
+
+    #[test]
+    fn what_the_base_price_includes_is_not_charged() {
+        let lines = price(&grid(), &parameters(100.0, 2, 20)).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].amount, 30_000);
+    }
+
+    #[test]
+    fn steps_priced_zero_are_left_out() {
+        let grid = PriceGrid { area_step_price: 0, sample_price: 0, minimum_price: 0, ..grid() };
+        let lines = price(&grid, &parameters(500.0, 10, 30)).unwrap();
+
+        assert_eq!(amounts(&lines), vec![(1, 30_000, 30_000), (10, 50, 500)]);
+    }
+
+    #[test]
+    fn small_missions_are_made_up_to_the_minimum() {
+        let lines = price(&grid(), &parameters(50.0, 0, 0)).unwrap();
+
+        assert_eq!(amounts(&lines), vec![(1, 30_000, 30_000), (1, 5_000, 5_000)]);
+        assert_eq!(lines[1].label, "Complément au minimum de facturation");
+        assert_eq!(totals(&lines, 2_000).unwrap().excluding_tax, grid().minimum_price);
+
+        // Nothing is added once the minimum is reached
+        let lines = price(&grid(), &parameters(150.0, 0, 0)).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].amount, 5_000);
+    }
+
+    #[test]
+    fn prices_past_a_graphql_int_are_invalid() {
+        let grid = PriceGrid { area_step: 0.01, area_step_price: 1_000_000, ..grid() };
+        let err = price(&grid, &parameters(1_000_000.0, 0, 0)).unwrap_err();
+
+        assert!(matches!(err, AppError::Validation(_)));
+        assert!(line("Ligne".to_string(), i64::from(i32::MAX) + 1, 1).is_err());
+        assert!(line("Ligne".to_string(), 3, i32::MAX).is_err());
+    }
+
+    fn lines(amounts: &[i32]) -> Vec<PriceLine> {
+        amounts.iter().map(|amount| line("Ligne".to_string(), 1, *amount).unwrap()).collect()
+    }
+
+    #[test]
+    fn vat_is_added_on_the_sum_of_the_lines() {
+        let totals = totals(&lines(&[30_000, 12_000, 500]), 2_000).unwrap();
+
+        assert_eq!(totals.excluding_tax, 42_500);
+        assert_eq!(totals.vat_amount, 8_500);
+        assert_eq!(totals.including_tax, 51_000);
+    }
+
+    // Rates are in basis points, 5,5 % is 550
+    #[test]
+    fn vat_rounds_half_cents_up() {
+        // 0,55 × 5,5 % = 3,025 cents
+        assert_eq!(totals(&lines(&[55]), 550).unwrap().vat_amount, 3);
+        // 0,10 × 5 % = 0,5 cent
+        assert_eq!(totals(&lines(&[10]), 500).unwrap().vat_amount, 1);
+        // 0,09 × 5 % = 0,45 cent
+        assert_eq!(totals(&lines(&[9]), 500).unwrap().vat_amount, 0);
+        // Rounded once on the total, not per line: 3 × 0,5 cent
+        assert_eq!(totals(&lines(&[10, 10, 10]), 500).unwrap().vat_amount, 2);
+    }
+
+    #[test]
+    fn negative_totals_round_half_up_too() {
+        // -0,10 × 5 % = -0,5 cent, up to 0
+        assert_eq!(totals(&lines(&[-10]), 500).unwrap().vat_amount, 0);
+        assert_eq!(totals(&lines(&[-11]), 500).unwrap().vat_amount, -1);
+        assert_eq!(totals(&lines(&[30_000, -10_000]), 2_000).unwrap().including_tax, 24_000);
+    }
+
+    #[test]
+    fn totals_past_a_graphql_int_are_invalid() {
+        assert!(totals(&lines(&[i32::MAX, 1]), 0).is_err());
+        assert!(totals(&lines(&[i32::MAX]), 2_000).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use diesel::prelude::*;
+use std::fs;
+use crate::config::Config;
+use crate::dates;
+use crate::errors::AppResult;
+use crate::missions::{Domain, MissionType};
+use crate::models::clients::Client;
+use crate::models::instruments::InstrumentRecord;
+use crate::models::report_signatures::{NewReportSignature, ReportSignature};
+use crate::models::users::User;
+use crate::models::worksite_versions::{compare, WorksiteRevision, WorksiteVersion};
+use crate::models::worksites::{Worksite, WorksiteContent};
+use crate::pdf::{Line, SignatureField};
+use crate::signing;
+
+// Render the report of an issued revision to disk, signed by whoever issued
+// it, and remember where it is and what it hashes to
+pub fn write_revision_report(
+    conn: &PgConnection,
+    config: &Config,
+    revision: &WorksiteRevision,
+) -> AppResult<WorksiteRevision> {
+    use crate::schema::{clients, users, worksite_versions, worksites};
+
+    // Shutdown waits for the file to be written and recorded
+    let _task = crate::shutdown::track("revision report");
+
+    let worksite: Worksite = worksites::table.find(revision.worksite_id).first(conn)?;
+    let client: Client = clients::table.find(worksite.client_id).first(conn)?;
+    let version: WorksiteVersion = worksite_versions::table.find(revision.version_id).first(conn)?;
+
+    let previous = match revision.previous(conn)? {
+        Some(previous) => Some((
+            previous.revision,
+            worksite_versions::table.find(previous.version_id).first::<WorksiteVersion>(conn)?,
+        )),
+        None => None,
+    };
+
+    let signer = match revision.issued_by {
+        Some(issuer) => users::table.find(issuer).first::<User>(conn).optional()?,
+        None => None,
+    };
+    let signer_name = signer
+        .map(|signer| signer.name)
+        .unwrap_or_else(|| config.billing.company_name.clone());
+
+    let instruments = InstrumentRecord::of_sessions(conn, version.worksite.lead_sessions.as_deref().unwrap_or_default())?;
+
+    let lines = revision_report(&client, revision, &version, previous.as_ref(), &instruments);
+    let title = format!("Rapport chantier {} - Rev {}", worksite.id, revision.revision);
+    let field = SignatureField {
+        name: signer_name.clone(),
+        reason: format!("Émission de la Rev {}", revision.revision),
+        signed_at: chrono::offset::Utc::now().naive_utc(),
+    };
+    let sealed = signing::seal(&config.signing, &title, &lines, field)?;
+
+    fs::create_dir_all(&config.storage.reports_path)?;
+
+    let path = config
+        .storage
+        .reports_path
+        .join(format!("worksite-{}-rev{}.pdf", worksite.id, revision.revision));
+    fs::write(&path, &sealed.document)?;
+
+    ReportSignature::record(
+        conn,
+        NewReportSignature {
+            revision_id: &revision.id,
+            content_hash: &sealed.content_hash,
+            signed_by: revision.issued_by,
+            signer_name: &signer_name,
+            certificate_subject: sealed.certificate_subject.as_deref(),
+            signed_at: &sealed.signed_at,
+        },
+    )?;
+
+    Ok(revision.set_report_path(conn, &path.to_string_lossy())?)
+}
+
+fn revision_report(
+    client: &Client,
+    revision: &WorksiteRevision,
+    version: &WorksiteVersion,
+    previous: Option<&(i32, WorksiteVersion)>,
+    instruments: &[InstrumentRecord],
+) -> Vec<Line> {
+    let content: &WorksiteContent = &version.worksite;
+
+    let folder_number = content
+        .worksite_information
+        .as_ref()
+        .map(|information| information.folder_number.clone())
+        .unwrap_or_else(|| "-".to_string());
+
+    // The mission picks the template: its title and the sections printed.
+    // Worksites from before mission types print every section.
+    let mission = content.mission();
+    let domain = mission.map(MissionType::domain);
+    let title = mission.map(MissionType::label).unwrap_or("Rapport de repérage");
+
+    let mut lines = vec![
+        Line::Heading(format!("{} - Dossier {}", title, folder_number)),
+        Line::Text(format!("Client : {}", client.name)),
+        Line::Text(format!(
+            "Adresse : {} {} {} {}",
+            client.address.street_number,
+            client.address.street,
+            client.address.postal_code.as_deref().unwrap_or_default(),
+            client.address.city.as_deref().unwrap_or_default(),
+        ).trim_end().to_string()),
+        Line::Text(format!(
+            "Révision : Rev {} émise le {}",
+            revision.revision,
+            dates::paris(revision.issued_at).format("%d/%m/%Y %H:%M")
+        )),
+        Line::Text(format!("Version du document : {}", version.version)),
+    ];
+
+    if let Some((previous_revision, previous_version)) = previous {
+        lines.push(Line::Heading(format!("Modifications depuis la Rev {}", previous_revision)));
+
+        let changes = compare(&previous_version.worksite, content);
+
+        if changes.is_empty() {
+            lines.push(Line::Text("Aucune modification".to_string()));
+        }
+
+        for change in changes {
+            lines.push(Line::Text(format!(
+                "{} : {} -> {}",
+                change.path,
+                change.before.unwrap_or_else(|| "(vide)".to_string()),
+                change.after.unwrap_or_else(|| "(vide)".to_string()),
+            )));
+        }
+    }
+
+    if domain != Some(Domain::Lead) {
+        asbestos_section(&mut lines, content);
+    }
+    if domain != Some(Domain::Asbestos) {
+        lead_section(&mut lines, content, instruments);
+    }
+
+    lines
+}
+
+fn asbestos_section(lines: &mut Vec<Line>, content: &WorksiteContent) {
+    lines.push(Line::Heading("Amiante".to_string()));
+
+    match &content.asbestos {
+        Some(asbestos) if !asbestos.is_empty() => {
+            for sample in asbestos {
+                lines.push(Line::Text(format!(
+                    "Unité {} - {} / {} : {} ({}), prélèvement {} du {}, résultat {}, état de conservation {}",
+                    sample.unit,
+                    sample.area,
+                    sample.localization,
+                    sample.surveyed_element,
+                    sample.materials_description,
+                    sample.sampling,
+                    sample.date_of_sampling,
+                    sample.fcr_result,
+                    sample.conservation_state,
+                )));
+            }
+        }
+        _ => lines.push(Line::Text("Aucun repérage amiante".to_string())),
+    }
+}
+
+fn lead_section(lines: &mut Vec<Line>, content: &WorksiteContent, instruments: &[InstrumentRecord]) {
+    lines.push(Line::Heading("Plomb".to_string()));
+
+    // The regulation asks for the analyser, its source and the readings of
+    // the reference standard of every session
+    for (index, session) in content.lead_sessions.iter().flatten().enumerate() {
+        let record = instruments.iter().find(|record| record.instrument.id == session.instrument_id);
+
+        match record {
+            Some(record) => {
+                let instrument = &record.instrument;
+                let check = dates::parse(&session.date).and_then(|day| record.usable_on(day).ok());
+
+                lines.push(Line::Text(format!(
+                    "Session {} du {} : analyseur {} {} n° {}, source {} du {}{}",
+                    index + 1,
+                    session.date,
+                    instrument.manufacturer,
+                    instrument.model,
+                    instrument.serial_number,
+                    instrument.radionuclide,
+                    instrument.source_date.format("%d/%m/%Y"),
+                    check
+                        .map(|check| format!(", vérifié le {} par {}", check.checked_on.format("%d/%m/%Y"), check.body))
+                        .unwrap_or_default(),
+                )));
+            }
+            None => lines.push(Line::Text(format!(
+                "Session {} du {} : analyseur {}",
+                index + 1,
+                session.date,
+                session.instrument_id
+            ))),
+        }
+
+        lines.push(Line::Text(format!(
+            "Étalon {} mg/cm², lecture en début de session {} mg/cm², en fin de session {} mg/cm²",
+            session.reference_value, session.start_reading, session.end_reading,
+        )));
+    }
+
+    match &content.leads {
+        Some(leads) if !leads.is_empty() => {
+            for lead in leads {
+                lines.push(Line::Text(format!(
+                    "N°{} - {} / {} : {} sur {} ({}), mesure {} ± {} : {}",
+                    lead.number,
+                    lead.localization,
+                    lead.area,
+                    lead.diagnostic_unity,
+                    lead.substrate,
+                    lead.exposed_coating,
+                    lead.measure,
+                    lead.incertitude,
+                    lead.result,
+                )));
+            }
+        }
+        _ => lines.push(Line::Text("Aucune mesure plomb".to_string())),
+    }
+}
@@ -1,3 +1,32 @@
+table! {
+    analysis_orders (id) {
+        id -> Int4,
+        laboratory_id -> Int4,
+        worksite_id -> Int4,
+        samples -> Jsonb,
+        analysis_type -> Varchar,
+        urgency -> Varchar,
+        sent_on -> Date,
+        expected_on -> Date,
+        received_on -> Nullable<Date>,
+        status -> Varchar,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    audit_logs (id) {
+        id -> Int4,
+        actor_id -> Nullable<Int4>,
+        entity -> Varchar,
+        entity_id -> Int4,
+        operation -> Varchar,
+        diff -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     authorizations (id) {
         id -> Int4,
@@ -5,6 +34,34 @@ table! {
     }
 }
 
+table! {
+    calibration_checks (id) {
+        id -> Int4,
+        instrument_id -> Int4,
+        checked_on -> Date,
+        valid_until -> Date,
+        body -> Varchar,
+        passed -> Bool,
+        certificate_number -> Nullable<Varchar>,
+        recorded_by -> Nullable<Int4>,
+        recorded_at -> Timestamp,
+    }
+}
+
+table! {
+    certifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        domain -> Varchar,
+        body -> Varchar,
+        number -> Varchar,
+        valid_from -> Date,
+        valid_until -> Date,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     clients (id) {
         id -> Int4,
@@ -16,6 +73,176 @@ table! {
     }
 }
 
+table! {
+    credit_notes (id) {
+        id -> Int4,
+        number -> Varchar,
+        invoice_id -> Int4,
+        reason -> Varchar,
+        lines -> Jsonb,
+        total_excluding_tax -> Int4,
+        vat_rate -> Int4,
+        vat_amount -> Int4,
+        total_including_tax -> Int4,
+        document_path -> Nullable<Varchar>,
+        issued_by -> Nullable<Int4>,
+        issued_at -> Timestamp,
+    }
+}
+
+table! {
+    document_sequences (series, year) {
+        series -> Varchar,
+        year -> Int4,
+        last_number -> Int4,
+    }
+}
+
+table! {
+    instruments (id) {
+        id -> Int4,
+        manufacturer -> Varchar,
+        model -> Varchar,
+        serial_number -> Varchar,
+        radionuclide -> Varchar,
+        source_date -> Date,
+        retired_on -> Nullable<Date>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    invoices (id) {
+        id -> Int4,
+        number -> Varchar,
+        client_id -> Int4,
+        worksite_id -> Int4,
+        quote_id -> Nullable<Int4>,
+        lines -> Jsonb,
+        total_excluding_tax -> Int4,
+        vat_rate -> Int4,
+        vat_amount -> Int4,
+        total_including_tax -> Int4,
+        amount_paid -> Int4,
+        amount_credited -> Int4,
+        status -> Varchar,
+        due_date -> Date,
+        document_path -> Nullable<Varchar>,
+        issued_by -> Nullable<Int4>,
+        issued_at -> Timestamp,
+    }
+}
+
+table! {
+    jobs (id) {
+        id -> Int4,
+        kind -> Varchar,
+        payload -> Jsonb,
+        status -> Varchar,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        result -> Nullable<Jsonb>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    laboratories (id) {
+        id -> Int4,
+        name -> Varchar,
+        accreditation_number -> Varchar,
+        contact_name -> Nullable<Varchar>,
+        email -> Nullable<Varchar>,
+        phone -> Nullable<Varchar>,
+        turnaround_days -> Int4,
+        urgent_turnaround_days -> Int4,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    payments (id) {
+        id -> Int4,
+        invoice_id -> Int4,
+        amount -> Int4,
+        paid_on -> Date,
+        method -> Varchar,
+        reference -> Nullable<Varchar>,
+        recorded_by -> Nullable<Int4>,
+        recorded_at -> Timestamp,
+    }
+}
+
+table! {
+    price_grids (id) {
+        id -> Int4,
+        mission_type -> Varchar,
+        base_price -> Int4,
+        included_area -> Float8,
+        area_step -> Float8,
+        area_step_price -> Int4,
+        included_samples -> Int4,
+        sample_price -> Int4,
+        included_distance -> Int4,
+        distance_price -> Int4,
+        minimum_price -> Int4,
+        valid_from -> Date,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    quotes (id) {
+        id -> Int4,
+        number -> Varchar,
+        client_id -> Int4,
+        mission_type -> Varchar,
+        parameters -> Jsonb,
+        lines -> Jsonb,
+        total_excluding_tax -> Int4,
+        vat_rate -> Int4,
+        vat_amount -> Int4,
+        total_including_tax -> Int4,
+        status -> Varchar,
+        valid_until -> Date,
+        worksite_id -> Nullable<Int4>,
+        document_path -> Nullable<Varchar>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+        edited_at -> Timestamp,
+    }
+}
+
+table! {
+    report_signatures (id) {
+        id -> Int4,
+        revision_id -> Int4,
+        content_hash -> Varchar,
+        signed_by -> Nullable<Int4>,
+        signer_name -> Varchar,
+        certificate_subject -> Nullable<Varchar>,
+        signed_at -> Timestamp,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
@@ -25,6 +252,29 @@ table! {
     }
 }
 
+table! {
+    worksite_revisions (id) {
+        id -> Int4,
+        worksite_id -> Int4,
+        version_id -> Int4,
+        revision -> Int4,
+        issued_by -> Nullable<Int4>,
+        issued_at -> Timestamp,
+        report_path -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    worksite_versions (id) {
+        id -> Int4,
+        worksite_id -> Int4,
+        version -> Int4,
+        worksite -> Jsonb,
+        author_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     worksites (id) {
         id -> Int4,
@@ -36,12 +286,59 @@ table! {
     }
 }
 
+joinable!(analysis_orders -> laboratories (laboratory_id));
+joinable!(analysis_orders -> users (created_by));
+joinable!(analysis_orders -> worksites (worksite_id));
+joinable!(audit_logs -> users (actor_id));
+joinable!(calibration_checks -> instruments (instrument_id));
+joinable!(calibration_checks -> users (recorded_by));
+joinable!(certifications -> users (user_id));
+joinable!(credit_notes -> invoices (invoice_id));
+joinable!(credit_notes -> users (issued_by));
+joinable!(instruments -> users (created_by));
+joinable!(invoices -> clients (client_id));
+joinable!(invoices -> quotes (quote_id));
+joinable!(invoices -> users (issued_by));
+joinable!(invoices -> worksites (worksite_id));
+joinable!(jobs -> users (created_by));
+joinable!(laboratories -> users (created_by));
+joinable!(payments -> invoices (invoice_id));
+joinable!(payments -> users (recorded_by));
+joinable!(price_grids -> users (created_by));
+joinable!(quotes -> clients (client_id));
+joinable!(quotes -> users (created_by));
+joinable!(quotes -> worksites (worksite_id));
+joinable!(report_signatures -> users (signed_by));
+joinable!(report_signatures -> worksite_revisions (revision_id));
+joinable!(sessions -> users (user_id));
 joinable!(users -> authorizations (authorization_id));
+joinable!(worksite_revisions -> users (issued_by));
+joinable!(worksite_revisions -> worksite_versions (version_id));
+joinable!(worksite_revisions -> worksites (worksite_id));
+joinable!(worksite_versions -> users (author_id));
+joinable!(worksite_versions -> worksites (worksite_id));
 joinable!(worksites -> clients (client_id));
 
 allow_tables_to_appear_in_same_query!(
+    analysis_orders,
+    audit_logs,
     authorizations,
+    calibration_checks,
+    certifications,
     clients,
+    credit_notes,
+    document_sequences,
+    instruments,
+    invoices,
+    jobs,
+    laboratories,
+    payments,
+    price_grids,
+    quotes,
+    report_signatures,
+    sessions,
     users,
+    worksite_revisions,
+    worksite_versions,
     worksites,
 );
@@ -0,0 +1,121 @@
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// Background work (report generation...) that must not be cut in the
+// middle when the process stops. Each one holds a `TaskGuard` while it runs.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+// Set once a signal is received, background workers stop taking new work
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref FINISHED: Notify = Notify::new();
+    static ref STOPPED: Notify = Notify::new();
+    static ref STOPPED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+pub struct TaskGuard {
+    name: &'static str,
+}
+
+// Register a running task, it is finished when the guard is dropped
+pub fn track(name: &'static str) -> TaskGuard {
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    TaskGuard { name }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if RUNNING.fetch_sub(1, Ordering::SeqCst) == 1 {
+            FINISHED.notify_waiters();
+        }
+        log::debug!("Task {} finished", self.name);
+    }
+}
+
+pub fn stop() {
+    STOPPED_AT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get_or_insert_with(Instant::now);
+    STOPPING.store(true, Ordering::SeqCst);
+    STOPPED.notify_waiters();
+}
+
+// When everything still running is given up on: `timeout` after stopping
+// began, so the server, the background tasks and the job workers share a
+// single allowance
+pub fn deadline(timeout: Duration) -> Instant {
+    let stopped_at = *STOPPED_AT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    stopped_at.unwrap_or_else(Instant::now) + timeout
+}
+
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+// Resolves once stopping, for long lived connections to close themselves
+pub async fn stopping() {
+    let stopped = STOPPED.notified();
+
+    if !is_stopping() {
+        stopped.await;
+    }
+}
+
+// Resolves on the first SIGINT or SIGTERM. Both stop the server gracefully,
+// unlike actix's own handling where SIGINT stops it at once.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use futures::future::{select, Either};
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen to SIGTERM");
+        let interrupt = Box::pin(tokio::signal::ctrl_c());
+        let termination = Box::pin(terminate.recv());
+
+        let received = select(interrupt, termination).await;
+
+        match received {
+            Either::Left(_) => info!("SIGINT received, shutting down"),
+            Either::Right(_) => info!("SIGTERM received, shutting down"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Interrupted, shutting down");
+    }
+}
+
+// Wait for the tracked tasks to finish, at most until `deadline`
+pub async fn drain(deadline: Instant) {
+    let wait = async {
+        loop {
+            let finished = FINISHED.notified();
+
+            if RUNNING.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            finished.await;
+        }
+    };
+
+    if RUNNING.load(Ordering::SeqCst) > 0 {
+        info!("Waiting for {} background tasks", RUNNING.load(Ordering::SeqCst));
+    }
+
+    if tokio::time::timeout_at(deadline.into(), wait).await.is_err() {
+        warn!(
+            "{} background tasks still running at the shutdown deadline, stopping anyway",
+            RUNNING.load(Ordering::SeqCst)
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use actix_web::{web, HttpResponse};
+use chrono::{NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use openssl::cms::{CMSOptions, CmsContentInfo};
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::{X509Ref, X509};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use crate::config::SigningConfig;
+use crate::database::PostgresPool;
+use crate::errors::{AppError, AppResult};
+use crate::models::report_signatures::ReportSignature;
+use crate::pdf::{self, Line, SignatureField, BYTE_RANGE_PLACEHOLDER, SIGNATURE_SIZE};
+
+// Largest PDF accepted by /reports/verify
+pub const MAX_REPORT_SIZE: usize = 20 * 1024 * 1024;
+
+// A rendered report, signed when a certificate is configured
+pub struct Sealed {
+    pub document: Vec<u8>,
+    pub content_hash: String,
+    pub certificate_subject: Option<String>,
+    pub signed_at: NaiveDateTime,
+}
+
+// Render a report and sign it with the certificate of the configuration.
+// Without one the report is rendered as before, only its hash is kept.
+pub fn seal(config: &SigningConfig, title: &str, lines: &[Line], field: SignatureField) -> AppResult<Sealed> {
+    let signed_at = field.signed_at;
+
+    let (document, certificate_subject) = match &config.certificate {
+        Some(certificate) => {
+            let signer = Signer::open(config, certificate)?;
+            let document = signer.sign(pdf::render_signable(title, lines, &field)?)?;
+
+            (document, Some(signer.subject.clone()))
+        }
+        None => (pdf::render(title, lines)?, None),
+    };
+
+    Ok(Sealed {
+        content_hash: content_hash(&document),
+        document,
+        certificate_subject,
+        signed_at,
+    })
+}
+
+// SHA-256 of a file, hexadecimal
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+// Certificate and key of the PKCS#12 file, kept in memory while the report
+// is signed
+struct Signer {
+    certificate: X509,
+    key: PKey<Private>,
+    // Intermediate certificates, embedded in the signature so readers can
+    // build the chain up to their trusted roots
+    chain: Option<Stack<X509>>,
+    subject: String,
+}
+
+impl Signer {
+    fn open(config: &SigningConfig, path: &Path) -> AppResult<Signer> {
+        let der = fs::read(path).map_err(|err| AppError::Internal(format!("cannot read {}: {}", path.display(), err)))?;
+        let parsed = Pkcs12::from_der(&der)
+            .and_then(|pkcs12| pkcs12.parse2(&config.certificate_password))
+            .map_err(|err| AppError::Internal(format!("cannot open {}: {}", path.display(), err)))?;
+
+        let (certificate, key) = match (parsed.cert, parsed.pkey) {
+            (Some(certificate), Some(key)) => (certificate, key),
+            _ => {
+                return Err(AppError::Internal(format!(
+                    "{} must hold a certificate and its private key",
+                    path.display()
+                )))
+            }
+        };
+
+        Ok(Signer {
+            subject: subject(&certificate),
+            certificate,
+            key,
+            chain: parsed.ca,
+        })
+    }
+
+    // Detached CMS signature of a signable document
+    fn sign(&self, document: Vec<u8>) -> AppResult<Vec<u8>> {
+        fill_signature(document, |signed| {
+            let flags = CMSOptions::BINARY | CMSOptions::DETACHED | CMSOptions::NOSMIMECAP;
+            let signature = CmsContentInfo::sign(
+                Some(&self.certificate),
+                Some(&self.key),
+                self.chain.as_deref(),
+                Some(signed),
+                flags,
+            )?;
+
+            Ok(signature.to_der()?)
+        })
+    }
+}
+
+// Fill in the byte range of a signable document, then the signature `sign`
+// makes of every byte but the signature itself. The placeholders keep their
+// width so no offset moves.
+fn fill_signature(mut document: Vec<u8>, sign: impl FnOnce(&[u8]) -> AppResult<Vec<u8>>) -> AppResult<Vec<u8>> {
+    let contents = format!("<{}>", "0".repeat(2 * SIGNATURE_SIZE));
+    let start = find(&document, contents.as_bytes())?;
+    let end = start + contents.len();
+
+    let placeholder = format!("[0 {0} {0} {0}]", BYTE_RANGE_PLACEHOLDER);
+    let at = find(&document, placeholder.as_bytes())?;
+    let range = format!("[0 {} {} {}", start, end, document.len() - end);
+    let range = format!("{:<width$}]", range, width = placeholder.len() - 1);
+    document[at..at + placeholder.len()].copy_from_slice(range.as_bytes());
+
+    let mut signed = document[..start].to_vec();
+    signed.extend_from_slice(&document[end..]);
+
+    let signature = sign(&signed)?;
+
+    if signature.len() > SIGNATURE_SIZE {
+        return Err(AppError::Internal(format!(
+            "the signature takes {} bytes, {} are reserved",
+            signature.len(),
+            SIGNATURE_SIZE
+        )));
+    }
+
+    let signature = hex::encode_upper(signature);
+    document[start + 1..start + 1 + signature.len()].copy_from_slice(signature.as_bytes());
+
+    Ok(document)
+}
+
+fn find(document: &[u8], needle: &[u8]) -> AppResult<usize> {
+    document
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .ok_or_else(|| AppError::Internal("the signature placeholder is missing from the document".to_string()))
+}
+
+// Subject of a certificate as RFC 2253 writes it, most specific attribute
+// first: `CN=Jean Dupont,O=General Service Amiantes,C=FR`
+fn subject(certificate: &X509Ref) -> String {
+    let mut attributes: Vec<String> = certificate
+        .subject_name()
+        .entries()
+        .map(|entry| {
+            let name = match entry.object().nid().short_name() {
+                Ok(name) => name.to_string(),
+                Err(_) => entry.object().to_string(),
+            };
+            let value = match entry.data().to_string() {
+                Ok(value) => escape_attribute(&value),
+                Err(_) => format!("#{}", hex::encode(entry.data().as_slice())),
+            };
+            format!("{}={}", name, value)
+        })
+        .collect();
+
+    attributes.reverse();
+    attributes.join(",")
+}
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+
+    for (index, character) in value.chars().enumerate() {
+        let leading = index == 0 && (character == '#' || character == ' ');
+        let trailing = index == last && character == ' ';
+
+        if leading || trailing || matches!(character, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+fn timestamp(utc: NaiveDateTime) -> String {
+    Utc.from_utc_datetime(&utc).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// Tell whether a PDF posted as the request body is a report we issued, by
+// its hash. Anyone holding a report may ask, so only what the report itself
+// says is returned.
+pub async fn verify(pool: web::Data<PostgresPool>, body: web::Bytes) -> HttpResponse {
+    let pool = pool.get_ref().clone();
+    let hash = content_hash(&body);
+    let lookup = hash.clone();
+
+    let found = web::block(move || -> AppResult<_> { Ok(ReportSignature::find_by_hash(&*pool.get()?, &lookup)?) })
+        .await
+        .unwrap_or_else(|err| Err(AppError::Internal(err.to_string())));
+
+    match found {
+        Ok(Some((signature, revision))) => HttpResponse::Ok().json(serde_json::json!({
+            "valid": true,
+            "content_hash": hash,
+            "worksite_id": revision.worksite_id,
+            "revision": revision.revision,
+            "issued_at": timestamp(revision.issued_at),
+            "signed": signature.certificate_subject.is_some(),
+            "signer_name": signature.signer_name,
+            "certificate_subject": signature.certificate_subject,
+            "signed_at": timestamp(signature.signed_at),
+        })),
+        Ok(None) => HttpResponse::Ok().json(serde_json::json!({
+            "valid": false,
+            "content_hash": hash,
+        })),
+        Err(err) => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": err.code(),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+    use std::env;
+
+    fn field() -> SignatureField {
+        SignatureField {
+            name: "Jean Dupont".to_string(),
+            reason: "Rapport de repérage".to_string(),
+            signed_at: NaiveDateTime::parse_from_str("2026-10-19 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+        }
+    }
+
+    fn signable() -> Vec<u8> {
+        let lines = [Line::Heading("Repérage".to_string()), Line::Text("Aucun matériau amianté".to_string())];
+
+        pdf::render_signable("Rapport", &lines, &field()).unwrap()
+    }
+
+    // `/ByteRange` of the signature dictionary of a document
+    fn byte_range(document: &[u8]) -> Vec<usize> {
+        let document = lopdf::Document::load_mem(document).unwrap();
+
+        document
+            .objects
+            .values()
+            .find_map(|object| object.as_dict().ok()?.get(b"ByteRange").ok()?.as_array().ok())
+            .unwrap()
+            .iter()
+            .map(|offset| offset.as_i64().unwrap() as usize)
+            .collect()
+    }
+
+    // A self-signed certificate in a PKCS#12 file protected by `password`
+    fn certificate(path: &Path, password: &str) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("C", "FR").unwrap();
+        name.append_entry_by_text("O", "General Service Amiantes").unwrap();
+        name.append_entry_by_text("CN", "Dupont, Jean").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let pkcs12 = Pkcs12::builder()
+            .name("signing")
+            .pkey(&key)
+            .cert(&builder.build())
+            .build2(password)
+            .unwrap();
+        fs::write(path, pkcs12.to_der().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn the_byte_range_covers_everything_but_the_signature() {
+        let document = signable();
+        let mut signed = vec![];
+
+        let filled = fill_signature(document.clone(), |bytes| {
+            signed = bytes.to_vec();
+            Ok(vec![0xab; 16])
+        })
+        .unwrap();
+
+        assert_eq!(filled.len(), document.len());
+
+        let range = byte_range(&filled);
+        let (start, end) = (range[1], range[2]);
+
+        assert_eq!(range[0], 0);
+        assert_eq!(end - start, 2 * SIGNATURE_SIZE + 2);
+        assert_eq!(end + range[3], filled.len());
+        assert_eq!(signed, [&filled[..start], &filled[end..]].concat());
+
+        let contents = &filled[start..end];
+        assert!(contents.starts_with(format!("<{}", "AB".repeat(16)).as_bytes()));
+        assert!(contents[33..].iter().take(2 * SIGNATURE_SIZE - 32).all(|digit| *digit == b'0'));
+        assert!(contents.ends_with(b">"));
+    }
+
+    #[test]
+    fn signatures_must_fit_their_placeholder() {
+        assert!(matches!(
+            fill_signature(signable(), |_| Ok(vec![0; SIGNATURE_SIZE + 1])),
+            Err(AppError::Internal(_))
+        ));
+        assert!(fill_signature(signable(), |_| Ok(vec![0; SIGNATURE_SIZE])).is_ok());
+    }
+
+    #[test]
+    fn documents_without_placeholders_are_refused() {
+        let document = pdf::render("Rapport", &[Line::Text("Aucun matériau amianté".to_string())]).unwrap();
+
+        assert!(matches!(fill_signature(document, |_| Ok(vec![])), Err(AppError::Internal(_))));
+    }
+
+    #[test]
+    fn reports_are_signed_with_the_certificate() {
+        let path = env::temp_dir().join(format!("signing-test-{}.p12", std::process::id()));
+        certificate(&path, "secret");
+
+        let config = SigningConfig {
+            certificate: Some(path.clone()),
+            certificate_password: "secret".to_string(),
+        };
+        let wrong_password = SigningConfig {
+            certificate_password: "wrong".to_string(),
+            ..config.clone()
+        };
+        let lines = [Line::Text("Aucun matériau amianté".to_string())];
+
+        let sealed = seal(&config, "Rapport", &lines, field());
+        let refused = seal(&wrong_password, "Rapport", &lines, field());
+        fs::remove_file(&path).unwrap();
+
+        let sealed = sealed.unwrap();
+        assert!(matches!(refused, Err(AppError::Internal(_))));
+        assert_eq!(
+            sealed.certificate_subject.as_deref(),
+            Some("CN=Dupont\\, Jean,O=General Service Amiantes,C=FR")
+        );
+        assert_eq!(sealed.content_hash, content_hash(&sealed.document));
+
+        let range = byte_range(&sealed.document);
+        let document = &sealed.document;
+        // The DER sequence is padded with zeros to the size of the placeholder
+        let contents = hex::decode(&document[range[1] + 1..range[2] - 1]).unwrap();
+        assert_eq!(contents[..2], [0x30, 0x82]);
+        let signature = &contents[..4 + usize::from(u16::from_be_bytes([contents[2], contents[3]]))];
+
+        let mut signature = CmsContentInfo::from_der(signature).unwrap();
+        let signed = [&document[..range[1]], &document[range[2]..]].concat();
+
+        signature
+            .verify(None, None, Some(&signed), None, CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY)
+            .unwrap();
+
+        let mut tampered = signed.clone();
+        tampered[0] ^= 1;
+        assert!(signature
+            .verify(None, None, Some(&tampered), None, CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY)
+            .is_err());
+    }
+}
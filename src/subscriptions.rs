@@ -0,0 +1,413 @@
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Closed, Message, MessageStream, Session};
+use futures::{stream, StreamExt};
+use juniper::http::{GraphQLRequest, GraphQLResponse};
+use juniper::{DefaultScalarValue, GraphQLError, IntoFieldError, Object, Value};
+use log::debug;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use crate::config::Config;
+use crate::context::GraphQLContext;
+use crate::database::{self, PostgresPool};
+use crate::errors::{AppError, AppResult};
+use crate::graphql::Schema;
+use crate::limits;
+use crate::loaders::Loaders;
+use crate::models::sessions::Session as UserSession;
+use crate::persisted_queries::{self, PersistedQueries};
+use crate::shutdown;
+
+// GraphQL over WebSocket, in both protocols Apollo Client can speak: the
+// `graphql-transport-ws` protocol of the graphql-ws library and the older
+// `graphql-ws` protocol of subscriptions-transport-ws
+#[derive(Clone, Copy, PartialEq)]
+enum Protocol {
+    TransportWs,
+    Legacy,
+}
+
+impl Protocol {
+    // From the subprotocols offered by the client, the legacy one when none
+    fn negotiate(req: &HttpRequest) -> Option<(Protocol, bool)> {
+        let offered = match req.headers().get(header::SEC_WEBSOCKET_PROTOCOL) {
+            Some(value) => value.to_str().ok()?,
+            None => return Some((Protocol::Legacy, false)),
+        };
+
+        let offered: Vec<&str> = offered.split(',').map(str::trim).collect();
+
+        if offered.contains(&"graphql-transport-ws") {
+            Some((Protocol::TransportWs, true))
+        } else if offered.contains(&"graphql-ws") {
+            Some((Protocol::Legacy, true))
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Protocol::TransportWs => "graphql-transport-ws",
+            Protocol::Legacy => "graphql-ws",
+        }
+    }
+
+    // Type of the messages carrying a result
+    fn data(self) -> &'static str {
+        match self {
+            Protocol::TransportWs => "next",
+            Protocol::Legacy => "data",
+        }
+    }
+}
+
+// Legacy clients expect a keep alive message, the other protocol relies on
+// the WebSocket pings
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// Operations running at once on a connection
+const MAX_OPERATIONS: usize = 50;
+
+#[derive(Deserialize)]
+struct ClientMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    id: Option<String>,
+    payload: Option<serde_json::Value>,
+}
+
+enum Incoming {
+    Message(Message),
+    KeepAlive,
+    Stop,
+    Shutdown,
+}
+
+fn close(code: u16, description: &str) -> CloseReason {
+    CloseReason {
+        code: CloseCode::Other(code),
+        description: Some(description.to_string()),
+    }
+}
+
+async fn send(session: &mut Session, message: serde_json::Value) -> Result<(), Closed> {
+    session.text(message.to_string()).await
+}
+
+pub async fn subscriptions(
+    req: HttpRequest,
+    body: web::Payload,
+    config: web::Data<Arc<Config>>,
+    pool: web::Data<PostgresPool>,
+    schema: web::Data<Arc<Schema>>,
+    persisted: web::Data<Arc<PersistedQueries>>,
+) -> Result<HttpResponse, Error> {
+    // CORS doesn't cover WebSockets, browsers send their origin anyway
+    let any_origin = config.server.cors_origins.iter().any(|origin| origin == "*");
+    if let Some(origin) = req.headers().get(header::ORIGIN) {
+        if !any_origin && !config.server.cors_origins.iter().any(|allowed| allowed.as_bytes() == origin.as_bytes()) {
+            return Ok(HttpResponse::Forbidden().body("Origin not allowed"));
+        }
+    }
+
+    let (protocol, offered) = match Protocol::negotiate(&req) {
+        Some(negotiated) => negotiated,
+        None => return Ok(HttpResponse::BadRequest().body("Supported subprotocols: graphql-transport-ws, graphql-ws")),
+    };
+
+    let (mut response, session, messages) = actix_ws::handle(&req, body)?;
+    let messages = messages.max_frame_size(config.limits.max_body_size);
+
+    if offered {
+        response
+            .headers_mut()
+            .insert(header::SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocol.name()));
+    }
+
+    let connection = Connection {
+        protocol,
+        session,
+        schema: schema.get_ref().clone(),
+        persisted: persisted.get_ref().clone(),
+        config: config.get_ref().clone(),
+        pool: pool.get_ref().clone(),
+        bearer: crate::bearer_token(&req),
+        address: req.peer_addr().map(|address| address.ip()),
+        context: None,
+        operations: HashMap::new(),
+    };
+
+    actix_web::rt::spawn(connection.run(messages));
+
+    Ok(response)
+}
+
+struct Connection {
+    protocol: Protocol,
+    session: Session,
+    schema: Arc<Schema>,
+    persisted: Arc<PersistedQueries>,
+    config: Arc<Config>,
+    pool: PostgresPool,
+    // Token of the upgrade request, clients can also give it on init
+    bearer: Option<String>,
+    // Rate limited like HTTP requests, by user or address
+    address: Option<IpAddr>,
+    // Set once the connection is initialised
+    context: Option<Arc<GraphQLContext>>,
+    operations: HashMap<String, JoinHandle<()>>,
+}
+
+impl Connection {
+    async fn run(mut self, messages: MessageStream) {
+        let messages = messages
+            .map(|message| match message {
+                Ok(message) => Incoming::Message(message),
+                Err(_) => Incoming::Stop,
+            })
+            .chain(stream::once(async { Incoming::Stop }));
+        let keep_alive = stream::unfold((), |_| async {
+            tokio::time::sleep(KEEP_ALIVE).await;
+            Some((Incoming::KeepAlive, ()))
+        });
+        let stopping = stream::once(shutdown::stopping()).map(|_| Incoming::Shutdown);
+
+        let mut incoming = Box::pin(stream::select(messages, stream::select(keep_alive, stopping)));
+        let mut reason = None;
+
+        while let Some(incoming) = incoming.next().await {
+            let handled = match incoming {
+                Incoming::Message(Message::Text(text)) => self.receive(&text).await,
+                Incoming::Message(Message::Ping(bytes)) => self.session.pong(&bytes).await.map_err(|_| None),
+                Incoming::Message(Message::Close(_)) | Incoming::Stop => break,
+                Incoming::Shutdown => Err(Some(CloseReason::from(CloseCode::Away))),
+                Incoming::Message(_) => Ok(()),
+                Incoming::KeepAlive if self.protocol == Protocol::Legacy && self.context.is_some() => {
+                    send(&mut self.session, json!({ "type": "ka" })).await.map_err(|_| None)
+                }
+                Incoming::KeepAlive => Ok(()),
+            };
+
+            if let Err(close) = handled {
+                reason = close;
+                break;
+            }
+        }
+
+        for (_, operation) in self.operations.drain() {
+            operation.abort();
+        }
+        let _ = self.session.close(reason).await;
+    }
+
+    // Handle a message of the client, an error closes the connection with
+    // the reason given, if any
+    async fn receive(&mut self, text: &str) -> Result<(), Option<CloseReason>> {
+        let message: ClientMessage =
+            serde_json::from_str(text).map_err(|_| Some(close(4400, "Invalid message")))?;
+
+        match (self.protocol, message.kind.as_str()) {
+            (_, "connection_init") => {
+                if self.context.is_some() {
+                    return Err(Some(close(4429, "Too many initialisation requests")));
+                }
+
+                match self.authenticate(message.payload).await {
+                    Ok(context) => {
+                        self.context = Some(Arc::new(context));
+                        send(&mut self.session, json!({ "type": "connection_ack" })).await.map_err(|_| None)
+                    }
+                    Err(err) => {
+                        if self.protocol == Protocol::Legacy {
+                            let error = json!({ "type": "connection_error", "payload": { "message": err.to_string() } });
+                            let _ = send(&mut self.session, error).await;
+                        }
+                        Err(Some(close(4403, "Forbidden")))
+                    }
+                }
+            }
+            (Protocol::TransportWs, "ping") => {
+                send(&mut self.session, json!({ "type": "pong" })).await.map_err(|_| None)
+            }
+            (Protocol::TransportWs, "pong") => Ok(()),
+            (Protocol::TransportWs, "subscribe") | (Protocol::Legacy, "start") => {
+                self.start(message.id, message.payload).await
+            }
+            (Protocol::TransportWs, "complete") | (Protocol::Legacy, "stop") => {
+                if let Some(operation) = message.id.and_then(|id| self.operations.remove(&id)) {
+                    operation.abort();
+                }
+                Ok(())
+            }
+            (Protocol::Legacy, "connection_terminate") => Err(None),
+            _ => Err(Some(close(4400, "Unknown message type"))),
+        }
+    }
+
+    // The user of the token given on init, or of the upgrade request
+    async fn authenticate(&self, payload: Option<serde_json::Value>) -> AppResult<GraphQLContext> {
+        let from_payload = payload.as_ref().and_then(|payload| {
+            ["Authorization", "authorization", "authToken", "token"]
+                .iter()
+                .find_map(|key| payload.get(*key)?.as_str())
+                .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).trim().to_string())
+        });
+        let session_token = from_payload.or_else(|| self.bearer.clone());
+
+        let user = match session_token.clone() {
+            Some(token) => Some(
+                database::run(&self.pool, move |conn| Ok(UserSession::user_for_token(conn, &token)?))
+                    .await?
+                    .ok_or(AppError::Unauthorized)?,
+            ),
+            None => None,
+        };
+
+        Ok(GraphQLContext {
+            config: self.config.clone(),
+            pool: self.pool.clone(),
+            session_token,
+            user,
+            loaders: Loaders::new(&self.pool),
+        })
+    }
+
+    async fn start(&mut self, id: Option<String>, payload: Option<serde_json::Value>) -> Result<(), Option<CloseReason>> {
+        let context = match &self.context {
+            Some(context) => context.clone(),
+            None => return Err(Some(close(4401, "Unauthorized"))),
+        };
+        let id = id.ok_or_else(|| Some(close(4400, "Missing operation id")))?;
+        let request: persisted_queries::Request = serde_json::from_value(payload.unwrap_or_default())
+            .map_err(|_| Some(close(4400, "Invalid operation")))?;
+
+        self.operations.retain(|_, operation| !operation.is_finished());
+
+        if self.operations.contains_key(&id) {
+            return Err(Some(close(4409, &format!("Subscriber for {} already exists", id))));
+        }
+        if self.operations.len() >= MAX_OPERATIONS {
+            let error = json!([{ "message": format!("At most {} operations per connection", MAX_OPERATIONS) }]);
+            return self.send_error(&id, error).await.map_err(|_| None);
+        }
+
+        let client = limits::client_key(context.user.as_ref(), self.address);
+        let allowed = limits::throttle(self.config.limits.rate_limit, &client, 1)
+            .and_then(|_| self.persisted.resolve(request))
+            .and_then(|request| {
+                limits::check(&self.config.limits, &self.schema, &request)?;
+                Ok(request)
+            });
+
+        let request = match allowed {
+            Ok(request) => request,
+            Err(err) => {
+                let response = serde_json::to_value(GraphQLResponse::<DefaultScalarValue>::error(err.into_field_error()))
+                    .unwrap_or_default();
+                let errors = response.get("errors").cloned().unwrap_or_default();
+
+                return self.send_error(&id, errors).await.map_err(|_| None);
+            }
+        };
+
+        let operation = actix_web::rt::spawn(operation(
+            self.schema.clone(),
+            context,
+            request,
+            id.clone(),
+            self.protocol,
+            self.session.clone(),
+        ));
+        self.operations.insert(id, operation);
+
+        Ok(())
+    }
+
+    async fn send_error(&mut self, id: &str, errors: serde_json::Value) -> Result<(), Closed> {
+        send(&mut self.session, error_message(self.protocol, id, errors)).await
+    }
+}
+
+// Errors preventing an operation to run, an array of GraphQL errors in the
+// current protocol and a single one in the legacy protocol
+fn error_message(protocol: Protocol, id: &str, errors: serde_json::Value) -> serde_json::Value {
+    let payload = match protocol {
+        Protocol::TransportWs => errors,
+        Protocol::Legacy => errors.get(0).cloned().unwrap_or_default(),
+    };
+
+    json!({ "type": "error", "id": id, "payload": payload })
+}
+
+// Run one operation of a connection until its stream ends or the client
+// stops it. Queries and mutations are accepted too and send a single result.
+async fn operation(
+    schema: Arc<Schema>,
+    context: Arc<GraphQLContext>,
+    request: GraphQLRequest,
+    id: String,
+    protocol: Protocol,
+    mut session: Session,
+) {
+    let data = |response: &GraphQLResponse| {
+        json!({
+            "type": protocol.data(),
+            "id": id,
+            "payload": serde_json::to_value(response).unwrap_or_default(),
+        })
+    };
+
+    match juniper::http::resolve_into_stream(&request, &schema, &context).await {
+        Ok((Value::Object(fields), errors)) if errors.is_empty() => {
+            let streams = fields.into_iter().filter_map(|(name, value)| match value {
+                Value::Scalar(values) => Some(values.map(move |value| {
+                    GraphQLResponse::from_result(match value {
+                        Ok(value) => {
+                            let mut data = Object::with_capacity(1);
+                            data.add_field(name.clone(), value);
+                            Ok((Value::object(data), vec![]))
+                        }
+                        Err(error) => Ok((Value::null(), vec![error])),
+                    })
+                })),
+                _ => None,
+            });
+
+            let mut responses = stream::select_all(streams);
+
+            while let Some(response) = responses.next().await {
+                if send(&mut session, data(&response)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        Ok((_, errors)) => {
+            let response = GraphQLResponse::from_result(Ok((Value::null(), errors)));
+            if send(&mut session, data(&response)).await.is_err() {
+                return;
+            }
+        }
+        Err(GraphQLError::NotSubscription) => {
+            let response = crate::execute(&request, &schema, &context).await;
+            if send(&mut session, data(&response)).await.is_err() {
+                return;
+            }
+        }
+        Err(err) => {
+            debug!("Subscription {} refused: {}", id, err);
+
+            let response = serde_json::to_value(GraphQLResponse::<DefaultScalarValue>::from_result(Err(err))).unwrap_or_default();
+            let errors = response.get("errors").cloned().unwrap_or_default();
+
+            let _ = send(&mut session, error_message(protocol, &id, errors)).await;
+            return;
+        }
+    }
+
+    let _ = send(&mut session, json!({ "type": "complete", "id": id })).await;
+}
@@ -0,0 +1,427 @@
+use crate::errors::{AppError, AppResult, FieldViolation};
+
+// Implemented by every GraphQL input. The rules are declared once per type
+// and run before anything touches the database.
+pub trait Validate {
+    fn rules(&self, v: &mut Validator);
+}
+
+// Check an input and turn every broken rule into a single validation error
+pub fn validate<T: Validate>(input: &T) -> AppResult<()> {
+    let mut validator = Validator::default();
+    input.rules(&mut validator);
+    validator.finish()
+}
+
+// Same for an optional argument, its violations are named after it
+pub fn validate_argument<T: Validate>(name: &str, value: &Option<T>) -> AppResult<()> {
+    let mut validator = Validator::default();
+    validator.nested(name, value);
+    validator.finish()
+}
+
+// Collects violations while walking an input. Field names are the GraphQL
+// (camelCase) names, nested inputs are prefixed with their parent path.
+#[derive(Default)]
+pub struct Validator {
+    prefix: String,
+    violations: Vec<FieldViolation>,
+}
+
+impl Validator {
+    pub fn finish(self) -> AppResult<()> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(self.violations))
+        }
+    }
+
+    fn fail(&mut self, field: &str, message: &str) {
+        let path = if self.prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", self.prefix, field)
+        };
+
+        self.violations.push(FieldViolation::new(&path, message));
+    }
+
+    pub fn required<T>(&mut self, field: &str, value: &Option<T>) -> &mut Self {
+        if value.is_none() {
+            self.fail(field, "Is required");
+        }
+        self
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) -> &mut Self {
+        if value.trim().is_empty() {
+            self.fail(field, "Must not be empty");
+        }
+        self
+    }
+
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let length = value.chars().count();
+
+        if length < min || length > max {
+            self.fail(field, &format!("Must be between {} and {} characters long", min, max));
+        }
+        self
+    }
+
+    pub fn range(&mut self, field: &str, value: i32, min: i32, max: i32) -> &mut Self {
+        if value < min || value > max {
+            self.fail(field, &format!("Must be between {} and {}", min, max));
+        }
+        self
+    }
+
+    pub fn between(&mut self, field: &str, value: f64, min: f64, max: f64) -> &mut Self {
+        if !(min..=max).contains(&value) {
+            self.fail(field, &format!("Must be between {} and {}", min, max));
+        }
+        self
+    }
+
+    pub fn one_of(&mut self, field: &str, value: &str, allowed: &[&str]) -> &mut Self {
+        if !allowed.contains(&value) {
+            self.fail(field, &format!("Must be one of {}", allowed.join(", ")));
+        }
+        self
+    }
+
+    // Rule the helpers don't cover, `message` says what is wrong
+    pub fn check(&mut self, field: &str, valid: bool, message: &str) -> &mut Self {
+        if !valid {
+            self.fail(field, message);
+        }
+        self
+    }
+
+    // French postal code, five digits
+    pub fn postal_code(&mut self, field: &str, value: &Option<String>) -> &mut Self {
+        if let Some(value) = value {
+            if value.len() != 5 || !value.chars().all(|c| c.is_ascii_digit()) {
+                self.fail(field, "Must be a 5 digit postal code");
+            }
+        }
+        self
+    }
+
+    pub fn email(&mut self, field: &str, value: &Option<String>) -> &mut Self {
+        if let Some(value) = value {
+            let valid = match value.split_once('@') {
+                Some((local, domain)) => {
+                    !local.is_empty()
+                        && !domain.contains('@')
+                        && domain.contains('.')
+                        && !domain.starts_with('.')
+                        && !domain.ends_with('.')
+                        && !value.contains(char::is_whitespace)
+                }
+                None => false,
+            };
+
+            if !valid {
+                self.fail(field, "Must be a valid email address");
+            }
+        }
+        self
+    }
+
+    // French phone number, `0X XX XX XX XX` or `+33 X XX XX XX XX`, separators
+    // are ignored
+    pub fn phone(&mut self, field: &str, value: &Option<String>) -> &mut Self {
+        if let Some(value) = value {
+            let digits: String = value
+                .chars()
+                .filter(|c| !matches!(c, ' ' | '.' | '-'))
+                .collect();
+
+            let national = if let Some(rest) = digits.strip_prefix("+33") {
+                format!("0{}", rest)
+            } else {
+                digits
+            };
+
+            let valid = national.len() == 10
+                && national.starts_with('0')
+                && !national.starts_with("00")
+                && national.chars().all(|c| c.is_ascii_digit());
+
+            if !valid {
+                self.fail(field, "Must be a valid phone number");
+            }
+        }
+        self
+    }
+
+    // Calendar date written `DD/MM/YYYY` or `YYYY-MM-DD`
+    pub fn date(&mut self, field: &str, value: &str) -> &mut Self {
+        if crate::dates::parse(value).is_none() {
+            self.fail(field, "Must be a date (DD/MM/YYYY)");
+        }
+        self
+    }
+
+    // End of a range, not before its start when both are given
+    pub fn ordered<T: PartialOrd>(&mut self, field: &str, start: &Option<T>, end: &Option<T>) -> &mut Self {
+        if let (Some(start), Some(end)) = (start, end) {
+            if end < start {
+                self.fail(field, "Must not be before the start of the range");
+            }
+        }
+        self
+    }
+
+    pub fn nested<T: Validate>(&mut self, field: &str, value: &Option<T>) -> &mut Self {
+        if let Some(value) = value {
+            self.scoped(field, value);
+        }
+        self
+    }
+
+    pub fn child<T: Validate>(&mut self, field: &str, value: &T) -> &mut Self {
+        self.scoped(field, value);
+        self
+    }
+
+    pub fn each<T: Validate>(&mut self, field: &str, values: &Option<Vec<T>>) -> &mut Self {
+        if let Some(values) = values {
+            for (index, value) in values.iter().enumerate() {
+                self.scoped(&format!("{}.{}", field, index), value);
+            }
+        }
+        self
+    }
+
+    fn scoped<T: Validate>(&mut self, field: &str, value: &T) {
+        let parent = self.prefix.clone();
+
+        self.prefix = if parent.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", parent, field)
+        };
+        value.rules(self);
+        self.prefix = parent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Address {
+        postal_code: Option<String>,
+    }
+
+    impl Validate for Address {
+        fn rules(&self, v: &mut Validator) {
+            v.postal_code("postalCode", &self.postal_code);
+        }
+    }
+
+    struct Client {
+        name: String,
+        address: Option<Address>,
+        addresses: Option<Vec<Address>>,
+    }
+
+    impl Validate for Client {
+        fn rules(&self, v: &mut Validator) {
+            v.not_blank("name", &self.name)
+                .nested("address", &self.address)
+                .each("addresses", &self.addresses);
+        }
+    }
+
+    fn address(postal_code: &str) -> Address {
+        Address {
+            postal_code: Some(postal_code.to_string()),
+        }
+    }
+
+    // Fields of the violations found by `rules`
+    fn fields(rules: impl FnOnce(&mut Validator)) -> Vec<String> {
+        let mut validator = Validator::default();
+        rules(&mut validator);
+
+        match validator.finish() {
+            Ok(()) => vec![],
+            Err(AppError::Validation(violations)) => violations.into_iter().map(|violation| violation.field).collect(),
+            Err(err) => panic!("unexpected error {}", err),
+        }
+    }
+
+    fn valid(rules: impl FnOnce(&mut Validator)) -> bool {
+        fields(rules).is_empty()
+    }
+
+    #[test]
+    fn required_and_not_blank() {
+        assert!(valid(|v| {
+            v.required("name", &Some(1));
+        }));
+        assert!(!valid(|v| {
+            v.required::<i32>("name", &None);
+        }));
+        assert!(valid(|v| {
+            v.not_blank("name", " a ");
+        }));
+        assert!(!valid(|v| {
+            v.not_blank("name", " \t");
+        }));
+    }
+
+    #[test]
+    fn length_counts_characters() {
+        assert!(valid(|v| {
+            v.length("name", "éèà", 3, 3);
+        }));
+        assert!(!valid(|v| {
+            v.length("name", "ab", 3, 5);
+        }));
+        assert!(!valid(|v| {
+            v.length("name", "abcdef", 3, 5);
+        }));
+    }
+
+    #[test]
+    fn ranges_include_their_bounds() {
+        assert!(valid(|v| {
+            v.range("count", 1, 1, 3).range("count", 3, 1, 3);
+        }));
+        assert!(!valid(|v| {
+            v.range("count", 0, 1, 3);
+        }));
+        assert!(valid(|v| {
+            v.between("rate", 0.0, 0.0, 1.0).between("rate", 1.0, 0.0, 1.0);
+        }));
+        assert!(!valid(|v| {
+            v.between("rate", f64::NAN, 0.0, 1.0);
+        }));
+        assert!(!valid(|v| {
+            v.between("rate", 1.5, 0.0, 1.0);
+        }));
+    }
+
+    #[test]
+    fn one_of() {
+        assert!(valid(|v| {
+            v.one_of("kind", "b", &["a", "b"]);
+        }));
+        assert!(!valid(|v| {
+            v.one_of("kind", "c", &["a", "b"]);
+        }));
+    }
+
+    #[test]
+    fn postal_codes() {
+        for value in ["75011", "01000"] {
+            assert!(valid(|v| {
+                v.postal_code("postalCode", &Some(value.to_string()));
+            }), "{}", value);
+        }
+        for value in ["7501", "750111", "75O11", "75 11"] {
+            assert!(!valid(|v| {
+                v.postal_code("postalCode", &Some(value.to_string()));
+            }), "{}", value);
+        }
+        assert!(valid(|v| {
+            v.postal_code("postalCode", &None);
+        }));
+    }
+
+    #[test]
+    fn emails() {
+        for value in ["contact@example.fr", "a.b+c@mail.example.com"] {
+            assert!(valid(|v| {
+                v.email("email", &Some(value.to_string()));
+            }), "{}", value);
+        }
+        for value in ["example.fr", "@example.fr", "a@b@example.fr", "a@example", "a@.example.fr", "a@example.fr.", "a b@example.fr"] {
+            assert!(!valid(|v| {
+                v.email("email", &Some(value.to_string()));
+            }), "{}", value);
+        }
+    }
+
+    #[test]
+    fn phone_numbers() {
+        for value in ["0142434445", "01 42 43 44 45", "01.42.43.44.45", "06-12-34-56-78", "+33 1 42 43 44 45"] {
+            assert!(valid(|v| {
+                v.phone("phone", &Some(value.to_string()));
+            }), "{}", value);
+        }
+        for value in ["142434445", "01424344455", "0042434445", "+33 01 42 43 44 45", "01 42 43 44 4a"] {
+            assert!(!valid(|v| {
+                v.phone("phone", &Some(value.to_string()));
+            }), "{}", value);
+        }
+    }
+
+    #[test]
+    fn dates_and_ranges() {
+        assert!(valid(|v| {
+            v.date("day", "29/02/2024").date("day", "2024-02-29");
+        }));
+        assert!(!valid(|v| {
+            v.date("day", "29/02/2023");
+        }));
+        assert!(!valid(|v| {
+            v.date("day", "tomorrow");
+        }));
+        assert!(valid(|v| {
+            v.ordered("end", &Some(1), &Some(1)).ordered("end", &None, &Some(0));
+        }));
+        assert!(!valid(|v| {
+            v.ordered("end", &Some(2), &Some(1));
+        }));
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        assert_eq!(
+            fields(|v| {
+                v.not_blank("name", "").length("name", "", 1, 10).range("count", 0, 1, 2);
+            }),
+            vec!["name", "name", "count"]
+        );
+    }
+
+    #[test]
+    fn nested_inputs_are_prefixed_with_their_path() {
+        let client = Client {
+            name: String::new(),
+            address: Some(address("7501")),
+            addresses: Some(vec![address("75011"), address("abcde")]),
+        };
+
+        assert_eq!(
+            fields(|v| {
+                v.child("input", &client);
+            }),
+            vec!["input.name", "input.address.postalCode", "input.addresses.1.postalCode"]
+        );
+
+        // The prefix is restored after a nested input
+        assert_eq!(
+            fields(|v| {
+                v.nested("address", &Some(address("1"))).not_blank("name", "");
+            }),
+            vec!["address.postalCode", "name"]
+        );
+    }
+
+    #[test]
+    fn arguments_are_named_after_themselves() {
+        assert!(validate_argument::<Address>("address", &None).is_ok());
+
+        match validate_argument("address", &Some(address("1"))) {
+            Err(AppError::Validation(violations)) => assert_eq!(violations[0].field, "address.postalCode"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
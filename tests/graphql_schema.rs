@@ -0,0 +1,17 @@
+use std::process::Command;
+
+// frontend/schema.graphql is what the frontend is written against, it is
+// written again whenever the API changes
+#[test]
+fn frontend_schema_matches_the_api() {
+    let status = Command::new(env!("CARGO_BIN_EXE_general_service_amiantes"))
+        .arg("check-graphql-schema")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("cannot run the server binary");
+
+    assert!(
+        status.success(),
+        "frontend/schema.graphql differs from the API, run `cargo run -- graphql-schema frontend/schema.graphql`"
+    );
+}